@@ -24,6 +24,8 @@ class FootprintController < ApplicationController
     end
 
     parameters[:time] = DateTime.strptime(parameters[:time], '%s')
+    parameters[:as_of] = DateTime.strptime(params[:as_of], '%s') if params[:as_of].present?
+    parameters[:ensemble_members] = params[:ensemble_members] == 'true'
     [
         :lat, :lon, :altitude,
         :burst_altitude_mean, :burst_altitude_std_dev,
@@ -1,6 +1,43 @@
 class PredictionController < ApplicationController
 
   def predict
+    parameters = prediction_parameters
+    return if parameters.nil?
+
+    # a JSON array of the flight's fixes so far, to correct the winds with
+    parameters[:telemetry] = params[:telemetry] if params[:telemetry].present?
+
+    render json: Predictor.predict(**parameters)
+
+  rescue RuntimeError => e
+    NewRelic::Agent.notice_error e
+    render json: {
+        success: false,
+        error: e.to_s
+    }, status: 500
+  end
+
+  # runs the prediction once with each forecast run on disk, to estimate how uncertain the forecast is
+  def ensemble
+    parameters = prediction_parameters
+    return if parameters.nil?
+
+    parameters[:max_runs] = params[:max_runs].to_i if params[:max_runs].present?
+
+    render json: Predictor.ensemble(**parameters)
+
+  rescue RuntimeError => e
+    NewRelic::Agent.notice_error e
+    render json: {
+        success: false,
+        error: e.to_s
+    }, status: 500
+  end
+
+  private
+
+  # renders an error and returns nil if any parameters are missing
+  def prediction_parameters
     required_params = [:lat, :lon, :altitude, :time, :profile]
 
     case params[:profile]
@@ -9,10 +46,11 @@ class PredictionController < ApplicationController
       when 'valbal'
         required_params.concat [:duration]
       else
-        return render json: {
+        render json: {
             success: false,
             error: "Invalid profile '#{params[:profile]}'"
         }, status: 400
+        return nil
     end
 
     parameters = {}
@@ -23,25 +61,24 @@ class PredictionController < ApplicationController
     end
 
     if missing.any?
-      return render json: {
+      render json: {
           success: false,
           error: "Missing required parameters: #{missing.join(', ')}"
       }, status: 400
+      return nil
     end
 
     parameters[:time] = DateTime.strptime(parameters[:time], '%s')
+    parameters[:as_of] = DateTime.strptime(params[:as_of], '%s') if params[:as_of].present?
+    parameters[:integrator] = params[:integrator] if params[:integrator].present?
+    parameters[:step] = params[:step].to_f if params[:step].present?
+    parameters[:absolute_tolerance] = params[:absolute_tolerance].to_f if params[:absolute_tolerance].present?
+    parameters[:relative_tolerance] = params[:relative_tolerance].to_f if params[:relative_tolerance].present?
     [:lat, :lon, :altitude, :ascent_rate, :descent_rate, :burst_altitude, :duration].each do |key|
       parameters[key] = parameters[key].to_f if parameters[:key].present?
     end
 
-    render json: Predictor.predict(**parameters)
-
-  rescue RuntimeError => e
-    NewRelic::Agent.notice_error e
-    render json: {
-        success: false,
-        error: e.to_s
-    }, status: 500
+    parameters
   end
 
 end
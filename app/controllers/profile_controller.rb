@@ -0,0 +1,38 @@
+class ProfileController < ApplicationController
+
+  def profile
+    required_params = [:lat, :lon, :time]
+
+    parameters = {}
+    missing = []
+    required_params.each do |key|
+      parameters[key] = params[key]
+      missing << key if params[key].blank?
+    end
+
+    if missing.any?
+      return render json: {
+          success: false,
+          error: "Missing required parameters: #{missing.join(', ')}"
+      }, status: 400
+    end
+
+    parameters[:time] = DateTime.strptime(parameters[:time], '%s')
+    parameters[:as_of] = DateTime.strptime(params[:as_of], '%s') if params[:as_of].present?
+    parameters[:altitudes] = params[:altitudes].split(',') if params[:altitudes].present?
+    parameters[:pressure_levels] = params[:pressure_levels] != 'false'
+    [:lat, :lon].each do |key|
+      parameters[key] = parameters[key].to_f
+    end
+
+    render json: Predictor.profile(**parameters)
+
+  rescue RuntimeError => e
+    NewRelic::Agent.notice_error e
+    render json: {
+        success: false,
+        error: e.to_s
+    }, status: 500
+  end
+
+end
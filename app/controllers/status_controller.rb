@@ -9,7 +9,9 @@ class StatusController < ApplicationController
   end
 
   def datasets
-    @downloaded = Predictor.datasets
+    @reports = Predictor.datasets
+    @downloaded = @reports.select { |report| report['complete'] }.map { |report| report['name'] }
+    @incomplete = @reports.reject { |report| report['complete'] }
     @processed = ProcessedDatasets.last_dataset
 
     respond_to do |format|
@@ -17,6 +19,7 @@ class StatusController < ApplicationController
       format.json {
         render json: {
             downloaded: @downloaded,
+            incomplete: @incomplete,
             processed: @processed
         }
       }
@@ -0,0 +1,44 @@
+class WindGridController < ApplicationController
+
+  def wind_grid
+    required_params = [:north, :south, :west, :east, :resolution, :altitude, :time]
+
+    parameters = {}
+    missing = []
+    required_params.each do |key|
+      parameters[key] = params[key]
+      missing << key if params[key].blank?
+    end
+
+    if missing.any?
+      return render json: {
+          success: false,
+          error: "Missing required parameters: #{missing.join(', ')}"
+      }, status: 400
+    end
+
+    parameters[:time] = DateTime.strptime(parameters[:time], '%s')
+    parameters[:as_of] = DateTime.strptime(params[:as_of], '%s') if params[:as_of].present?
+    [:north, :south, :west, :east, :resolution, :altitude].each do |key|
+      parameters[key] = parameters[key].to_f
+    end
+
+    # the binary raster is much smaller than the JSON, for animating over large areas
+    if params[:raster] == 'true'
+      Tempfile.create(['wind_grid', '.bin']) do |file|
+        Predictor.wind_grid(**parameters, raster_path: file.path)
+        send_data File.binread(file.path), type: 'application/octet-stream', filename: 'wind_grid.bin'
+      end
+    else
+      render json: Predictor.wind_grid(**parameters)
+    end
+
+  rescue RuntimeError => e
+    NewRelic::Agent.notice_error e
+    render json: {
+        success: false,
+        error: e.to_s
+    }, status: 500
+  end
+
+end
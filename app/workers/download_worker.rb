@@ -1,3 +1,4 @@
+require Rails.root.join('lib', 'storage', 'dataset_names.rb')
 require 'open3'
 require 'fileutils'
 
@@ -12,9 +13,9 @@ class DownloadWorker
 
     puts "Downloading #{@dataset_url}"
 
-    @output_path = Rails.root.join('data', @dataset_url.split('/').pop)
+    @output_path = Rails.root.join('data', DatasetNames.filename(@dataset_url))
 
-    @partial_name = "#{@output_path.to_s.split('.').first}.grb2.partial"
+    @partial_name = "#{@output_path}.partial"
 
     puts "Downloading #{remote_bytes} bytes"
 
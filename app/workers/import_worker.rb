@@ -1,3 +1,4 @@
+require Rails.root.join('lib', 'storage', 'dataset_names.rb')
 require 'zip'
 require 'fileutils'
 
@@ -5,7 +6,7 @@ require 'fileutils'
 class ImportWorker
 
   def perform(dataset_url, overwrite: true)
-    @folder_name = dataset_url.split('/').last.split('.').first
+    @folder_name = DatasetNames.folder_name(dataset_url)
     @filename = "#{@folder_name}.zip"
     @zip_path = Rails.root.join('data', @filename)
 
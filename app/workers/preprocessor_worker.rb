@@ -1,3 +1,4 @@
+require Rails.root.join('lib', 'storage', 'dataset_names.rb')
 require 'fileutils'
 
 class PreprocessorWorker
@@ -9,21 +10,35 @@ class PreprocessorWorker
 
   def perform(dataset_url)
 
-    filename = dataset_url.split('/').pop
+    filename = DatasetNames.filename(dataset_url)
     path = Rails.root.join('data', filename)
 
     puts "Preprocessing #{filename}"
 
+    # decode natively when the rust predictor is available, rather than shelling out to grib_get_data
+    if defined?(Predictor) && Predictor.respond_to?(:preprocess)
+      result = Predictor.preprocess(path)
+
+      puts "-> Converted #{result['levels'].length} levels into #{result['tiles']} tiles (#{result['seconds'].round(2)}s)"
+      puts
+
+      Predictor.reload_datasets if Predictor.respond_to?(:reload_datasets)
+      return
+    end
+
     true_start = Time.now
 
-    base_dir = path.to_s.split('.').first
+    # written to a partial directory and moved into place once complete, so the predictor never reads half a dataset
+    base_dir = Rails.root.join('data', DatasetNames.folder_name(dataset_url)).to_s
+    partial_dir = "#{base_dir}.partial"
 
-    FileUtils::mkdir_p base_dir
+    FileUtils::rm_rf partial_dir
+    FileUtils::mkdir_p partial_dir
 
     LEVELS.each.with_index do |level, i|
       level_start = Time.now
 
-      read_to_format(dir: base_dir, path: path, level: level)
+      read_to_format(dir: partial_dir, path: path, level: level)
 
       seconds = Time.now - level_start
       total_seconds = Time.now - true_start
@@ -35,6 +50,11 @@ class PreprocessorWorker
       puts "\t Level #{level.to_s.rjust(4)} written (#{percentage_string} complete; #{seconds.round}s; ~#{extrapolation.round}s remaining)"
     end
 
+    FileUtils::rm_rf base_dir
+    FileUtils::mv partial_dir, base_dir
+
+    Predictor.reload_datasets if defined?(Predictor) && Predictor.respond_to?(:reload_datasets)
+
     seconds = Time.now - true_start
     puts "-> Converted #{LEVELS.length} levels (#{seconds.round(2)}s, #{(seconds / LEVELS.length.to_f).round(2)}s avg)"
     puts
@@ -1,3 +1,4 @@
+require Rails.root.join('lib', 'storage', 'dataset_names.rb')
 require Rails.root.join('lib', 'storage', 'processed_datasets.rb')
 
 class StartPreprocessorWorker
@@ -147,7 +148,7 @@ class StartPreprocessorWorker
   end
 
   def has_processed?(url)
-    name = url.split('/').last.split('.').first
+    name = DatasetNames.folder_name(url)
     @processed_datasets.include? name
   end
 
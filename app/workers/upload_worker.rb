@@ -1,8 +1,9 @@
+require Rails.root.join('lib', 'storage', 'dataset_names.rb')
 
 class UploadWorker
 
   def perform(dataset_url)
-    folder_name = dataset_url.split('/').last.split('.').first
+    folder_name = DatasetNames.folder_name(dataset_url)
     puts "Uploading #{folder_name}.zip"
 
     unless ENV['AZURE_STORAGE_ACCOUNT'].present? && ENV['AZURE_STORAGE_ACCESS_KEY'].present?
@@ -1,9 +1,10 @@
+require Rails.root.join('lib', 'storage', 'dataset_names.rb')
 require 'zip'
 
 class ZipWorker
 
   def perform(dataset_url)
-    folder_name = dataset_url.split('/').last.split('.').first
+    folder_name = DatasetNames.folder_name(dataset_url)
     puts "Zipping #{folder_name}"
 
     output_file = Rails.root.join('data', "#{folder_name}.zip")
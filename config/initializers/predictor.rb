@@ -21,7 +21,11 @@ if defined? Predictor
         RustPredictor.test arg
       end
 
-      def predict(lat:, lon:, altitude:, time:, profile:, burst_altitude: nil, ascent_rate: nil, descent_rate: nil, duration: nil)
+      # integrator is euler (the default), rk4, abm or dopri5, and step is the seconds between positions, 60 by default
+      # dopri5 adapts its own steps to keep each one's error within absolute_tolerance meters plus relative_tolerance of its length
+      # sounding is the path to an IGRA or CSV sounding, used near the launch site, or on its own with sounding_only
+      # telemetry is the flight's GPS fixes so far, as hashes with :latitude, :longitude, :altitude and :time, or as a JSON string of them
+      def predict(lat:, lon:, altitude:, time:, profile:, burst_altitude: nil, ascent_rate: nil, descent_rate: nil, duration: nil, as_of: nil, sounding: nil, sounding_only: false, telemetry: nil, integrator: nil, step: nil, absolute_tolerance: nil, relative_tolerance: nil)
 
         unless %w(standard valbal).include? profile
           raise ArgumentError, "Invalid profile '#{profile}'"
@@ -45,11 +49,45 @@ if defined? Predictor
             burst_altitude.to_f,
             ascent_rate.to_f,
             descent_rate.to_f,
-            duration.to_f.minutes.to_i
+            duration.to_f.minutes.to_i,
+            as_of_string(as_of),
+            sounding.to_s,
+            sounding_only ? true : false,
+            telemetry_string(telemetry),
+            integrator.to_s,
+            step.to_f,
+            absolute_tolerance.to_f,
+            relative_tolerance.to_f
         ))
       end
 
-      def footprint(lat:, lon:, altitude:, time:, burst_altitude_mean:, burst_altitude_std_dev:, ascent_rate_mean:, ascent_rate_std_dev:, descent_rate_mean:, descent_rate_std_dev:, trials:)
+      # predicts with each forecast run on disk, newest first, and reports how far apart the landing sites are
+      def ensemble(lat:, lon:, altitude:, time:, profile:, burst_altitude: nil, ascent_rate: nil, descent_rate: nil, duration: nil, max_runs: 0, as_of: nil, integrator: nil, step: nil, absolute_tolerance: nil, relative_tolerance: nil)
+
+        unless %w(standard valbal).include? profile
+          raise ArgumentError, "Invalid profile '#{profile}'"
+        end
+
+        parse_response(RustPredictor.ensemble(
+            lat.to_f,
+            lon.to_f,
+            altitude.to_f,
+            time.to_i.to_s,
+            profile.to_s,
+            burst_altitude.to_f,
+            ascent_rate.to_f,
+            descent_rate.to_f,
+            duration.to_f.minutes.to_i,
+            max_runs.to_i,
+            as_of_string(as_of),
+            integrator.to_s,
+            step.to_f,
+            absolute_tolerance.to_f,
+            relative_tolerance.to_f
+        ))
+      end
+
+      def footprint(lat:, lon:, altitude:, time:, burst_altitude_mean:, burst_altitude_std_dev:, ascent_rate_mean:, ascent_rate_std_dev:, descent_rate_mean:, descent_rate_std_dev:, trials:, ensemble_members: false, as_of: nil)
         parse_response(RustPredictor.footprint(
             lat.to_f,
             lon.to_f,
@@ -61,11 +99,40 @@ if defined? Predictor
             ascent_rate_std_dev.to_f,
             descent_rate_mean.to_f,
             descent_rate_std_dev.to_f,
-            trials.to_i
+            trials.to_i,
+            ensemble_members ? true : false,
+            as_of_string(as_of)
         ))
       end
 
-      def navigation(lat:, lon:, altitude:, time:, timeout:, duration:, time_increment:180, altitude_variance:5, altitude_increment:500, compare_with_naive: false, navigation_type:'distance', destination_lat:nil, destination_lon:nil, destination_altitude:nil)
+      # the atmosphere above a location, at each isobaric level and at any altitudes asked for, in meters
+      def profile(lat:, lon:, time:, altitudes: [], pressure_levels: true, as_of: nil)
+        parse_response(RustPredictor.profile(
+            lat.to_f,
+            lon.to_f,
+            time.to_i.to_s,
+            Array(altitudes).map(&:to_f).join(','),
+            pressure_levels ? true : false,
+            as_of_string(as_of)
+        ))
+      end
+
+      # the winds over a box at one altitude and time, or written as a binary raster to raster_path when given, with only the rest of the grid returned
+      def wind_grid(north:, south:, west:, east:, resolution:, altitude:, time:, as_of: nil, raster_path: nil)
+        parse_response(RustPredictor.wind_grid(
+            north.to_f,
+            south.to_f,
+            west.to_f,
+            east.to_f,
+            resolution.to_f,
+            altitude.to_f,
+            time.to_i.to_s,
+            as_of_string(as_of),
+            raster_path.to_s
+        ))
+      end
+
+      def navigation(lat:, lon:, altitude:, time:, timeout:, duration:, time_increment:180, altitude_variance:5, altitude_increment:500, compare_with_naive: false, navigation_type:'distance', destination_lat:nil, destination_lon:nil, destination_altitude:nil, as_of: nil)
 
         unless %w(distance destination).include? navigation_type
           raise ArgumentError, "Invalid navigation type '#{navigation_type}'"
@@ -88,7 +155,8 @@ if defined? Predictor
             compare_with_naive,
             navigation_type.to_s,
             destination_lat.to_f,
-            destination_lon.to_f
+            destination_lon.to_f,
+            as_of_string(as_of)
         ))
       end
 
@@ -96,8 +164,43 @@ if defined? Predictor
         parse_response RustPredictor.datasets
       end
 
+      def set_coverage_policy(policy:, max_gap_hours: 6, fallback_directory: nil)
+        unless %w(error clamp fallback).include? policy.to_s
+          raise ArgumentError, "Invalid coverage policy '#{policy}'"
+        end
+
+        parse_response RustPredictor.set_coverage_policy(policy.to_s, max_gap_hours.to_f, fallback_directory.to_s)
+      end
+
+      def reload_datasets
+        parse_response RustPredictor.reload_datasets
+      end
+
+      def preprocess(path)
+        parse_response RustPredictor.preprocess(path.to_s)
+      end
+
       private
 
+      # only forecasts from runs that had started by as_of are used, for hindcasts of past flights
+      def as_of_string(as_of)
+        as_of.present? ? as_of.to_i.to_s : ''
+      end
+
+      def telemetry_string(telemetry)
+        return '' if telemetry.blank?
+        return telemetry if telemetry.is_a? String
+
+        telemetry.map do |fix|
+          {
+              latitude: fix[:latitude].to_f,
+              longitude: fix[:longitude].to_f,
+              altitude: fix[:altitude].to_f,
+              time: fix[:time].to_i
+          }
+        end.to_json
+      end
+
       def parse_response(response_str)
 
         if response_str.start_with? 'Error'
@@ -2,7 +2,10 @@ Rails.application.routes.draw do
   # For details on the DSL available within this file, see http://guides.rubyonrails.org/routing.html
 
   get 'predict' => 'prediction#predict'
+  get 'ensemble' => 'prediction#ensemble'
   get 'footprint' => 'footprint#footprint'
+  get 'profile' => 'profile#profile'
+  get 'wind_grid' => 'wind_grid#wind_grid'
   get 'navigation' => 'navigation#navigation'
 
   get 'status/datasets'
@@ -12,6 +12,7 @@ extern crate serde;
 extern crate serde_json;
 
 extern crate lru_cache;
+extern crate memmap;
 extern crate rand;
 extern crate libc;
 
@@ -22,6 +23,7 @@ pub mod macros;
 
 pub mod predictor;
 pub mod navigation;
+pub mod preprocessor;
 
 macro_rules! check_error {
     ($result:expr) => {
@@ -34,15 +36,94 @@ macro_rules! check_error {
     }
 }
 
+/*
+ * Parses an optional UNIX timestamp, where an empty string means there is none
+ */
+fn parse_as_of(as_of: &String) -> Result<Option<DateTime<Utc>>, String> {
+    if as_of.is_empty() {
+        return Ok(None);
+    }
+
+    match Utc.datetime_from_str(as_of.as_str(), "%s") {
+        Ok(as_of) => Ok(Some(as_of)),
+        Err(_) => Err(format!("Invalid as of time {}", as_of))
+    }
+}
+
+/*
+ * Parses a UNIX timestamp
+ */
+fn parse_time(time: &str) -> Result<DateTime<Utc>, String> {
+    Utc.datetime_from_str(time, "%s").map_err(|_| format!("Invalid time {}", time))
+}
+
+/*
+ * Parses an integrator's name, where an empty string means the default one
+ * Tolerances only apply to adaptive integrators, and are left as their defaults when 0
+ */
+fn parse_integrator(name: &str, absolute_tolerance: f64, relative_tolerance: f64) -> Result<predictor::integrator::Integrator, String> {
+    if name.is_empty() {
+        return Ok(predictor::integrator::Integrator::default());
+    }
+
+    match predictor::integrator::Integrator::from_name(name) {
+        Ok(predictor::integrator::Integrator::DormandPrince(tolerance)) => {
+            Ok(predictor::integrator::Integrator::DormandPrince(predictor::integrator::Tolerance {
+                absolute: if absolute_tolerance > 0.0 { absolute_tolerance as f32 } else { tolerance.absolute },
+                relative: if relative_tolerance > 0.0 { relative_tolerance as f32 } else { tolerance.relative }
+            }))
+        },
+        result => result
+    }
+}
+
+/*
+ * A step size in seconds, where 0 means the default one
+ */
+fn step_duration(step: f64) -> chrono::Duration {
+    if step > 0.0 {
+        chrono::Duration::milliseconds((step * 1000.0) as i64)
+    } else {
+        chrono::Duration::seconds(predictor::integrator::DEFAULT_STEP_SECONDS)
+    }
+}
+
+/*
+ * Parses comma separated altitudes, where an empty string means there are none
+ */
+fn parse_altitudes(altitudes: &str) -> Result<Vec<f32>, String> {
+    altitudes.split(',').map(|altitude| altitude.trim()).filter(|altitude| !altitude.is_empty()).map(|altitude| {
+        altitude.parse::<f32>().map_err(|_| format!("Invalid altitude {}", altitude))
+    }).collect()
+}
+
+/*
+ * Predicts with a source, corrected by the flight's telemetry so far when there is any
+ */
+fn predict_with_track<S: predictor::wind_source::WindSource + ?Sized>(params: predictor::predictor::PredictorParams, source: &S, track: Vec<predictor::point::Point>) -> Result<predictor::predictor::Prediction, String> {
+    if track.is_empty() {
+        predictor::predictor::predict(params, source)
+    } else {
+        predictor::predictor::predict(params, &predictor::telemetry::CorrectedSource::new(source, track))
+    }
+}
+
 ruby! {
     class Predictor {
         def test(path: String){
             println!("{}", path)
         }
 
-        def predict(latitude: f64, longitude: f64, altitude: f64, time: String, profile: String, burst_altitude: f64, ascent_rate: f64, descent_rate: f64, duration: f64) -> String {
+        def predict(latitude: f64, longitude: f64, altitude: f64, time: String, profile: String, burst_altitude: f64, ascent_rate: f64, descent_rate: f64, duration: f64, as_of: String, sounding: String, sounding_only: bool, telemetry: String, integrator: String, step: f64, absolute_tolerance: f64, relative_tolerance: f64) -> String {
+
+            let integrator = match parse_integrator(&integrator, absolute_tolerance, relative_tolerance) {
+                Ok(integrator) => integrator,
+                Err(why) => {
+                    return "Error: ".to_string() + why.as_str();
+                }
+            };
 
-            let result = predictor::predictor::predict(predictor::predictor::PredictorParams {
+            let params = predictor::predictor::PredictorParams {
                 launch: predictor::point::Point {
                     latitude: latitude as f32,
                     longitude: longitude as f32,
@@ -65,15 +146,89 @@ ruby! {
                 ascent_rate: ascent_rate as f32,
                 descent_rate: descent_rate as f32,
 
-                duration: chrono::Duration::seconds(duration as i64)
+                duration: chrono::Duration::seconds(duration as i64),
+
+                integrator,
+                step: step_duration(step)
+            };
+
+            // a sounding from before launch is used near the launch site, or everywhere it reaches with sounding_only
+            // telemetry from the flight so far corrects whichever winds are used
+            let result = predictor::telemetry::parse_track(&telemetry).and_then(|track| {
+                if sounding.is_empty() {
+                    parse_as_of(&as_of).and_then(predictor::dataset_reader::default_source).and_then(|source| {
+                        predict_with_track(params, &source, track)
+                    })
+                } else if sounding_only {
+                    predictor::sounding::Sounding::open(&sounding).and_then(|sounding| {
+                        predict_with_track(params, &sounding, track)
+                    })
+                } else {
+                    predictor::sounding::Sounding::open(&sounding).and_then(|sounding| {
+                        parse_as_of(&as_of).and_then(predictor::dataset_reader::default_source).and_then(|source| {
+                            let launch = params.launch.clone();
+
+                            predict_with_track(params, &predictor::sounding::BlendedSource::new(sounding, &source, &launch), track)
+                        })
+                    })
+                }
+            });
+
+            check_error!(result)
+        }
+
+        def ensemble(latitude: f64, longitude: f64, altitude: f64, time: String, profile: String, burst_altitude: f64, ascent_rate: f64, descent_rate: f64, duration: f64, max_runs: i64, as_of: String, integrator: String, step: f64, absolute_tolerance: f64, relative_tolerance: f64) -> String {
+
+            let integrator = match parse_integrator(&integrator, absolute_tolerance, relative_tolerance) {
+                Ok(integrator) => integrator,
+                Err(why) => {
+                    return "Error: ".to_string() + why.as_str();
+                }
+            };
+
+            let params = predictor::ensemble::EnsembleParams {
+                prediction: predictor::predictor::PredictorParams {
+                    launch: predictor::point::Point {
+                        latitude: latitude as f32,
+                        longitude: longitude as f32,
+                        altitude: altitude as f32,
+                        time: {
+                            Utc.datetime_from_str(time.as_str(), "%s").unwrap()
+                        }
+                    },
+
+                    profile: {
+                        match profile.as_ref() {
+                            "standard" => predictor::predictor::PredictionProfile::Standard,
+                            "float" => predictor::predictor::PredictionProfile::Float,
+                            "valbal" => predictor::predictor::PredictionProfile::ValBal,
+                            _ => predictor::predictor::PredictionProfile::Standard
+                        }
+                    },
+
+                    burst_altitude: burst_altitude as f32,
+                    ascent_rate: ascent_rate as f32,
+                    descent_rate: descent_rate as f32,
+
+                    duration: chrono::Duration::seconds(duration as i64),
+
+                    integrator,
+                    step: step_duration(step)
+                },
+
+                max_runs: max_runs.max(0) as usize
+            };
+
+            let result = parse_as_of(&as_of).and_then(predictor::dataset_reader::default_reader_as_of).and_then(|reader| {
+                predictor::ensemble::calculate_ensemble(params, &reader)
             });
 
             check_error!(result)
         }
 
-        def footprint(latitude: f64, longitude: f64, altitude: f64, time: String, burst_altitude_mean: f64, burst_altitude_std_dev: f64, ascent_rate_mean: f64, ascent_rate_std_dev: f64, descent_rate_mean: f64, descent_rate_std_dev: f64, trials: i64) -> String {
+        def footprint(latitude: f64, longitude: f64, altitude: f64, time: String, burst_altitude_mean: f64, burst_altitude_std_dev: f64, ascent_rate_mean: f64, ascent_rate_std_dev: f64, descent_rate_mean: f64, descent_rate_std_dev: f64, trials: i64, ensemble_members: bool, as_of: String) -> String {
 
-            let result = predictor::footprint::calculate_footprint(predictor::footprint::FootprintParams {
+            let params = predictor::footprint::FootprintParams {
                 launch: predictor::point::Point {
                     latitude: latitude as f32,
                     longitude: longitude as f32,
@@ -93,12 +248,23 @@ ruby! {
                 descent_rate_std_dev: descent_rate_std_dev as f32,
 
                 trials: trials as u32
-            });
+            };
+
+            // ensemble members are only in the global datasets, so sampling them skips the regional model
+            let result = if ensemble_members {
+                parse_as_of(&as_of).and_then(predictor::dataset_reader::default_reader_as_of).and_then(|reader| {
+                    predictor::footprint::calculate_ensemble_footprint(params, &reader)
+                })
+            } else {
+                parse_as_of(&as_of).and_then(predictor::dataset_reader::default_source).and_then(|source| {
+                    predictor::footprint::calculate_footprint(params, &source)
+                })
+            };
 
             check_error!(result)
         }
 
-        def navigation(latitude: f64, longitude: f64, altitude: f64, time: String, timeout: f64, duration: f64, time_increment: f64, altitude_variance: f64, altitude_increment: f64, compare_with_naive: bool, navigation_type_string: String, destination_latitude: f64, destination_longitude: f64) -> String {
+        def navigation(latitude: f64, longitude: f64, altitude: f64, time: String, timeout: f64, duration: f64, time_increment: f64, altitude_variance: f64, altitude_increment: f64, compare_with_naive: bool, navigation_type_string: String, destination_latitude: f64, destination_longitude: f64, as_of: String) -> String {
 
             let navigation_type = match navigation_type_string.as_ref() {
                 "distance" => navigation::navigation::NavigationType::Distance,
@@ -116,7 +282,7 @@ ruby! {
             };
 
 
-            let result = navigation::navigation::navigation(navigation::navigation::NavigationParams {
+            let params = navigation::navigation::NavigationParams {
                 launch: predictor::point::Point {
                     latitude: latitude as f32,
                     longitude: longitude as f32,
@@ -137,20 +303,92 @@ ruby! {
                 compare_with_naive: compare_with_naive,
 
                 navigation_type
+            };
+
+            let result = parse_as_of(&as_of).and_then(predictor::dataset_reader::default_source).and_then(|source| {
+                navigation::navigation::navigation(params, &source)
+            });
+
+            check_error!(result)
+        }
+
+        def profile(latitude: f64, longitude: f64, time: String, altitudes: String, pressure_levels: bool, as_of: String) -> String {
+            let result = parse_time(&time).and_then(|time| {
+                parse_altitudes(&altitudes).and_then(|altitudes| {
+                    parse_as_of(&as_of).and_then(predictor::dataset_reader::default_source).and_then(|source| {
+                        predictor::profile::wind_profile(&source, latitude as f32, longitude as f32, time, &altitudes, pressure_levels)
+                    })
+                })
+            });
+
+            check_error!(result)
+        }
+
+        def wind_grid(north: f64, south: f64, west: f64, east: f64, resolution: f64, altitude: f64, time: String, as_of: String, raster_path: String) -> String {
+            // the raster is written to a file, as it is not valid UTF-8, and then only the header is returned
+            let result = parse_time(&time).and_then(|time| {
+                let params = predictor::wind_grid::WindGridParams {
+                    north: north as f32,
+                    south: south as f32,
+                    west: west as f32,
+                    east: east as f32,
+                    resolution: resolution as f32,
+
+                    altitude: altitude as f32,
+                    time
+                };
+
+                parse_as_of(&as_of).and_then(predictor::dataset_reader::default_source).and_then(|source| {
+                    predictor::wind_grid::wind_grid(&params, &source)
+                })
+            }).and_then(|grid| {
+                if raster_path.is_empty() {
+                    Ok(grid.serialize())
+                } else {
+                    grid.write_raster(&raster_path).map(|_| grid.into_header().serialize())
+                }
+            });
+
+            match result {
+                Ok(json) => json,
+                Err(why) => {
+                    "Error: ".to_string() + why.as_str()
+                }
+            }
+        }
+
+        def set_coverage_policy(policy: String, max_gap_hours: f64, fallback_directory: String) -> String {
+            let result = predictor::coverage::CoveragePolicy::from_name(policy.as_str(), fallback_directory).and_then(|policy| {
+                predictor::dataset_reader::set_coverage_config(predictor::coverage::CoverageConfig {
+                    policy,
+                    max_gap: chrono::Duration::seconds((max_gap_hours * 3600.0) as i64)
+                })
             });
 
             check_error!(result)
         }
 
+        def reload_datasets() -> String {
+            let result = predictor::dataset_reader::reload_datasets();
+
+            check_error!(result)
+        }
+
+        def preprocess(path: String) -> String {
+            let result = preprocessor::preprocessor::preprocess(path);
+
+            check_error!(result)
+        }
+
         def datasets() -> String {
-            match predictor::dataset_reader::get_datasets() {
+            match predictor::dataset_reader::default_reader().and_then(|reader| reader.get_datasets()) {
                 Ok(datasets) => {
                     let mut result = "[".to_string();
                     for i in 0..datasets.len() {
                         if i != 0 {
                             result += ", ";
                         }
-                        result += ("\"".to_string() + datasets[i].to_string().as_str() + "\"").as_str();
+                        result += datasets[i].serialize().as_str();
                     }
 
                     result += "]";
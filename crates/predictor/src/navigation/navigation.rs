@@ -6,6 +6,8 @@ use serde_json;
 
 use predictor::point::*;
 use predictor::predictor::*;
+use predictor::integrator::*;
+use predictor::wind_source::WindSource;
 use navigation::navigation_node::*;
 use navigation::generational_pqueue::*;
 
@@ -54,13 +56,13 @@ impl Navigation {
     }
 }
 
-pub fn navigation(params : NavigationParams) -> Result<Navigation, String> {
+pub fn navigation<S: WindSource + ?Sized>(params : NavigationParams, source : &S) -> Result<Navigation, String> {
 
     let score = score_for(&params);
 
     let mut result = {
 
-        result_or_return!(search(&params, score))
+        result_or_return!(search(&params, score, source))
     };
 
     let naive = match (&params).compare_with_naive {
@@ -89,8 +91,11 @@ pub fn navigation(params : NavigationParams) -> Result<Navigation, String> {
                     };
 
                     last.time.signed_duration_since(first.time)
-                }
-            });
+                },
+
+                integrator: Integrator::default(),
+                step: Duration::seconds(DEFAULT_STEP_SECONDS)
+            }, source);
 
             let naive_positions = match result_or_return!(prediction) {
                 Prediction::ValBal(prediction) => {
@@ -141,7 +146,7 @@ fn score_for(params : &NavigationParams) -> Box<Fn(&Node) -> f32> {
 /*
  * Does greedy search, starting from the start point and going for timeout seconds
  */
-fn search(params : &NavigationParams, score: Box<Fn(&Node) -> f32>) -> Result<Navigation, String> {
+fn search<S: WindSource + ?Sized>(params : &NavigationParams, score: Box<Fn(&Node) -> f32>, source : &S) -> Result<Navigation, String> {
 
     let mut free_at_end : Vec<*mut Node> = Vec::new();
     let end_time = Local::now() + Duration::seconds(params.timeout as i64);
@@ -200,7 +205,7 @@ fn search(params : &NavigationParams, score: Box<Fn(&Node) -> f32>) -> Result<Na
         }
 
         // enqueue children
-        let mut children = result_or_return!(node.neighbors(node_ptr, &params));
+        let mut children = result_or_return!(node.neighbors(node_ptr, &params, source));
 
         while !children.is_empty() {
             // TODO: make a preliminary filter on children's cost
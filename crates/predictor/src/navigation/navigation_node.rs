@@ -2,10 +2,13 @@ use std::cmp;
 use std::mem;
 use std::f32;
 use libc;
+use chrono::Duration;
 use std::collections::VecDeque;
 
 use predictor::point::*;
 use predictor::predictor::*;
+use predictor::integrator::*;
+use predictor::wind_source::WindSource;
 use navigation::navigation::*;
 
 /*
@@ -71,7 +74,7 @@ impl Node {
     /*
      * Gets the neighbors of this node by making a prediction
      */
-    pub fn neighbors(&self, address : *mut Self, params : &NavigationParams) -> Result<Vec<*mut Self>, String> {
+    pub fn neighbors<S: WindSource + ?Sized>(&self, address : *mut Self, params : &NavigationParams, source : &S) -> Result<Vec<*mut Self>, String> {
         // return blank if you're at the end of the time period
         if (self.generation as i64)*params.time_increment.num_seconds() > params.duration.num_seconds() {
             return Ok(vec![]);
@@ -85,8 +88,11 @@ impl Node {
             ascent_rate: 0.0,
             descent_rate: 0.0,
 
-            duration: params.time_increment
-        });
+            duration: params.time_increment,
+
+            integrator: Integrator::default(),
+            step: Duration::seconds(DEFAULT_STEP_SECONDS)
+        }, source);
 
         let point = match prediction {
             Ok(unwrapped) => {
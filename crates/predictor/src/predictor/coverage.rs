@@ -0,0 +1,129 @@
+use chrono::Duration;
+use serde_json;
+
+const DEFAULT_MAX_GAP_HOURS : i64 = 6;
+
+/*
+ * What to do when a point is further than the maximum gap from every dataset
+ */
+#[derive(Clone)]
+pub enum CoveragePolicy {
+    Error,
+    Clamp, // use the closest dataset anyway, but flag the result
+    Fallback(String) // directory containing a second set of datasets
+}
+
+#[derive(Clone)]
+pub struct CoverageConfig {
+    pub policy: CoveragePolicy,
+    pub max_gap: Duration
+}
+
+/*
+ * How well a lookup was covered by the datasets
+ * Ordered from best to worst
+ */
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Coverage {
+    Covered,
+    Fallback,
+    Clamped
+}
+
+/*
+ * Included in prediction output so callers can tell how trustworthy the winds were
+ */
+#[derive(Serialize)]
+pub struct CoverageReport {
+    pub policy: &'static str,
+    pub max_gap_hours: i64,
+    pub coverage: Coverage
+}
+
+#[derive(Serialize)]
+struct CoverageSettings {
+    policy: &'static str,
+    max_gap_hours: i64,
+    fallback_directory: Option<String>
+}
+
+impl CoveragePolicy {
+
+    pub fn from_name(name: &str, fallback_directory: String) -> Result<CoveragePolicy, String> {
+        match name {
+            "error" => Ok(CoveragePolicy::Error),
+            "clamp" => Ok(CoveragePolicy::Clamp),
+            "fallback" => {
+                if fallback_directory.is_empty() {
+                    return_error!("Fallback policy requires a fallback directory");
+                }
+
+                Ok(CoveragePolicy::Fallback(fallback_directory))
+            },
+            _ => Err(format!("Invalid coverage policy '{}'", name))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CoveragePolicy::Error => "error",
+            CoveragePolicy::Clamp => "clamp",
+            CoveragePolicy::Fallback(_) => "fallback"
+        }
+    }
+}
+
+impl Default for CoverageConfig {
+    fn default() -> Self {
+        CoverageConfig {
+            policy: CoveragePolicy::Clamp,
+            max_gap: Duration::hours(DEFAULT_MAX_GAP_HOURS)
+        }
+    }
+}
+
+impl CoverageConfig {
+    pub fn serialize(&self) -> String {
+        let fallback_directory = match self.policy {
+            CoveragePolicy::Fallback(ref directory) => Some(directory.clone()),
+            _ => None
+        };
+
+        serde_json::to_string(&CoverageSettings {
+            policy: self.policy.name(),
+            max_gap_hours: self.max_gap.num_hours(),
+            fallback_directory
+        }).unwrap()
+    }
+
+    pub fn report(&self, coverage: Coverage) -> CoverageReport {
+        CoverageReport {
+            policy: self.policy.name(),
+            max_gap_hours: self.max_gap.num_hours(),
+            coverage
+        }
+    }
+}
+
+impl Coverage {
+
+    /*
+     * The worse of two coverages, for summarizing many lookups
+     */
+    pub fn worst(self, other: Coverage) -> Coverage {
+        if other.severity() > self.severity() {
+            other
+        } else {
+            self
+        }
+    }
+
+    fn severity(&self) -> u8 {
+        match *self {
+            Coverage::Covered => 0,
+            Coverage::Fallback => 1,
+            Coverage::Clamped => 2
+        }
+    }
+}
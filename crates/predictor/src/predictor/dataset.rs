@@ -1,127 +1,480 @@
-use std::io::prelude::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
 use std::fs::File;
-use std::mem;
+use std::io::Read;
+use std::time::SystemTime;
 
 use chrono::prelude::*;
-use chrono::Duration;
+use lru_cache::LruCache;
+use serde_json;
 
 use predictor::point::*;
-use predictor::dataset_reader::*;
+use predictor::tile::*;
+use predictor::coverage::*;
+use predictor::wind_source::*;
+use predictor::dataset_name::*;
+
+pub const CELL_SIZE : f32 = 25.0; // Make sure this matches the grid size in grib_convert.rb and the preprocessor
+pub const DATASET_MANIFEST : &'static str = "manifest.json"; // written by the preprocessor once every tile is
+
+const CACHE_SIZE : usize = 10_000_000; // in bytes, per thread
+const BYTES_PER_CACHE_ELEMENT : usize = 44; // 7 floats, plus a 16 byte key
+const HEIGHT_CACHE_SIZE : usize = 1_000_000; // in bytes, per thread
+const BYTES_PER_HEIGHT_CACHE_ELEMENT : usize = 20; // 1 float, plus a 16 byte key
+
+pub type Cache = LruCache<CacheKey, Atmospheroid>;
+
+/*
+ * Atmospheroids and geopotential heights are cached per thread, so lookups never wait on each other
+ * Dataset ids are unique across every reader, so all datasets can share them
+ */
+thread_local! {
+    static CACHE : RefCell<Cache> = RefCell::new(LruCache::new(CACHE_SIZE / BYTES_PER_CACHE_ELEMENT));
+    static HEIGHT_CACHE : RefCell<LruCache<CacheKey, f32>> = RefCell::new(LruCache::new(HEIGHT_CACHE_SIZE / BYTES_PER_HEIGHT_CACHE_ELEMENT));
+}
+
+/*
+ * Whether every tile a dataset should have is present and full
+ */
+#[derive(Serialize, Clone)]
+pub struct DatasetReport {
+    pub name: String,
+    pub model: String,
+    pub convention: NamingConvention,
+    pub member: Option<u32>,
+    pub created_at: String,
+    pub time: String,
+    pub complete: bool,
+    pub missing_levels: Vec<i32>,
+    pub missing_tiles: usize,
+    pub short_tiles: usize,
+    pub mismatched_tiles: usize, // tiles whose header does not match the grid the dataset is read at
+    pub format_version: u16,
+    pub resolution: f32,
+    pub heights: bool, // whether levels are aligned by geopotential height, rather than the standard atmosphere
+    pub extended: bool // whether specific humidity and vertical velocity are available, rather than being 0
+}
 
-const CELL_SIZE : f32 = 25.0; // Make sure this matches the grid size in grib_convert.rb
+impl DatasetReport {
+    pub fn serialize(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/*
+ * What the preprocessor wrote, so that a dataset can be validated without opening every tile
+ * Datasets converted before manifests existed do not have one, and are validated by reading each tile's header instead
+ */
+#[derive(Serialize, Deserialize)]
+pub struct DatasetManifest {
+    pub format_version: u16,
+    pub resolution: f32,
+    pub cell_size: f32,
+    pub records: BTreeMap<String, usize> // in each tile, by its path within the dataset
+}
 
+impl DatasetManifest {
+    pub fn new(resolution: f32) -> DatasetManifest {
+        DatasetManifest {
+            format_version: TILE_VERSION,
+            resolution,
+            cell_size: CELL_SIZE,
+            records: BTreeMap::new()
+        }
+    }
+
+    /*
+     * The path of a tile within its dataset
+     */
+    pub fn tile_path(kind: TileKind, level: i32, grid_lat: i32, grid_lon: i32) -> String {
+        "L".to_string() + level.to_string().as_str() +
+            "/" + kind.prefix() + grid_lat.to_string().as_str() + "_" + grid_lon.to_string().as_str() +
+            ".gribp"
+    }
+
+    pub fn read(directory: &String) -> Result<DatasetManifest, String> {
+        let mut file = result_or_return_why!(File::open(directory.clone() + "/" + DATASET_MANIFEST), "Could not open manifest");
+        let mut contents = String::new();
+        result_or_return_why!(file.read_to_string(&mut contents), "Could not read manifest");
+
+        Ok(result_or_return_why!(serde_json::from_str(contents.as_str()), "Could not parse manifest"))
+    }
+
+    pub fn serialize(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    fn layout(&self, kind: TileKind, level: i32, grid_lat: i32, grid_lon: i32) -> Option<TileLayout> {
+        self.records.get(&DatasetManifest::tile_path(kind, level, grid_lat, grid_lon)).map(|&records| TileLayout {
+            version: self.format_version,
+            resolution: self.resolution,
+            cell_size: self.cell_size,
+            records
+        })
+    }
+}
+
+/*
+ * How a single tile is laid out, from either its header or the manifest
+ */
+struct TileLayout {
+    version: u16,
+    resolution: f32,
+    cell_size: f32,
+    records: usize
+}
 
 pub struct Dataset {
     pub created_at: DateTime<Utc>,
     pub time: DateTime<Utc>,
+    pub member: Option<u32>, // ensemble member, or None for a deterministic model
 
     id: u32,
     path: String,
-    pub name: String
-}
-
-struct GribLine {
-    latitude : f32,
-    longitude : f32,
-    u : f32,
-    v : f32,
-    temperature: f32
-}
-
-enum GribReadError {
-    EOF,
-    Corrupted(usize),
-    IO(String)
+    modified: Option<SystemTime>,
+    pub name: String,
+    pub report: DatasetReport
 }
 
 impl Dataset {
 
     pub fn new(path: String, id: u32) -> Result<Dataset, String> {
 
-        let (name, created_at, time) = {
+        // datasets are directories of tiles, so anything else here is a download or a dataset still being written
+        if path.ends_with(".partial") || !fs::metadata(&path).map(|metadata| metadata.is_dir()).unwrap_or(false) {
+            return_error!("Is not a complete dataset");
+        }
 
-            let name : &String = &some_or_return_why!(path.split("/").collect::<Vec<&str>>().last(), "Could not get name").to_string();
+        let mut dataset = result_or_return!(Dataset::named(path, id));
 
-            let parts: Vec<&str> = name.split("_").collect();
+        dataset.report = match DatasetManifest::read(&dataset.path) {
+            Ok(manifest) => dataset.validate(manifest.resolution, |kind, level, grid_lat, grid_lon| manifest.layout(kind, level, grid_lat, grid_lon)),
+            Err(_) => dataset.inspect()
+        };
 
-            if parts.len() != 5 {
-                return_error!(format!("Expected 5 parts in name, got {}", parts.len()));
-            }
+        Ok(dataset)
+    }
+
+    /*
+     * A dataset that has not been validated, and so is not complete until its report says so
+     */
+    fn named(path: String, id: u32) -> Result<Dataset, String> {
+        let identity = result_or_return!(DatasetName::parse(path.as_str()));
+        let (name, created_at, time, member) = (identity.name.clone(), identity.created_at, identity.time, identity.member);
+
+        let modified = Dataset::modified_at(&path);
+
+        Ok(Dataset {
+            report: DatasetReport {
+                name: name.clone(),
+                model: identity.model.clone(),
+                convention: identity.convention,
+                member,
+                created_at: created_at.to_rfc3339(),
+                time: time.to_rfc3339(),
+                complete: false,
+                missing_levels: vec![],
+                missing_tiles: 0,
+                short_tiles: 0,
+                mismatched_tiles: 0,
+                format_version: 0,
+                resolution: DATA_RESOLUTION,
+                heights: false,
+                extended: false
+            },
+            name, path, created_at, time, member, id, modified
+        })
+    }
+
+    /*
+     * A complete dataset that is never read from disk, for testing readers without any tiles
+     */
+    #[cfg(test)]
+    pub fn in_memory(path: &str, id: u32) -> Dataset {
+        let mut dataset = Dataset::named(path.to_string(), id).unwrap();
+        dataset.report.complete = true;
+
+        dataset
+    }
 
-            if parts[4].contains(".") {
-                return_error!("Is not a complete dataset");
+    pub fn is_complete(&self) -> bool {
+        self.report.complete
+    }
+
+    /*
+     * Validates the dataset by reading the header of every tile, rather than trusting the manifest
+     * This opens thousands of files, so it is only done for the datasets report and for datasets without a manifest
+     */
+    pub fn inspect(&self) -> DatasetReport {
+        self.validate(self.detect_resolution(), |kind, level, grid_lat, grid_lon| self.tile_layout(kind, level, grid_lat, grid_lon).ok())
+    }
+
+    /*
+     * Checks that every level has every tile, each with a record for every grid point
+     * Incomplete datasets are kept out of lookups, rather than failing partway through a prediction
+     * Height and extended tiles are optional, as datasets preprocessed before they existed do not have them
+     */
+    fn validate<F>(&self, resolution: f32, layout: F) -> DatasetReport where F: Fn(TileKind, i32, i32, i32) -> Option<TileLayout> {
+        let expected_tiles = expected_tiles(resolution);
+        let full_tile = |layout: Option<TileLayout>, expected: usize| {
+            layout.map(|layout| matches_grid(&layout, resolution) && layout.records >= expected).unwrap_or(false)
+        };
+
+        let mut missing_levels = vec![];
+        let mut missing_tiles = 0;
+        let mut short_tiles = 0;
+        let mut mismatched_tiles = 0;
+        let mut format_version = 0;
+        let mut heights = true;
+        let mut extended = true;
+
+        for level in LEVELS.iter() {
+            let mut found = 0;
+
+            for (&(grid_lat, grid_lon), &expected) in expected_tiles.iter() {
+                match layout(TileKind::Wind, *level, grid_lat, grid_lon) {
+                    Some(tile) => {
+                        found += 1;
+                        format_version = format_version.max(tile.version);
+
+                        if !matches_grid(&tile, resolution) {
+                            mismatched_tiles += 1;
+                        } else if tile.records < expected {
+                            short_tiles += 1;
+                        }
+                    },
+                    None => {
+                        missing_tiles += 1;
+                    }
+                }
+
+                if heights {
+                    heights = full_tile(layout(TileKind::Height, *level, grid_lat, grid_lon), expected);
+                }
+
+                if extended {
+                    extended = full_tile(layout(TileKind::Extended, *level, grid_lat, grid_lon), expected);
+                }
             }
 
-            if parts[0] != "gfs" {
-                return_error!(format!("Expected first part to be gfs, got {}", parts[0]));
+            if found == 0 {
+                missing_levels.push(*level);
             }
+        }
+
+        DatasetReport {
+            complete: missing_levels.is_empty() && missing_tiles == 0 && short_tiles == 0 && mismatched_tiles == 0,
+            missing_levels,
+            missing_tiles,
+            short_tiles,
+            mismatched_tiles,
+            format_version,
+            resolution,
+            heights,
+            extended,
+            ..self.report.clone()
+        }
+    }
+
+    /*
+     * Reads a tile's header and record count
+     */
+    fn tile_layout(&self, kind: TileKind, level: i32, grid_lat: i32, grid_lon: i32) -> Result<TileLayout, String> {
+        let filename = result_or_return!(self.tile_filename(kind, level, grid_lat, grid_lon));
+
+        let (header, records) = result_or_return!(TileHeader::read_file(&filename, kind));
+
+        Ok(TileLayout {
+            version: header.version,
+            resolution: header.resolution,
+            cell_size: header.cell_size,
+            records
+        })
+    }
 
-            if parts[1] != "4" {
-                return_error!(format!("Expected second part to be 4, got {}", parts[1]));
+    /*
+     * The resolution of the first wind tile that can be read
+     * Every other tile has to match it, which validation checks
+     */
+    fn detect_resolution(&self) -> f32 {
+        for level in LEVELS.iter() {
+            match self.tile_layout(TileKind::Wind, *level, 0, 0) {
+                Ok(layout) => {
+                    return layout.resolution;
+                },
+                Err(_) => {}
             }
+        }
+
+        DATA_RESOLUTION
+    }
+
+    pub fn resolution(&self) -> f32 {
+        self.report.resolution
+    }
+
+    fn tile_filename(&self, kind: TileKind, level: i32, grid_lat: i32, grid_lon: i32) -> Result<String, String> {
+        Ok(self.path.clone() + "/" + DatasetManifest::tile_path(kind, level, grid_lat, grid_lon).as_str())
+    }
+
+    pub fn path(&self) -> &String {
+        &self.path
+    }
+
+    /*
+     * Whether the dataset on disk is still the one that was read
+     * Datasets are replaced wholesale by the preprocessor, so the directory's modification time is enough
+     */
+    pub fn is_unchanged(&self) -> bool {
+        self.modified.is_some() && Dataset::modified_at(&self.path) == self.modified
+    }
+
+    fn modified_at(path: &String) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    /*
+     * Aligns a point to the grid, bracketing it between the levels whose geopotential height is either side of it
+     * On hot or cold days this can be hundreds of meters off from the standard atmosphere, which is used when there are no heights
+     */
+    fn align(&self, point: &Point) -> Result<Alignment, String> {
+        let alignment = point.align(self.resolution());
+
+        if !self.report.heights {
+            return Ok(alignment);
+        }
 
-            if parts[2].len() != 8 {
-                return_error!(format!("Expected 8 characters in third part, got {}", parts[2].len()));
+        // start at the closest level in the standard atmosphere, then walk up or down until the altitude is bracketed
+        let pressure = point.standard_pressure();
+
+        let mut index = 0;
+        for i in 0..LEVELS.len() {
+            if (LEVELS[i] as f32 - pressure).abs() < (LEVELS[index] as f32 - pressure).abs() {
+                index = i;
             }
+        }
+
+        let last = LEVELS.len() - 1;
 
-            let year = match parts[2][0..4].parse::<i32>() {
-                Ok(val) => val,
-                Err(_) => {
-                    return_error!("Invalid year");
+        // levels are in increasing pressure, so lower indices are higher up
+        loop {
+            let height = result_or_return!(self.height_at(&alignment, LEVELS[index]));
+
+            if point.altitude >= height {
+                if index == 0 {
+                    return Ok(alignment.with_levels(LEVELS[0], LEVELS[0], 0.0));
                 }
-            };
 
-            let month = match parts[2][4..6].parse::<u32>() {
-                Ok(val) => val,
-                Err(_) => {
-                    return_error!("Invalid month");
+                let height_above = result_or_return!(self.height_at(&alignment, LEVELS[index - 1]));
+
+                if point.altitude <= height_above {
+                    return Ok(alignment.with_levels(LEVELS[index], LEVELS[index - 1], percent_between(height, height_above, point.altitude)));
                 }
-            };
 
-            let day = match parts[2][6..8].parse::<u32>() {
-                Ok(val) => val,
-                Err(_) => {
-                    return_error!("Invalid day");
+                index -= 1;
+            } else {
+                if index == last {
+                    return Ok(alignment.with_levels(LEVELS[last], LEVELS[last], 0.0));
                 }
-            };
 
-            let hour = match parts[3] {
-                "0000" => 0,
-                "0600" => 6,
-                "1200" => 12,
-                "1800" => 18,
-                _ => {
-                    return_error!(format!("Invalid hour offset in fourth part: {}", parts[3]));
+                let height_below = result_or_return!(self.height_at(&alignment, LEVELS[index + 1]));
+
+                if point.altitude >= height_below {
+                    return Ok(alignment.with_levels(LEVELS[index + 1], LEVELS[index], percent_between(height_below, height, point.altitude)));
                 }
-            };
 
-            let created_at = Utc.ymd(year, month, day).and_hms(hour, 0, 0);
+                index += 1;
+            }
+        }
+    }
+
+    /*
+     * Geopotential height of a level, interpolated between the corners of an alignment
+     */
+    fn height_at(&self, alignment: &Alignment, level: i32) -> Result<f32, String> {
+        let ne = result_or_return!(self.height_at_aligned(alignment.ne_down.latitude, alignment.ne_down.longitude, level));
+        let nw = result_or_return!(self.height_at_aligned(alignment.nw_down.latitude, alignment.nw_down.longitude, level));
+        let se = result_or_return!(self.height_at_aligned(alignment.se_down.latitude, alignment.se_down.longitude, level));
+        let sw = result_or_return!(self.height_at_aligned(alignment.sw_down.latitude, alignment.sw_down.longitude, level));
+
+        Ok(
+            (ne * alignment.percent_east + nw * alignment.percent_west) * alignment.percent_north +
+                (se * alignment.percent_east + sw * alignment.percent_west) * alignment.percent_south
+        )
+    }
 
+    fn height_at_aligned(&self, latitude: f32, longitude: f32, level: i32) -> Result<f32, String> {
+        let key = AlignedPoint::cache_key(level, latitude, longitude, self.id, self.resolution());
 
-            let hour_offset = match parts[4].parse::<u32>() {
-                Ok(val) => val,
-                Err(_) => {
-                    return_error!("Invalid hour offset in fifth part");
-                }
-            };
+        let cached = HEIGHT_CACHE.with(|cache| cache.borrow_mut().get_mut(&key).map(|height| *height));
+
+        match cached {
+            Some(height) => {
+                return Ok(height);
+            },
+            None => {}
+        }
 
-            let time = created_at + Duration::hours(hour_offset as i64);
+        let grid_lat = (latitude / CELL_SIZE).floor() * CELL_SIZE;
+        let grid_lon = (longitude / CELL_SIZE).floor() * CELL_SIZE;
 
-            (name.clone(), created_at, time)
+        let tile_key = TileKey {
+            dataset_id: self.id,
+            kind: TileKind::Height,
+            level,
+            grid_lat: grid_lat as i32,
+            grid_lon: grid_lon as i32
         };
 
-        Ok(Dataset {
-            name, path, created_at, time, id
-        })
+        let tile = result_or_return!(open_tile(tile_key, || {
+            let filename = result_or_return!(self.tile_filename(TileKind::Height, level, grid_lat as i32, grid_lon as i32));
+
+            Tile::open(&filename, TileKind::Height, grid_lat, grid_lon)
+        }));
+
+        let height = some_or_return_why!(tile.height(latitude, longitude), "Could not find geopotential height");
+
+        HEIGHT_CACHE.with(|cache| {
+            cache.borrow_mut().insert(key, height);
+        });
+
+        Ok(height)
     }
 
     /*
      * Returns the interpolated atmospheroid at a given point
      */
-    pub fn atmospheroid_at(&self, point: &Point, cache: &mut Cache) -> Result<Atmospheroid, String> {
+    fn interpolated_atmospheroid_at(&self, point: &Point, cache: &mut Cache) -> Result<Atmospheroid, String> {
+
+        let aligned = result_or_return!(self.align(point));
+
+        self.interpolate(&aligned, cache)
+    }
+
+    /*
+     * The atmosphere on one level, interpolated between the corners of the cell around a point
+     * The height is the level's geopotential height, or the standard atmosphere's for datasets without heights
+     */
+    fn level_at(&self, point: &Point, level: i32, cache: &mut Cache) -> Result<IsobaricLevel, String> {
+        let aligned = point.align(self.resolution()).with_levels(level, level, 0.0);
+
+        let atmospheroid = result_or_return!(self.interpolate(&aligned, cache));
+
+        let height = if self.report.heights {
+            result_or_return!(self.height_at(&aligned, level))
+        } else {
+            Point::standard_altitude(level as f32)
+        };
+
+        Ok(IsobaricLevel {
+            atmospheroid,
+            height
+        })
+    }
 
+    fn interpolate(&self, aligned: &Alignment, cache: &mut Cache) -> Result<Atmospheroid, String> {
         // get the eight points to interpolate between
-        let aligned = point.align();
         let ne_down = result_or_return!(self.atmospheroid_at_aligned(&aligned.ne_down, cache));
         let ne_up = result_or_return!(self.atmospheroid_at_aligned(&aligned.ne_up, cache));
         let nw_down = result_or_return!(self.atmospheroid_at_aligned(&aligned.nw_down, cache));
@@ -163,11 +516,12 @@ impl Dataset {
 
     /*
      * Returns the uninterpolated atmospheroid at an aligned point
+     * Note that u is east and v is south, as per https://en.wikipedia.org/wiki/Zonal_and_meridional
      */
     fn atmospheroid_at_aligned(&self, aligned: &AlignedPoint, cache: &mut Cache) -> Result<Atmospheroid, String> {
         // check cache
         {
-            match cache.get_mut(&aligned.key(self.id)) {
+            match cache.get_mut(&aligned.key(self.id, self.resolution())) {
                 Some(atmo) => {
                     return Ok(atmo.clone())
                 },
@@ -178,184 +532,85 @@ impl Dataset {
         let grid_lat = (aligned.latitude / CELL_SIZE).floor() * CELL_SIZE;
         let grid_lon = (aligned.longitude / CELL_SIZE).floor() * CELL_SIZE;
 
-        let proper_filename = {
-            let mut parts = self.path.split('.');
-            some_or_return_why!(parts.next(), "Could not get filename").to_string() +
-                "/L" + aligned.level.to_string().as_str() +
-                "/C" + grid_lat.to_string().as_str() + "_" + grid_lon.to_string().as_str() +
-                ".gribp"
+        let tile_key = TileKey {
+            dataset_id: self.id,
+            kind: TileKind::Wind,
+            level: aligned.level,
+            grid_lat: grid_lat as i32,
+            grid_lon: grid_lon as i32
         };
 
-        self.scan_file(proper_filename, aligned, cache)
-    }
-
-    /*
-     * Looks for a point in the file, adding everything in it to the cache
-     * Note that u is east and v is south, as per https://en.wikipedia.org/wiki/Zonal_and_meridional
-     */
-    fn scan_file(&self, filename : String, aligned : &AlignedPoint, cache: &mut Cache) -> Result<Atmospheroid, String> {
-        let name = &filename;
-        let mut file = &mut result_or_return_why!(File::open(name), "Could not open file");
-
-        let mut u : f32 = 0.0;
-        let mut v : f32 = 0.0;
-        let mut temperature : f32 = 0.0;
-
-        let mut data_found = false;
-
-        loop {
-            match Dataset::read_line(&mut file) {
-                Ok(line) => {
-                    if aligned.latitude == line.latitude && aligned.longitude == line.longitude {
-                        u = line.u;
-                        v = line.v;
-                        temperature = line.temperature;
-                        data_found = true;
-                    }
-
-                    cache.insert(
-                        AlignedPoint::cache_key(aligned.level, line.latitude, line.longitude, self.id),
-                        Atmospheroid {
-                            velocity: Velocity {
-                                east: line.u,
-                                north: -line.v,
-                                vertical: 0.0
-                            },
-                            temperature: line.temperature
-                        }
-                    );
-                }
-                Err(why) => {
-                    match why {
-                        GribReadError::EOF => {
-                            break; // you're done!
-                        },
-                        GribReadError::Corrupted(_) => {
-                            return Err(String::from("Invalid number of bytes in file"));
-                        },
-                        GribReadError::IO(why) => {
-                            return Err(why);
-                        }
-                    }
-                },
-            }
-        }
-
-        if !data_found {
-            println!("Looking for ({}, {}, {}) in {}",
-                     aligned.latitude, aligned.longitude, aligned.level,
-                     filename
-            );
-            return Err(String::from("Datapoint not found"));
-        }
-
-        Ok(Atmospheroid {
-            velocity: Velocity {
-                east: v,
-                north: -u,
-                vertical: 0.0
-            },
-            temperature
-        })
-    }
+        let tile = result_or_return!(open_tile(tile_key, || {
+            let proper_filename = result_or_return!(self.tile_filename(TileKind::Wind, aligned.level, grid_lat as i32, grid_lon as i32));
 
-    /*
-     * Reads a line into a struct
-     * All values except for the key are IEEE754 formatted floats
-     * The key is just a byte
-     */
-    fn read_line(file: &mut File) -> Result<GribLine, GribReadError> {
+            Tile::open(&proper_filename, TileKind::Wind, grid_lat, grid_lon)
+        }));
 
-        // TODO: add a mem transmute thing here
+        let line = some_or_return_why!(tile.get(aligned.latitude, aligned.longitude), format!(
+            "Datapoint ({}, {}, {}) not found in tile {}_{}",
+            aligned.latitude, aligned.longitude, aligned.level, grid_lat, grid_lon
+        ));
 
-        let mut buffer = [0; 20];
+        let (specific_humidity, omega) = if self.report.extended {
+            let tile_key = TileKey {
+                kind: TileKind::Extended,
+                ..tile_key
+            };
 
-        match file.read(&mut buffer) {
-            Ok(bytes) => {
-                if bytes == 0 { //EOF
-                    return Err(GribReadError::EOF);
-                }
+            let tile = result_or_return!(open_tile(tile_key, || {
+                let filename = result_or_return!(self.tile_filename(TileKind::Extended, aligned.level, grid_lat as i32, grid_lon as i32));
 
-                if bytes != 20 {
-                    return Err(GribReadError::Corrupted(bytes));
-                }
-            },
-            Err(why) => {
-                return Err(GribReadError::IO(why.to_string()));
-            }
-        }
+                Tile::open(&filename, TileKind::Extended, grid_lat, grid_lon)
+            }));
 
-        let latitude = match bytes_to_f32(buffer[0..4].to_vec()) {
-            Ok(val) => {
-                val
-            },
-            Err(why) => {
-                return Err(GribReadError::IO(why))
-            }
-        };
+            let extended = some_or_return_why!(tile.extended(aligned.latitude, aligned.longitude), "Extended datapoint not found");
 
-        let longitude = match bytes_to_f32(buffer[4..8].to_vec()) {
-            Ok(val) => {
-                val
-            },
-            Err(why) => {
-                return Err(GribReadError::IO(why))
-            }
+            (extended.specific_humidity, extended.omega)
+        } else {
+            (0.0, 0.0)
         };
 
-        let u = match bytes_to_f32(buffer[8..12].to_vec()) {
-            Ok(val) => {
-                val
-            },
-            Err(why) => {
-                return Err(GribReadError::IO(why))
-            }
-        };
+        // on an isobaric level, the pressure is the level itself
+        let pressure = (aligned.level as f32) * 100.0;
 
-        let v = match bytes_to_f32(buffer[12..16].to_vec()) {
-            Ok(val) => {
-                val
-            },
-            Err(why) => {
-                return Err(GribReadError::IO(why))
-            }
-        };
+        let atmospheroid = Atmospheroid::from_grib(line.u, -line.v, line.temperature, pressure, specific_humidity, omega);
 
-        let temperature = match bytes_to_f32(buffer[16..20].to_vec()) {
-            Ok(val) => {
-                val
-            },
-            Err(why) => {
-                return Err(GribReadError::IO(why))
-            }
-        };
+        cache.insert(aligned.key(self.id, self.resolution()), atmospheroid.clone());
 
-        Ok(GribLine {
-            latitude,
-            longitude,
-            u,
-            v,
-            temperature
-        })
+        Ok(atmospheroid)
     }
 }
 
-fn bytes_to_u64(bytes : Vec<u8>, number_of_bytes : u64) -> u64 {
-    let mut number : u64 = 0;
-    for i in 0..number_of_bytes {
-        // TODO: these casts spook me
-        number += (bytes[i as usize] as u64) << (8 * (number_of_bytes - i - 1));
+impl WindSource for Dataset {
+    fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+        CACHE.with(|cache| {
+            self.interpolated_atmospheroid_at(point, &mut cache.borrow_mut())
+        })
     }
 
-    number
+    fn isobaric_level_at(&self, point: &Point, level: i32) -> Result<(IsobaricLevel, Coverage), String> {
+        let level = result_or_return!(CACHE.with(|cache| {
+            self.level_at(point, level, &mut cache.borrow_mut())
+        }));
+
+        Ok((level, Coverage::Covered))
+    }
 }
 
-fn bytes_to_f32(bytes : Vec<u8>) -> Result<f32, String> {
-    if bytes.len() != 4 {
-        return Err("Invalid byte length ".to_string() + bytes.len().to_string().as_str())
+/*
+ * How far a value is between two others, from 0 at the bottom to 1 at the top
+ */
+fn percent_between(bottom: f32, top: f32, value: f32) -> f32 {
+    if top == bottom {
+        return 0.0;
     }
 
-    let num = bytes_to_u64(bytes, 4) as u32;
+    ((value - bottom) / (top - bottom)).max(0.0).min(1.0)
+}
 
-    Ok(unsafe {mem::transmute(num)})
+/*
+ * Whether a tile is laid out on the grid lookups are aligned to
+ */
+fn matches_grid(layout: &TileLayout, resolution: f32) -> bool {
+    layout.resolution == resolution && layout.cell_size == CELL_SIZE
 }
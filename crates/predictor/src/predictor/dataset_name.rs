@@ -0,0 +1,331 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use chrono::LocalResult;
+
+pub const GRIB_EXTENSIONS : [&str; 4] = [".grb2", ".grib2", ".grb", ".grib"];
+
+/*
+ * The naming schemes datasets are recognized by
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum NamingConvention {
+    Archive, // gfs_4_YYYYMMDD_HHMM_FFF, from the NCEI archive
+    Nomads, // gfs.tHHz.pgrb2.0p50.fFFF, optionally with the run date as gfs.YYYYMMDD.tHHz..., and GEFS members like gep01.tHHz.pgrb2a.0p50.fFFF
+    EcmwfOpenData // YYYYMMDDHH0000-FFFh-oper-fc
+}
+
+/*
+ * What a dataset is, as given by its name
+ */
+#[derive(Clone, Debug)]
+pub struct DatasetName {
+    pub name: String,
+    pub model: String,
+    pub convention: NamingConvention,
+
+    pub created_at: DateTime<Utc>, // when the run started
+    pub time: DateTime<Utc>, // what time the dataset is a forecast for
+    pub member: Option<u32>, // ensemble member, where 0 is the control, or None for a deterministic model
+
+    pub resolution: Option<f32> // in degrees, when the name says
+}
+
+impl DatasetName {
+
+    /*
+     * Parses the last component of a path, ignoring any GRIB extension
+     * NOMADS names without a date take it from an enclosing gfs.YYYYMMDD or gefs.YYYYMMDD directory, as laid out on the NOMADS server
+     */
+    pub fn parse(path: &str) -> Result<DatasetName, String> {
+        let components : Vec<&str> = path.split('/').filter(|component| !component.is_empty()).collect();
+
+        let name = DatasetName::strip_extension(some_or_return_why!(components.last(), "Could not get name"));
+
+        // every scheme is ASCII, which lets the parts be sliced by byte
+        if !name.is_ascii() {
+            return_error!(format!("Unrecognized dataset name {}", name));
+        }
+
+        if name.starts_with("gfs_") {
+            return DatasetName::parse_archive(name);
+        }
+
+        if name.starts_with("gfs.") || name.starts_with("gec") || name.starts_with("gep") {
+            let parent_date = components.iter().rev().skip(1)
+                .filter_map(|component| {
+                    let mut parts = component.split('.');
+
+                    match (parts.next(), parts.next(), parts.next()) {
+                        (Some("gfs"), Some(date), None) | (Some("gefs"), Some(date), None) => parse_date(date).ok(),
+                        _ => None
+                    }
+                })
+                .next();
+
+            return DatasetName::parse_nomads(name, parent_date);
+        }
+
+        if name.len() > 14 && name.chars().take(14).all(|character| character.is_ascii_digit()) {
+            return DatasetName::parse_ecmwf(name);
+        }
+
+        return_error!(format!("Unrecognized dataset name {}", name));
+    }
+
+    /*
+     * The path without a GRIB extension, if it has one
+     */
+    pub fn strip_extension(path: &str) -> &str {
+        for extension in GRIB_EXTENSIONS.iter() {
+            if let Some(stripped) = path.strip_suffix(extension) {
+                return stripped;
+            }
+        }
+
+        path
+    }
+
+    /*
+     * gfs_4_YYYYMMDD_HHMM_FFF
+     * The second part is 3 for the 1° product and 4 for the 0.5° one
+     */
+    fn parse_archive(name: &str) -> Result<DatasetName, String> {
+        let parts : Vec<&str> = name.split('_').collect();
+
+        if parts.len() != 5 {
+            return_error!(format!("Expected 5 parts in name, got {}", parts.len()));
+        }
+
+        let resolution = match parts[1] {
+            "3" => Some(1.0),
+            "4" => Some(0.5),
+            _ => {
+                if parts[1].parse::<u32>().is_err() {
+                    return_error!(format!("Expected second part to be a number, got {}", parts[1]));
+                }
+
+                None
+            }
+        };
+
+        let date = result_or_return!(parse_date(parts[2]));
+
+        if parts[3].len() != 4 {
+            return_error!(format!("Invalid run time in fourth part: {}", parts[3]));
+        }
+
+        let hour = result_or_return!(parse_number(&parts[3][0..2], "run hour"));
+        let minute = result_or_return!(parse_number(&parts[3][2..4], "run minute"));
+        let created_at = some_or_return_why!(date.and_hms_opt(hour, minute, 0), format!("Invalid run time in fourth part: {}", parts[3]));
+
+        let hour_offset = result_or_return!(parse_number(parts[4], "hour offset in fifth part"));
+
+        Ok(DatasetName {
+            name: name.to_string(),
+            model: "gfs".to_string(),
+            convention: NamingConvention::Archive,
+            created_at,
+            time: created_at + Duration::hours(hour_offset as i64),
+            member: None,
+            resolution
+        })
+    }
+
+    /*
+     * gfs.tHHz.pgrb2.0p50.fFFF, or gfs.YYYYMMDD.tHHz.pgrb2.0p50.fFFF
+     * GEFS members replace gfs with gec00 for the control or gepNN for the perturbed members
+     * Resolutions are written like 0p25, 0p50 and 1p00
+     */
+    fn parse_nomads(name: &str, parent_date: Option<Date<Utc>>) -> Result<DatasetName, String> {
+        let parts : Vec<&str> = name.split('.').collect();
+
+        let (model, member) = match parts[0] {
+            "gfs" => ("gfs", None),
+            prefix if prefix.len() == 5 && (prefix.starts_with("gec") || prefix.starts_with("gep")) => {
+                ("gefs", Some(result_or_return!(parse_number(&prefix[3..], "ensemble member"))))
+            },
+            prefix => {
+                return_error!(format!("Unrecognized model {}", prefix));
+            }
+        };
+
+        let (date, rest) = match parts.get(1) {
+            Some(part) if part.len() == 8 && part.chars().all(|character| character.is_ascii_digit()) => {
+                (result_or_return!(parse_date(part)), &parts[2..])
+            },
+            _ => (some_or_return_why!(parent_date, format!("No run date for {}", name)), &parts[1..])
+        };
+
+        if rest.len() != 4 {
+            return_error!(format!("Expected 4 parts after the date in {}, got {}", name, rest.len()));
+        }
+
+        if !(rest[0].starts_with('t') && rest[0].ends_with('z') && rest[0].len() == 4) {
+            return_error!(format!("Invalid run hour {}", rest[0]));
+        }
+
+        let hour = result_or_return!(parse_number(&rest[0][1..3], "run hour"));
+        let created_at = some_or_return_why!(date.and_hms_opt(hour, 0, 0), format!("Invalid run hour {}", rest[0]));
+
+        if !rest[1].starts_with("pgrb2") {
+            return_error!(format!("Expected a pgrb2 product, got {}", rest[1]));
+        }
+
+        let resolution = {
+            let degrees : Vec<&str> = rest[2].split('p').collect();
+
+            if degrees.len() != 2 {
+                return_error!(format!("Invalid resolution {}", rest[2]));
+            }
+
+            let whole = result_or_return!(parse_number(degrees[0], "resolution"));
+            let fraction = result_or_return!(parse_number(degrees[1], "resolution"));
+
+            whole as f32 + fraction as f32 / 100.0
+        };
+
+        let forecast_hour = some_or_return_why!(rest[3].strip_prefix('f'), format!("Invalid forecast hour {}", rest[3]));
+        let hour_offset = result_or_return!(parse_number(forecast_hour, "forecast hour"));
+
+        Ok(DatasetName {
+            name: name.to_string(),
+            model: model.to_string(),
+            convention: NamingConvention::Nomads,
+            created_at,
+            time: created_at + Duration::hours(hour_offset as i64),
+            member,
+            resolution: Some(resolution)
+        })
+    }
+
+    /*
+     * YYYYMMDDHHmmss-FFFh-stream-type, as published by ECMWF's open data service
+     */
+    fn parse_ecmwf(name: &str) -> Result<DatasetName, String> {
+        let parts : Vec<&str> = name.split('-').collect();
+
+        if parts.len() < 3 {
+            return_error!(format!("Expected at least 3 parts in name, got {}", parts.len()));
+        }
+
+        let date = result_or_return!(parse_date(&parts[0][0..8]));
+        let hour = result_or_return!(parse_number(&parts[0][8..10], "run hour"));
+        let minute = result_or_return!(parse_number(&parts[0][10..12], "run minute"));
+        let created_at = some_or_return_why!(date.and_hms_opt(hour, minute, 0), format!("Invalid run time {}", parts[0]));
+
+        let forecast_step = some_or_return_why!(parts[1].strip_suffix('h'), format!("Invalid forecast step {}", parts[1]));
+        let hour_offset = result_or_return!(parse_number(forecast_step, "forecast step"));
+
+        Ok(DatasetName {
+            name: name.to_string(),
+            model: "ecmwf".to_string(),
+            convention: NamingConvention::EcmwfOpenData,
+            created_at,
+            time: created_at + Duration::hours(hour_offset as i64),
+            member: None,
+            resolution: None
+        })
+    }
+}
+
+/*
+ * YYYYMMDD
+ */
+fn parse_date(date: &str) -> Result<Date<Utc>, String> {
+    if date.len() != 8 {
+        return_error!(format!("Expected 8 characters in date, got {}", date.len()));
+    }
+
+    let year = result_or_return!(parse_number(&date[0..4], "year"));
+    let month = result_or_return!(parse_number(&date[4..6], "month"));
+    let day = result_or_return!(parse_number(&date[6..8], "day"));
+
+    match Utc.ymd_opt(year as i32, month, day) {
+        LocalResult::Single(date) => Ok(date),
+        _ => Err(format!("Invalid date {}", date))
+    }
+}
+
+fn parse_number(number: &str, what: &str) -> Result<u32, String> {
+    if number.is_empty() || !number.chars().all(|character| character.is_ascii_digit()) {
+        return_error!(format!("Invalid {}: {}", what, number));
+    }
+
+    number.parse::<u32>().map_err(|_| format!("Invalid {}: {}", what, number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.ymd(year, month, day).and_hms(hour, 0, 0)
+    }
+
+    #[test]
+    fn parses_every_convention() {
+        let cases = [
+            // path, name, model, convention, run, forecast time, member, resolution
+            ("data/gfs_4_20180101_0600_012", "gfs_4_20180101_0600_012", "gfs", NamingConvention::Archive, at(2018, 1, 1, 6), at(2018, 1, 1, 18), None, Some(0.5)),
+            ("data/gfs_3_20180101_1200_003.grb2", "gfs_3_20180101_1200_003", "gfs", NamingConvention::Archive, at(2018, 1, 1, 12), at(2018, 1, 1, 15), None, Some(1.0)),
+            ("data/gfs.20240101.t18z.pgrb2.0p50.f384.grib2", "gfs.20240101.t18z.pgrb2.0p50.f384", "gfs", NamingConvention::Nomads, at(2024, 1, 1, 18), at(2024, 1, 17, 18), None, Some(0.5)),
+            ("data/gfs.20240101.t00z.pgrb2.0p25.f006", "gfs.20240101.t00z.pgrb2.0p25.f006", "gfs", NamingConvention::Nomads, at(2024, 1, 1, 0), at(2024, 1, 1, 6), None, Some(0.25)),
+            ("gfs.20240229/12/atmos/gfs.t12z.pgrb2.0p25.f003", "gfs.t12z.pgrb2.0p25.f003", "gfs", NamingConvention::Nomads, at(2024, 2, 29, 12), at(2024, 2, 29, 15), None, Some(0.25)),
+            ("gfs.20240101/06/gfs.t06z.pgrb2.0p50.f000", "gfs.t06z.pgrb2.0p50.f000", "gfs", NamingConvention::Nomads, at(2024, 1, 1, 6), at(2024, 1, 1, 6), None, Some(0.5)),
+            ("gefs.20240101/00/atmos/pgrb2ap5/gec00.t00z.pgrb2a.0p50.f012", "gec00.t00z.pgrb2a.0p50.f012", "gefs", NamingConvention::Nomads, at(2024, 1, 1, 0), at(2024, 1, 1, 12), Some(0), Some(0.5)),
+            ("data/gep05.20240101.t06z.pgrb2a.0p50.f024", "gep05.20240101.t06z.pgrb2a.0p50.f024", "gefs", NamingConvention::Nomads, at(2024, 1, 1, 6), at(2024, 1, 2, 6), Some(5), Some(0.5)),
+            ("data/20240101000000-144h-oper-fc.grib2", "20240101000000-144h-oper-fc", "ecmwf", NamingConvention::EcmwfOpenData, at(2024, 1, 1, 0), at(2024, 1, 7, 0), None, None),
+            ("data/20240101120000-0h-oper-fc", "20240101120000-0h-oper-fc", "ecmwf", NamingConvention::EcmwfOpenData, at(2024, 1, 1, 12), at(2024, 1, 1, 12), None, None)
+        ];
+
+        for &(path, name, model, convention, created_at, time, member, resolution) in cases.iter() {
+            let parsed = DatasetName::parse(path).unwrap_or_else(|why| panic!("{}: {}", path, why));
+
+            assert_eq!(parsed.name, name, "{}", path);
+            assert_eq!(parsed.model, model, "{}", path);
+            assert_eq!(parsed.convention, convention, "{}", path);
+            assert_eq!(parsed.created_at, created_at, "{}", path);
+            assert_eq!(parsed.time, time, "{}", path);
+            assert_eq!(parsed.member, member, "{}", path);
+            assert_eq!(parsed.resolution, resolution, "{}", path);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_names() {
+        let cases = [
+            "data/gfs_4_20180101_0600",
+            "data/gfs_4_20180101_2500_012",
+            "data/gfs_4_20180101_06_012",
+            "data/gfs_4_20181301_0600_012",
+            "data/gfs_x_20180101_0600_012",
+            "data/gfs_4_202\u{e9}010_0600_012",
+            "data/gfs.20240101.t24z.pgrb2.0p50.f003",
+            "data/gfs.20240101.t6z.pgrb2.0p50.f003",
+            "data/gfs.20240101.t06z.pgrb2.0p50",
+            "data/gfs.20240101.t06z.pgrb2.0p50.003",
+            "data/gfs.20240101.t06z.pgrb2.half.f003",
+            "data/gfs.20240101.t06z.sfluxgrb.0p50.f003",
+            "data/gfs.t06z.pgrb2.0p50.f003",
+            "gfs.2024010/gfs.t06z.pgrb2.0p50.f003",
+            "data/gep5.20240101.t06z.pgrb2a.0p50.f024",
+            "data/gepxx.20240101.t06z.pgrb2a.0p50.f024",
+            "data/20240101250000-144h-oper-fc",
+            "data/20240101000000-144-oper-fc",
+            "data/20240101000000-144h",
+            "data/forecast.grib2",
+            ""
+        ];
+
+        for path in cases.iter() {
+            assert!(DatasetName::parse(path).is_err(), "{} should not parse", path);
+        }
+    }
+
+    #[test]
+    fn strips_grib_extensions() {
+        assert_eq!(DatasetName::strip_extension("data/gfs_4_20180101_0600_012.grb2"), "data/gfs_4_20180101_0600_012");
+        assert_eq!(DatasetName::strip_extension("data/20240101000000-144h-oper-fc.grib2"), "data/20240101000000-144h-oper-fc");
+        assert_eq!(DatasetName::strip_extension("data/gfs_4_20180101_0600_012"), "data/gfs_4_20180101_0600_012");
+    }
+}
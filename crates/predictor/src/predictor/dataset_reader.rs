@@ -1,49 +1,149 @@
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, RwLock, PoisonError, TryLockError};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::fs;
+use std::ops::{Add, Mul};
 use std::env;
+use std::time::{Duration, Instant};
 
-use lru_cache::LruCache;
+use chrono::prelude::*;
+use serde_json;
 
 use predictor::point::*;
 use predictor::dataset::*;
+use predictor::coverage::*;
+use predictor::wind_source::*;
+use predictor::regional::*;
+
+const RESCAN_INTERVAL_SECONDS : u64 = 60;
+
+/*
+ * Dataset ids are unique across every reader, since they key caches shared by all of them
+ */
+static NEXT_DATASET_ID : AtomicUsize = AtomicUsize::new(0);
+
+/*
+ * Reads every dataset in a directory
+ * Immutable once created, so it can be shared between threads
+ * Cloning only copies the handles to the datasets, so views of a reader are cheap
+ */
+#[derive(Clone)]
+pub struct DataSetReader {
+    dataset_directory: String,
+    coverage: CoverageConfig,
+    fallback: Option<Box<DataSetReader>>,
+    member: Option<u32>, // the ensemble member used for lookups, or None for deterministic datasets
 
-const CACHE_SIZE : usize = 50_000_000; // in bytes
-const BYTES_PER_CACHE_ELEMENT : usize = 16; // 4 floats
+    datasets: Vec<Arc<Dataset>>,
+    incomplete: Vec<Arc<Dataset>> // reported, but never used for lookups
+}
 
-struct UninitializedDataSetReader {
-    dataset_directory: String
+/*
+ * What changed when the datasets were rescanned
+ */
+#[derive(Serialize)]
+pub struct Reload {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub datasets: Vec<String>
 }
 
-pub type Cache = LruCache<u32, Atmospheroid>;
+impl Reload {
+    pub fn serialize(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
 
-struct DataSetReader {
-    cache: Cache,
+impl DataSetReader {
 
-    datasets: Vec<Box<Dataset>>
-}
+    pub fn new(dataset_directory: String) -> Result<DataSetReader, String> {
+        DataSetReader::with_coverage(dataset_directory, CoverageConfig::default())
+    }
 
-impl UninitializedDataSetReader {
+    pub fn with_coverage(dataset_directory: String, coverage: CoverageConfig) -> Result<DataSetReader, String> {
+        DataSetReader::scan(dataset_directory, coverage, None)
+    }
+
+    /*
+     * A reader of datasets that are never read from disk
+     */
+    #[cfg(test)]
+    pub fn in_memory(datasets: Vec<Dataset>, coverage: CoverageConfig) -> DataSetReader {
+        DataSetReader {
+            dataset_directory: String::new(),
+            coverage,
+            fallback: None,
+            member: None,
+            datasets: datasets.into_iter().map(Arc::new).collect(),
+            incomplete: vec![]
+        }
+    }
+
+    /*
+     * Reads the directory again, picking up new and replaced datasets and dropping removed ones
+     * Datasets that have not changed are shared with this reader, so their cached atmospheroids stay valid
+     */
+    pub fn rescan(&self) -> Result<DataSetReader, String> {
+        DataSetReader::scan(self.dataset_directory.clone(), self.coverage.clone(), Some(self))
+    }
+
+    fn scan(dataset_directory: String, coverage: CoverageConfig, previous: Option<&DataSetReader>) -> Result<DataSetReader, String> {
+        let mut incomplete : Vec<Arc<Dataset>> = vec![];
 
-    fn initialize(&mut self) -> Result<DataSetReader, String> {
         Ok(DataSetReader {
-            cache: LruCache::new(CACHE_SIZE / BYTES_PER_CACHE_ELEMENT),
+            fallback: {
+                match coverage.policy {
+                    CoveragePolicy::Fallback(ref directory) => {
+                        // the fallback is the last resort, so it errors rather than falling back again
+                        let fallback_coverage = CoverageConfig {
+                            policy: CoveragePolicy::Error,
+                            max_gap: coverage.max_gap
+                        };
+
+                        let previous_fallback = previous.and_then(|previous| previous.fallback.as_ref().map(|fallback| &**fallback));
+
+                        let fallback = result_or_return!(DataSetReader::scan(directory.clone(), fallback_coverage, previous_fallback));
+
+                        Some(Box::new(fallback))
+                    },
+                    _ => None
+                }
+            },
+            coverage,
+            member: None,
 
             datasets: {
 
-                let mut readers : Vec<Box<Dataset>> = vec![];
-
-                let folders = result_or_return_why!(fs::read_dir(self.dataset_directory.as_str()), "Could not read dir");
+                let mut readers : Vec<Arc<Dataset>> = vec![];
 
-                let mut id : u32 = 1;
+                let folders = result_or_return_why!(fs::read_dir(dataset_directory.as_str()), "Could not read dir");
 
                 for entry in folders {
                     let path = result_or_return_why!(entry, "Could not read entry").path();
 
                     let path_as_str = some_or_return_why!(path.to_str(), "Could not read path");
 
+                    // incomplete datasets are reused too, and only validated again once they change
+                    let existing = previous.and_then(|previous| {
+                        previous.datasets.iter().chain(previous.incomplete.iter()).find(|dataset| dataset.path() == path_as_str && dataset.is_unchanged())
+                    });
+
+                    match existing {
+                        Some(dataset) => {
+                            if dataset.is_complete() {
+                                readers.push(dataset.clone());
+                            } else {
+                                incomplete.push(dataset.clone());
+                            }
+
+                            continue;
+                        },
+                        None => {}
+                    }
+
+                    let id = (NEXT_DATASET_ID.fetch_add(1, Ordering::SeqCst) + 1) as u32;
+
                     let reader = match Dataset::new(path_as_str.to_string(), id) {
                         Ok(reader) => {
-                            id += 1;
                             reader
                         },
                         Err(_) => {
@@ -51,172 +151,616 @@ impl UninitializedDataSetReader {
                         }
                     };
 
-                    readers.push(Box::new(reader));
+                    if reader.is_complete() {
+                        readers.push(Arc::new(reader));
+                    } else {
+                        incomplete.push(Arc::new(reader));
+                    }
                 }
 
                 readers
-            }
+            },
+            incomplete,
+
+            dataset_directory
         })
     }
-}
 
-impl DataSetReader {
+    /*
+     * Compares the datasets in this reader with an older one
+     */
+    pub fn changes_since(&self, previous: &DataSetReader) -> Reload {
+        let current = self.dataset_names();
+        let old = previous.dataset_names();
+
+        Reload {
+            added: current.iter().filter(|name| !old.contains(name)).cloned().collect(),
+            removed: old.iter().filter(|name| !current.contains(name)).cloned().collect(),
+            datasets: current
+        }
+    }
+
+    /*
+     * A view of this reader with only the runs that had started by a given time, including in the fallback
+     * Used for hindcasts, so that a past flight is predicted with only the forecasts that were available for it
+     */
+    pub fn as_of(&self, as_of: DateTime<Utc>) -> DataSetReader {
+        DataSetReader {
+            fallback: self.fallback.as_ref().map(|fallback| Box::new(fallback.as_of(as_of))),
+            datasets: self.datasets.iter().filter(|dataset| dataset.created_at <= as_of).cloned().collect(),
+            ..self.clone()
+        }
+    }
+
+    /*
+     * When each forecast run on disk started, newest first
+     */
+    pub fn runs(&self) -> Vec<DateTime<Utc>> {
+        let mut runs : Vec<DateTime<Utc>> = self.datasets.iter().filter(|dataset| dataset.member == self.member).map(|dataset| dataset.created_at).collect();
+
+        runs.sort_by(|a, b| b.cmp(a));
+        runs.dedup();
+
+        runs
+    }
+
+    /*
+     * A view of this reader with only the datasets from a single forecast run
+     * The fallback is left as it is, since its runs are unrelated
+     */
+    pub fn run(&self, created_at: DateTime<Utc>) -> DataSetReader {
+        DataSetReader {
+            datasets: self.datasets.iter().filter(|dataset| dataset.created_at == created_at).cloned().collect(),
+            ..self.clone()
+        }
+    }
+
+    /*
+     * The ensemble members with datasets on disk, with the control as 0
+     */
+    pub fn members(&self) -> Vec<u32> {
+        let mut members : Vec<u32> = self.datasets.iter().filter_map(|dataset| dataset.member).collect();
 
-    pub fn velocity_at(&mut self, point: &Point) -> Result<Velocity, String> {
-        let atmospheroid = self.atmospheroid_at(point)?;
+        members.sort();
+        members.dedup();
 
-        Ok(atmospheroid.velocity)
+        members
     }
 
-    pub fn temperature_at(&mut self, point: &Point) -> Result<Temperature, String> {
-        let atmospheroid = self.atmospheroid_at(point)?;
+    /*
+     * A view of this reader that looks up a single ensemble member, rather than the deterministic datasets
+     * The fallback is left as it is
+     */
+    pub fn member(&self, member: u32) -> DataSetReader {
+        DataSetReader {
+            member: Some(member),
+            ..self.clone()
+        }
+    }
 
-        Ok(atmospheroid.temperature)
+    pub fn coverage(&self) -> &CoverageConfig {
+        &self.coverage
     }
 
-    pub fn atmospheroid_at(&mut self, point: &Point) -> Result<Atmospheroid, String> {
-        let readers = &self.datasets;
+    /*
+     * The datasets at or before and at or after a time, ignoring other ensemble members
+     * When several runs cover the same time, the newest one wins
+     */
+    fn bracket(&self, time: DateTime<Utc>) -> (Option<&Arc<Dataset>>, Option<&Arc<Dataset>>) {
+        let mut before : Option<&Arc<Dataset>> = None;
+        let mut after : Option<&Arc<Dataset>> = None;
+
+        for reader in self.datasets.iter().filter(|dataset| dataset.member == self.member) {
+            if reader.time <= time {
+                match before {
+                    Some(current) if (current.time, current.created_at) >= (reader.time, reader.created_at) => {},
+                    _ => {
+                        before = Some(reader);
+                    }
+                }
+            }
+
+            if reader.time >= time {
+                match after {
+                    Some(current) if current.time < reader.time || (current.time == reader.time && current.created_at >= reader.created_at) => {},
+                    _ => {
+                        after = Some(reader);
+                    }
+                }
+            }
+        }
 
-        if readers.is_empty() {
+        (before, after)
+    }
+
+    /*
+     * Returns the atmospheroid at a point, linearly interpolated in time between the datasets before and after it
+     * Outside of the time range of the datasets, the closest dataset is used if it is within the maximum gap
+     * Otherwise, including in the middle of a long gap between datasets such as a missing forecast hour, the coverage policy decides what happens
+     */
+    fn bracketed_atmospheroid_at(&self, point: &Point) -> Result<(Atmospheroid, Coverage), String> {
+        self.bracketed(point, |dataset| dataset.atmospheroid_at(point), |fallback| fallback.atmospheroid_with_coverage_at(point))
+    }
+
+    /*
+     * Looks something up in the datasets around a point's time, interpolating between them like bracketed_atmospheroid_at
+     * fallback looks it up in the fallback datasets, for the fallback coverage policy
+     */
+    fn bracketed<T, F, G>(&self, point: &Point, at: F, fallback: G) -> Result<(T, Coverage), String>
+        where T: Mul<f32, Output = T> + for<'a> Add<&'a T, Output = T>, F: Fn(&Dataset) -> Result<T, String>, G: Fn(&DataSetReader) -> Result<(T, Coverage), String> {
+        if !self.datasets.iter().any(|dataset| dataset.member == self.member) {
             return Err(String::from("No grib readers"));
         }
 
-        let mut best_index = 0;
-        let mut best_seconds = {
-            let best_reader = &readers[0];
-            best_reader.time.signed_duration_since(point.time).num_seconds().abs()
-        };
+        match self.bracket(point.time) {
+            (Some(before_reader), Some(after_reader)) => {
+                let nearer = nearer_of(point, before_reader, after_reader);
+
+                if !self.within_gap(point, nearer) {
+                    return self.out_of_coverage(point, nearer, at, fallback);
+                }
+
+                let span = after_reader.time.signed_duration_since(before_reader.time).num_seconds();
+
+                if span == 0 {
+                    let value = result_or_return!(at(before_reader));
+                    return Ok((value, Coverage::Covered));
+                }
 
-        for i in 1..readers.len() {
-            let reader = &readers[i];
+                let percent_after = (point.time.signed_duration_since(before_reader.time).num_seconds() as f32) / (span as f32);
 
-            let abs_seconds = reader.time.signed_duration_since(point.time).num_seconds().abs();
+                let before_value = result_or_return!(at(before_reader));
+                let after_value = result_or_return!(at(after_reader));
 
-            if abs_seconds < best_seconds {
-                best_index = i;
-                best_seconds = abs_seconds;
+                Ok((before_value * (1.0 - percent_after) + &(after_value * percent_after), Coverage::Covered))
+            },
+            (Some(closest), None) | (None, Some(closest)) => {
+                if self.within_gap(point, closest) {
+                    let value = result_or_return!(at(closest));
+                    return Ok((value, Coverage::Covered));
+                }
+
+                self.out_of_coverage(point, closest, at, fallback)
+            },
+            (None, None) => {
+                Err(String::from("No dataset found"))
             }
         }
+    }
+
+    fn within_gap(&self, point: &Point, closest: &Dataset) -> bool {
+        closest.time.signed_duration_since(point.time).num_seconds().abs() <= self.coverage.max_gap.num_seconds()
+    }
 
-        let reader = &readers[best_index];
+    /*
+     * Applies the coverage policy to a point too far from the closest dataset
+     */
+    fn out_of_coverage<T, F, G>(&self, point: &Point, closest: &Dataset, at: F, fallback: G) -> Result<(T, Coverage), String>
+        where F: Fn(&Dataset) -> Result<T, String>, G: Fn(&DataSetReader) -> Result<(T, Coverage), String> {
+        match self.coverage.policy {
+            CoveragePolicy::Error => {
+                Err(format!("No dataset within {} hours of {}", self.coverage.max_gap.num_hours(), point.time))
+            },
+            CoveragePolicy::Clamp => {
+                let value = result_or_return!(at(closest));
 
-        reader.atmospheroid_at(point, &mut self.cache)
+                Ok((value, Coverage::Clamped))
+            },
+            CoveragePolicy::Fallback(_) => {
+                let fallback_reader = some_or_return_why!(self.fallback.as_ref(), "No fallback datasets");
+                let (value, coverage) = result_or_return!(fallback(fallback_reader));
+
+                Ok((value, coverage.worst(Coverage::Fallback)))
+            }
+        }
     }
 
-    pub fn get_datasets(&self) -> Result<Vec<String>, String> {
+    /*
+     * The datasets the coverage policy uses for a point too far from the closest dataset
+     */
+    fn out_of_coverage_sources(&self, point: &Point, closest: &Dataset) -> Vec<String> {
+        match (&self.coverage.policy, self.fallback.as_ref()) {
+            (&CoveragePolicy::Clamp, _) => vec![closest.name.clone()],
+            (&CoveragePolicy::Fallback(_), Some(fallback)) => fallback.sources_at(point),
+            _ => vec![]
+        }
+    }
+
+    /*
+     * Reports on every dataset, including incomplete ones that are not used for lookups
+     * Every tile is checked again, rather than trusting the manifests lookups were validated with
+     */
+    pub fn get_datasets(&self) -> Result<Vec<DatasetReport>, String> {
         let mut result = vec![];
 
-        let readers = &self.datasets;
+        for dataset in self.datasets.iter().chain(self.incomplete.iter()) {
+            result.push(dataset.inspect());
+        }
 
-        for i in 0..readers.len() {
-            let reader = &readers[i];
+        Ok(result)
+    }
 
-            result.push(reader.name.clone());
+    /*
+     * Names of the datasets used for lookups
+     */
+    pub fn dataset_names(&self) -> Vec<String> {
+        self.datasets.iter().map(|dataset| dataset.name.clone()).collect()
+    }
+}
+
+impl WindSource for DataSetReader {
+    fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+        let (atmospheroid, _) = result_or_return!(self.bracketed_atmospheroid_at(point));
+
+        Ok(atmospheroid)
+    }
+
+    fn atmospheroid_with_coverage_at(&self, point: &Point) -> Result<(Atmospheroid, Coverage), String> {
+        self.bracketed_atmospheroid_at(point)
+    }
+
+    fn coverage_report(&self, coverage: Coverage) -> CoverageReport {
+        self.coverage.report(coverage)
+    }
+
+    fn isobaric_level_at(&self, point: &Point, level: i32) -> Result<(IsobaricLevel, Coverage), String> {
+        self.bracketed(point, |dataset| dataset.isobaric_level_at(point, level).map(|(level, _)| level), |fallback| fallback.isobaric_level_at(point, level))
+    }
+
+    fn sources_at(&self, point: &Point) -> Vec<String> {
+        match self.bracket(point.time) {
+            (Some(before), Some(after)) if self.within_gap(point, nearer_of(point, before, after)) => {
+                if before.name == after.name {
+                    vec![before.name.clone()]
+                } else {
+                    vec![before.name.clone(), after.name.clone()]
+                }
+            },
+            (Some(before), Some(after)) => self.out_of_coverage_sources(point, nearer_of(point, before, after)),
+            (Some(closest), None) | (None, Some(closest)) => {
+                if self.within_gap(point, closest) {
+                    return vec![closest.name.clone()];
+                }
+
+                self.out_of_coverage_sources(point, closest)
+            },
+            (None, None) => vec![]
         }
+    }
+}
 
-        Ok(result)
+/*
+ * Whichever of two bracketing datasets is closer in time to a point
+ */
+fn nearer_of<'a>(point: &Point, before: &'a Dataset, after: &'a Dataset) -> &'a Dataset {
+    if point.time.signed_duration_since(before.time) <= after.time.signed_duration_since(point.time) {
+        before
+    } else {
+        after
     }
 }
 
+/*
+ * Lazily creates the reader for the Rails data directory, for use by the Ruby bindings
+ * The directory is rescanned periodically, so datasets finished by the preprocessor show up without a restart
+ */
 struct WrappedDataSetReader {
     dataset_directory: String,
-    reader: Option<DataSetReader>
+    coverage: RwLock<CoverageConfig>,
+    reader: RwLock<Option<ScannedReader>>,
+    scanning: Mutex<()> // held while a new reader is built, so that only one thread scans at a time
+}
+
+#[derive(Clone)]
+struct ScannedReader {
+    reader: Arc<DataSetReader>,
+    regional: Arc<RegionalReader>,
+    scanned_at: Instant
 }
 
-macro_rules! get_reader_then {
-    ($sel:ident.$F:ident $( $arg:ident ),* ) => {
+impl ScannedReader {
+    fn is_fresh(&self) -> bool {
+        self.scanned_at.elapsed() < Duration::from_secs(RESCAN_INTERVAL_SECONDS)
+    }
+}
+
+impl WrappedDataSetReader {
+
+    /*
+     * Changes the coverage policy
+     * The reader is recreated on next use, so that a new fallback directory gets picked up
+     * Predictions already in progress keep using the old reader
+     */
+    pub fn set_coverage(&self, coverage: CoverageConfig) -> Result<(), String> {
+        // wait for any scan in progress, so that it cannot swap in a reader with the old policy afterwards
+        let _scanning = self.scanning.lock().unwrap_or_else(PoisonError::into_inner);
+
+        *result_or_return_why!(self.coverage.write(), "Could not establish lock on coverage") = coverage;
+        *result_or_return_why!(self.reader.write(), "Could not establish lock on reader") = None;
+
+        Ok(())
+    }
+
+    /*
+     * Returns the current reader, creating or rescanning it if needed
+     * Lookups only share the read lock just long enough to clone the Arc, and are never held up by a rescan
+     */
+    pub fn reader(&self) -> Result<Arc<DataSetReader>, String> {
+        let (reader, _) = result_or_return!(self.readers());
 
-        #[allow(unused_mut)]
-        match $sel.initialize_reader() {
-            Ok(_) => {
-                // take the reader temporarily
-                let reader = $sel.reader.take();
+        Ok(reader)
+    }
 
-                // unwrap will not panic, because we already know it has initialized properly
-                let mut unwrapped = some_or_return_why!(reader, "No reader");
+    /*
+     * Returns the global datasets nested with the regional model, which are rescanned together
+     */
+    pub fn source(&self, as_of: Option<DateTime<Utc>>) -> Result<NestedSource, String> {
+        let (reader, regional) = result_or_return!(self.readers());
 
-                let result = unwrapped.$F($($arg)*);
+        match as_of {
+            Some(as_of) => Ok(NestedSource::new(Arc::new(regional.as_of(as_of)), Arc::new(reader.as_of(as_of)))),
+            None => Ok(NestedSource::new(regional, reader))
+        }
+    }
 
-                // replace it
-                $sel.reader = Some(unwrapped);
+    fn readers(&self) -> Result<(Arc<DataSetReader>, Arc<RegionalReader>), String> {
+        let current = result_or_return!(self.current());
 
-                result
+        match current {
+            Some(ref scanned) if scanned.is_fresh() => {
+                return Ok((scanned.reader.clone(), scanned.regional.clone()));
             },
-            Err(why) => Err(why)
+            _ => {}
         }
-    };
-}
 
-impl WrappedDataSetReader {
+        // while another thread rescans, keep using the stale reader rather than waiting for it
+        match (self.scanning.try_lock(), current) {
+            (Err(TryLockError::WouldBlock), Some(scanned)) => {
+                return Ok((scanned.reader, scanned.regional));
+            },
+            _ => {}
+        }
 
-    pub fn velocity_at(&mut self, point: &Point) -> Result<Velocity, String> {
-        get_reader_then!(self.velocity_at point)
+        let (reader, regional, _) = result_or_return!(self.scan(false));
+
+        Ok((reader, regional))
     }
 
-    pub fn temperature_at(&mut self, point: &Point) -> Result<Temperature, String> {
-        get_reader_then!(self.temperature_at point)
+    fn current(&self) -> Result<Option<ScannedReader>, String> {
+        let scanned = result_or_return_why!(self.reader.read(), "Could not establish lock on reader");
+
+        Ok(scanned.clone())
     }
 
-    pub fn get_datasets(&mut self) -> Result<Vec<String>, String> {
-        get_reader_then!(self.get_datasets)
+    /*
+     * Rescans the directory immediately
+     */
+    pub fn reload(&self) -> Result<Reload, String> {
+        let (reader, _, previous) = result_or_return!(self.scan(true));
+
+        match previous {
+            Some(previous) => Ok(reader.changes_since(&previous)),
+            None => {
+                let datasets = reader.dataset_names();
+
+                Ok(Reload {
+                    added: datasets.clone(),
+                    removed: vec![],
+                    datasets
+                })
+            }
+        }
     }
 
     /*
-     * Function to create a new dataset reader if needed
+     * Builds a new reader and swaps it in, returning it along with the one it replaced
+     * The new reader is built without holding the reader lock, which is only written to swap the Arcs
+     * Predictions holding the old reader finish with it, and it is dropped once they are done
+     * If a periodic rescan fails, the old reader keeps being used until the next one
      */
-    fn initialize_reader(&mut self) -> Result<bool, String> {
+    fn scan(&self, force: bool) -> Result<(Arc<DataSetReader>, Arc<RegionalReader>, Option<Arc<DataSetReader>>), String> {
+        let _scanning = self.scanning.lock().unwrap_or_else(PoisonError::into_inner);
 
-        if !self.reader.is_none() {
-            return Ok(true);
-        }
+        // another thread may have scanned while this one waited for it
+        let current = result_or_return!(self.current());
+        let previous = current.as_ref().map(|scanned| scanned.reader.clone());
 
-        match self.create() {
-            Ok(reader) => {
-                self.reader = Some(reader);
-                Ok(true)
+        match current {
+            Some(ref scanned) if !force && scanned.is_fresh() => {
+                return Ok((scanned.reader.clone(), scanned.regional.clone(), previous));
             },
-            Err(why) => {
-                Err(why)
-            }
+            _ => {}
         }
+
+        let rescanned = match current {
+            Some(ref scanned) => scanned.reader.rescan(),
+            None => {
+                let coverage = result_or_return_why!(self.coverage.read(), "Could not establish lock on coverage").clone();
+
+                DataSetReader::with_coverage(self.dataset_directory.clone(), coverage)
+            }
+        };
+
+        let (reader, regional) = match (rescanned, current) {
+            (Ok(reader), Some(ref scanned)) => (Arc::new(reader), Arc::new(scanned.regional.rescan())),
+            (Ok(reader), None) => (Arc::new(reader), Arc::new(RegionalReader::new(self.dataset_directory.clone() + "/" + REGIONAL_DIRECTORY))),
+            (Err(_), Some(ref scanned)) if !force => (scanned.reader.clone(), scanned.regional.clone()),
+            (Err(why), _) => {
+                return Err(why);
+            }
+        };
+
+        *result_or_return_why!(self.reader.write(), "Could not establish lock on reader") = Some(ScannedReader {
+            reader: reader.clone(),
+            regional: regional.clone(),
+            scanned_at: Instant::now()
+        });
+
+        Ok((reader, regional, previous))
     }
 
     pub fn new(dataset_directory : String) -> Self {
         WrappedDataSetReader {
             dataset_directory,
-            reader: None
+            coverage: RwLock::new(CoverageConfig::default()),
+            reader: RwLock::new(None),
+            scanning: Mutex::new(())
         }
     }
-
-    fn create(&self) -> Result<DataSetReader, String> {
-        let mut uninitialized = UninitializedDataSetReader {
-            dataset_directory: self.dataset_directory.clone()
-        };
-
-        uninitialized.initialize()
-    }
 }
 
 lazy_static! {
-    static ref READER : Mutex<WrappedDataSetReader> = Mutex::new(WrappedDataSetReader::new(
+    static ref READER : WrappedDataSetReader = WrappedDataSetReader::new(
         [env::var("RAILS_ROOT").expect("RAILS_ROOT environment variable not found"), "/data".to_string()].concat()
-    ));
+    );
 }
 
-pub fn velocity_at(point: &Point) -> Result<Velocity, String> {
-    let result = result_or_return_why!(READER.lock(), "Could not establish lock on reader").velocity_at(&point);
+/*
+ * The reader for $RAILS_ROOT/data
+ */
+pub fn default_reader() -> Result<Arc<DataSetReader>, String> {
+    READER.reader()
+}
 
-    result
+/*
+ * A copy of the default reader, with only runs that had started by as_of if it is given
+ */
+pub fn default_reader_as_of(as_of: Option<DateTime<Utc>>) -> Result<DataSetReader, String> {
+    let reader = result_or_return!(READER.reader());
+
+    match as_of {
+        Some(as_of) => Ok(reader.as_of(as_of)),
+        None => Ok((*reader).clone())
+    }
 }
 
-pub fn temperature_at(point: &Point) -> Result<Temperature, String> {
-    let result = result_or_return_why!(READER.lock(), "Could not establish lock on reader").temperature_at(&point);
+/*
+ * The default reader, nested with the regional model in $RAILS_ROOT/data/regional
+ * With as_of, only runs that had started by then are used
+ */
+pub fn default_source(as_of: Option<DateTime<Utc>>) -> Result<NestedSource, String> {
+    READER.source(as_of)
+}
 
-    result
+/*
+ * Changes the coverage policy of the default reader
+ */
+pub fn set_coverage_config(coverage: CoverageConfig) -> Result<CoverageConfig, String> {
+    result_or_return!(READER.set_coverage(coverage.clone()));
+
+    Ok(coverage)
+}
+
+/*
+ * Rescans $RAILS_ROOT/data right away, rather than waiting for the next periodic rescan
+ */
+pub fn reload_datasets() -> Result<Reload, String> {
+    READER.reload()
 }
 
-pub fn get_datasets() -> Result<Vec<String>, String> {
-    let result = result_or_return_why!(READER.lock(), "Could not establish lock on reader").get_datasets();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono;
+
+    const FALLBACK_OFFSET : f32 = 1000.0;
 
-    result
+    fn start() -> DateTime<Utc> {
+        Utc.ymd(2020, 1, 1).and_hms(0, 0, 0)
+    }
+
+    /*
+     * A reader of in-memory datasets from the 2020-01-01 00Z run, at forecast hours
+     */
+    fn reader(hours: &[i64], policy: CoveragePolicy, fallback: Option<DataSetReader>) -> DataSetReader {
+        DataSetReader {
+            dataset_directory: String::new(),
+            coverage: CoverageConfig {
+                policy,
+                max_gap: chrono::Duration::hours(3)
+            },
+            fallback: fallback.map(Box::new),
+            member: None,
+            datasets: hours.iter().enumerate().map(|(id, hour)| {
+                Arc::new(Dataset::in_memory(format!("gfs_4_20200101_0000_{:03}", hour).as_str(), id as u32))
+            }).collect(),
+            incomplete: vec![]
+        }
+    }
+
+    /*
+     * Looks up a point hours after the run, where every dataset's temperature is its forecast hour,
+     * and FALLBACK_OFFSET more in the fallback
+     */
+    fn lookup(reader: &DataSetReader, hours: f32) -> Result<(f32, Coverage), String> {
+        let point = Point {
+            latitude: 0.0,
+            longitude: 0.0,
+            altitude: 0.0,
+            time: start() + chrono::Duration::minutes((hours * 60.0) as i64)
+        };
+
+        let value = |dataset: &Dataset, offset: f32| {
+            let hour = dataset.time.signed_duration_since(start()).num_hours() as f32;
+
+            Ok(Atmospheroid::from_grib(0.0, 0.0, offset + hour, 100_000.0, 0.0, 0.0))
+        };
+
+        let (atmospheroid, coverage) = result_or_return!(reader.bracketed(&point, |dataset| value(dataset, 0.0), |fallback| {
+            fallback.bracketed(&point, |dataset| value(dataset, FALLBACK_OFFSET), |_| Err(String::from("No second fallback")))
+        }));
+
+        Ok((atmospheroid.temperature, coverage))
+    }
+
+    fn assert_close(actual: Result<(f32, Coverage), String>, expected: f32, coverage: Coverage) {
+        let (value, actual_coverage) = actual.unwrap();
+
+        assert!((value - expected).abs() < 0.001, "{} is not {}", value, expected);
+        assert_eq!(actual_coverage, coverage);
+    }
+
+    #[test]
+    fn interpolates_in_time_within_the_maximum_gap() {
+        let reader = reader(&[0, 3, 6], CoveragePolicy::Error, None);
+
+        assert_close(lookup(&reader, 1.0), 1.0, Coverage::Covered);
+        assert_close(lookup(&reader, 4.5), 4.5, Coverage::Covered);
+        assert_close(lookup(&reader, 8.0), 6.0, Coverage::Covered);
+        assert_close(lookup(&reader, 9.0), 6.0, Coverage::Covered);
+    }
+
+    #[test]
+    fn error_policy_rejects_points_beyond_the_maximum_gap() {
+        let reader = reader(&[0, 3, 12], CoveragePolicy::Error, None);
+
+        // in the middle of a missing stretch of forecast hours, as well as after the last one
+        assert!(lookup(&reader, 7.5).is_err());
+        assert!(lookup(&reader, 15.5).is_err());
+
+        assert_close(lookup(&reader, 14.0), 12.0, Coverage::Covered);
+    }
+
+    #[test]
+    fn clamp_policy_uses_the_closest_dataset() {
+        let reader = reader(&[0, 3, 12], CoveragePolicy::Clamp, None);
+
+        assert_close(lookup(&reader, 7.0), 3.0, Coverage::Clamped);
+        assert_close(lookup(&reader, 20.0), 12.0, Coverage::Clamped);
+        assert_eq!(reader.sources_at(&Point { latitude: 0.0, longitude: 0.0, altitude: 0.0, time: start() + chrono::Duration::hours(20) }), vec!["gfs_4_20200101_0000_012".to_string()]);
+
+        assert_close(lookup(&reader, 1.5), 1.5, Coverage::Covered);
+    }
+
+    #[test]
+    fn fallback_policy_uses_the_fallback_datasets() {
+        let fallback = reader(&[0, 6, 12, 18], CoveragePolicy::Error, None);
+        let reader = reader(&[0, 3], CoveragePolicy::Fallback(String::from("fallback")), Some(fallback));
+
+        assert_close(lookup(&reader, 9.0), FALLBACK_OFFSET + 9.0, Coverage::Fallback);
+        assert_close(lookup(&reader, 2.0), 2.0, Coverage::Covered);
+
+        // the fallback has its own maximum gap, and does not fall back again
+        assert!(lookup(&reader, 22.0).is_err());
+    }
 }
@@ -0,0 +1,284 @@
+use chrono::prelude::*;
+use serde_json;
+
+use predictor::point::*;
+use predictor::predictor::*;
+use predictor::coverage::*;
+use predictor::dataset_reader::DataSetReader;
+use predictor::wind_source::WindSource;
+
+/*
+ * Parameters for a time-lagged ensemble
+ * Every forecast run on disk is a member, unless max_runs limits it to the newest ones
+ */
+pub struct EnsembleParams {
+    pub prediction: PredictorParams,
+    pub max_runs: usize // 0 for every run
+}
+
+/*
+ * The prediction made with a single forecast run
+ */
+#[derive(Serialize)]
+pub struct EnsembleMember {
+    pub run: String,
+    pub trajectory: Vec<Point>,
+    pub burst: Option<Point>, // only for the standard profile
+    pub landing: Point,
+    pub coverage: CoverageReport
+}
+
+/*
+ * A forecast run that could not be used, such as one that does not reach the launch time
+ */
+#[derive(Serialize)]
+pub struct SkippedRun {
+    pub run: String,
+    pub error: String
+}
+
+/*
+ * How far apart the landing sites of the members are
+ * Distances are in meters, from the mean landing site
+ */
+#[derive(Serialize)]
+pub struct LandingSpread {
+    pub mean_latitude: f32,
+    pub mean_longitude: f32,
+    pub mean_distance: f32,
+    pub std_dev_distance: f32,
+    pub max_distance: f32,
+    pub newest_distance: f32 // of the newest run, which is what a single prediction would use
+}
+
+#[derive(Serialize)]
+pub struct Ensemble {
+    pub members: Vec<EnsembleMember>, // newest run first
+    pub skipped: Vec<SkippedRun>,
+    pub spread: LandingSpread
+}
+
+impl Ensemble {
+    pub fn serialize(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/*
+ * Runs the prediction once with each forecast run, treating the runs as members of an ensemble
+ * Successive runs disagree about the same time, and how much they disagree is a cheap estimate of the forecast's uncertainty
+ */
+pub fn calculate_ensemble(params: EnsembleParams, reader: &DataSetReader) -> Result<Ensemble, String> {
+    let mut runs = reader.runs();
+
+    if params.max_runs > 0 {
+        runs.truncate(params.max_runs);
+    }
+
+    let runs = runs.into_iter().map(|run| (run, reader.run(run))).collect();
+
+    ensemble_of(&params.prediction, runs)
+}
+
+/*
+ * Runs the prediction with each run's source, newest run first
+ */
+fn ensemble_of<S: WindSource>(prediction: &PredictorParams, runs: Vec<(DateTime<Utc>, S)>) -> Result<Ensemble, String> {
+    let mut members = vec![];
+    let mut skipped = vec![];
+
+    for (run, source) in runs {
+        let result = predict(prediction.clone(), &source).and_then(|prediction| {
+            member(run, prediction, &source)
+        });
+
+        match result {
+            Ok(member) => {
+                members.push(member);
+            },
+            Err(why) => {
+                skipped.push(SkippedRun {
+                    run: run.to_rfc3339(),
+                    error: why
+                });
+            }
+        }
+    }
+
+    if members.is_empty() {
+        return_error!("No forecast runs cover the prediction");
+    }
+
+    let spread = landing_spread(&members);
+
+    Ok(Ensemble {
+        members,
+        skipped,
+        spread
+    })
+}
+
+fn member<S: WindSource>(run: DateTime<Utc>, prediction: Prediction, source: &S) -> Result<EnsembleMember, String> {
+    let (trajectory, burst, coverage) = match prediction {
+        Prediction::Standard(prediction) => {
+            let mut trajectory = prediction.ascent;
+            trajectory.push(prediction.burst.clone());
+            trajectory.extend(prediction.descent);
+
+            (trajectory, Some(prediction.burst), prediction.coverage.coverage)
+        },
+        Prediction::Float(prediction) | Prediction::ValBal(prediction) => {
+            (prediction.positions, None, prediction.coverage.coverage)
+        }
+    };
+
+    let landing = some_or_return_why!(trajectory.last(), "Prediction has no positions").clone();
+
+    Ok(EnsembleMember {
+        run: run.to_rfc3339(),
+        trajectory,
+        burst,
+        landing,
+        coverage: source.coverage_report(coverage)
+    })
+}
+
+/*
+ * The mean landing site is found on the unit sphere, so members either side of the antimeridian average correctly
+ */
+fn landing_spread(members: &[EnsembleMember]) -> LandingSpread {
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+
+    for member in members.iter() {
+        let latitude = (member.landing.latitude as f64).to_radians();
+        let longitude = (member.landing.longitude as f64).to_radians();
+
+        x += latitude.cos() * longitude.cos();
+        y += latitude.cos() * longitude.sin();
+        z += latitude.sin();
+    }
+
+    let mean = Point {
+        latitude: z.atan2((x * x + y * y).sqrt()).to_degrees() as f32,
+        longitude: y.atan2(x).to_degrees() as f32,
+        altitude: 0.0,
+        time: members[0].landing.time
+    };
+
+    let distances : Vec<f32> = members.iter().map(|member| member.landing.distance_to(&mean)).collect();
+
+    let count = distances.len() as f32;
+    let mean_distance = distances.iter().sum::<f32>() / count;
+    let variance = distances.iter().map(|distance| (distance - mean_distance).powi(2)).sum::<f32>() / count;
+
+    LandingSpread {
+        mean_latitude: mean.latitude,
+        mean_longitude: mean.longitude,
+        mean_distance,
+        std_dev_distance: variance.sqrt(),
+        max_distance: distances.iter().cloned().fold(0.0, f32::max),
+        newest_distance: distances[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use predictor::integrator::Integrator;
+
+    fn launch_time() -> DateTime<Utc> {
+        Utc.ymd(2024, 1, 1).and_hms(12, 0, 0)
+    }
+
+    /*
+     * A forecast run with a uniform east wind, covering only the times from its first forecast on
+     */
+    struct Run {
+        first_forecast: DateTime<Utc>
+    }
+
+    impl WindSource for Run {
+        fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+            if point.time < self.first_forecast {
+                return_error!("Before the first forecast");
+            }
+
+            Ok(Atmospheroid::from_grib(10.0, 0.0, 250.0, point.standard_pressure() * 100.0, 0.0, 0.0))
+        }
+    }
+
+    fn params() -> PredictorParams {
+        PredictorParams {
+            launch: Point {
+                latitude: 40.0,
+                longitude: -105.0,
+                altitude: 0.0,
+                time: launch_time()
+            },
+            profile: PredictionProfile::Standard,
+            burst_altitude: 3000.0,
+            ascent_rate: 5.0,
+            descent_rate: 5.0,
+            duration: Duration::hours(1),
+            integrator: Integrator::Euler,
+            step: Duration::seconds(60)
+        }
+    }
+
+    fn member_landing_at(latitude: f32, longitude: f32) -> EnsembleMember {
+        let landing = Point {
+            latitude,
+            longitude,
+            altitude: 0.0,
+            time: launch_time()
+        };
+
+        EnsembleMember {
+            run: launch_time().to_rfc3339(),
+            trajectory: vec![landing.clone()],
+            burst: None,
+            landing,
+            coverage: CoverageReport {
+                policy: "none",
+                max_gap_hours: 0,
+                coverage: Coverage::Covered
+            }
+        }
+    }
+
+    #[test]
+    fn skips_runs_that_do_not_cover_the_launch() {
+        let newest = launch_time() + Duration::hours(1);
+        let oldest = launch_time() - Duration::hours(6);
+
+        let ensemble = ensemble_of(&params(), vec![
+            (newest, Run { first_forecast: newest }),
+            (oldest, Run { first_forecast: oldest })
+        ]).unwrap();
+
+        assert_eq!(ensemble.members.len(), 1);
+        assert_eq!(ensemble.members[0].run, oldest.to_rfc3339());
+        assert!(ensemble.members[0].landing.longitude > -105.0);
+
+        assert_eq!(ensemble.skipped.len(), 1);
+        assert_eq!(ensemble.skipped[0].run, newest.to_rfc3339());
+        assert!(ensemble.skipped[0].error.contains("Before the first forecast"), "{}", ensemble.skipped[0].error);
+
+        assert!(ensemble_of(&params(), vec![(newest, Run { first_forecast: newest })]).is_err());
+    }
+
+    #[test]
+    fn averages_landings_across_the_antimeridian() {
+        let spread = landing_spread(&[member_landing_at(0.0, 179.5), member_landing_at(0.0, -179.5)]);
+
+        assert!(spread.mean_latitude.abs() < 1e-3);
+        assert!((spread.mean_longitude.abs() - 180.0).abs() < 1e-3, "{}", spread.mean_longitude);
+
+        // half a degree of longitude at the equator
+        assert!((spread.mean_distance - 55_600.0).abs() < 500.0, "{}", spread.mean_distance);
+        assert!((spread.max_distance - spread.mean_distance).abs() < 1.0);
+        assert!(spread.std_dev_distance < 1.0);
+        assert!((spread.newest_distance - spread.mean_distance).abs() < 1.0);
+    }
+}
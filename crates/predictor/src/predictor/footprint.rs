@@ -1,8 +1,12 @@
 use rand;
-use rand::distributions::{Normal, IndependentSample};
+use rand::distributions::{Normal, Range, IndependentSample};
 use serde_json;
 use predictor::point::*;
 use predictor::predictor::*;
+use predictor::integrator::*;
+use predictor::coverage::*;
+use predictor::wind_source::WindSource;
+use predictor::dataset_reader::DataSetReader;
 use chrono::Duration;
 
 /*
@@ -25,7 +29,11 @@ pub struct FootprintParams {
 
 #[derive(Serialize)]
 pub struct Footprint {
-    positions: Vec<Point>
+    positions: Vec<Point>,
+    coverage: CoverageReport,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    members: Vec<u32> // the ensemble member each position was predicted with, when sampling members
 }
 
 impl Footprint {
@@ -34,49 +42,121 @@ impl Footprint {
     }
 }
 
-pub fn calculate_footprint(params : FootprintParams) -> Result<Footprint, String> {
+pub fn calculate_footprint<S: WindSource + ?Sized>(params : FootprintParams, source : &S) -> Result<Footprint, String> {
     let mut positions : Vec<Point> = vec![];
+    let mut coverage = Coverage::Covered;
 
-    let burst_distribution = Normal::new(params.burst_altitude_mean as f64, params.burst_altitude_std_dev as f64);
-    let ascent_distribution = Normal::new(params.ascent_rate_mean as f64, params.ascent_rate_std_dev as f64);
-    let descent_distribution = Normal::new(params.descent_rate_mean as f64, params.descent_rate_std_dev as f64);
+    let distributions = Distributions::new(&params);
 
     for _ in 0..params.trials {
-        let result = predict(PredictorParams {
-            launch: params.launch.clone(),
-            profile: PredictionProfile::Standard,
-
-            burst_altitude: burst_distribution.ind_sample(&mut rand::thread_rng()) as f32,
-            ascent_rate: ascent_distribution.ind_sample(&mut rand::thread_rng()) as f32,
-            descent_rate: descent_distribution.ind_sample(&mut rand::thread_rng()) as f32,
-
-            duration: Duration::minutes(0)
-        });
-
-        match result {
-            Ok(unwrapped) => {
-                match unwrapped {
-                    Prediction::Standard(prediction) => {
-                        let mut borrowed = prediction;
-                        match borrowed.descent.pop() {
-                            Some(point) => {
-                                positions.push(point)
-                            },
-                            _ => {}
-                        }
-                    },
-                    _ => {
-                        panic!("Yikes");
-                    }
-                }
+        let (position, trial_coverage) = result_or_return!(run_trial(&params, &distributions, source));
+
+        coverage = coverage.worst(trial_coverage);
+        positions.extend(position);
+    }
+
+    Ok(Footprint {
+        positions: positions,
+        coverage: source.coverage_report(coverage),
+        members: vec![]
+    })
+}
+
+/*
+ * Like calculate_footprint, but each trial also picks an ensemble member at random to take its winds from
+ * Wind uncertainty usually spreads landing sites far more than balloon uncertainty does, so this gives a more honest footprint
+ */
+pub fn calculate_ensemble_footprint(params : FootprintParams, reader : &DataSetReader) -> Result<Footprint, String> {
+    let member_ids = reader.members();
+
+    if member_ids.is_empty() {
+        return_error!("No ensemble member datasets");
+    }
+
+    let member_readers : Vec<DataSetReader> = member_ids.iter().map(|member| reader.member(*member)).collect();
+
+    let mut positions : Vec<Point> = vec![];
+    let mut members : Vec<u32> = vec![];
+    let mut coverage = Coverage::Covered;
+
+    let distributions = Distributions::new(&params);
+    let member_distribution = Range::new(0, member_ids.len());
+
+    for _ in 0..params.trials {
+        let index = member_distribution.ind_sample(&mut rand::thread_rng());
+
+        let (position, trial_coverage) = result_or_return!(run_trial(&params, &distributions, &member_readers[index]));
+
+        coverage = coverage.worst(trial_coverage);
+
+        match position {
+            Some(position) => {
+                positions.push(position);
+                members.push(member_ids[index]);
             },
-            Err(why) => {
-                return Err(why);
-            }
+            None => {}
         }
     }
 
     Ok(Footprint {
-        positions: positions
+        positions: positions,
+        coverage: reader.coverage_report(coverage),
+        members
     })
 }
+
+/*
+ * The distributions balloon parameters are sampled from
+ */
+struct Distributions {
+    burst: Normal,
+    ascent: Normal,
+    descent: Normal
+}
+
+impl Distributions {
+    fn new(params: &FootprintParams) -> Distributions {
+        Distributions {
+            burst: Normal::new(params.burst_altitude_mean as f64, params.burst_altitude_std_dev as f64),
+            ascent: Normal::new(params.ascent_rate_mean as f64, params.ascent_rate_std_dev as f64),
+            descent: Normal::new(params.descent_rate_mean as f64, params.descent_rate_std_dev as f64)
+        }
+    }
+}
+
+/*
+ * Predicts a single trial, returning where it landed
+ */
+fn run_trial<S: WindSource + ?Sized>(params : &FootprintParams, distributions: &Distributions, source : &S) -> Result<(Option<Point>, Coverage), String> {
+    let result = predict(PredictorParams {
+        launch: params.launch.clone(),
+        profile: PredictionProfile::Standard,
+
+        burst_altitude: distributions.burst.ind_sample(&mut rand::thread_rng()) as f32,
+        ascent_rate: distributions.ascent.ind_sample(&mut rand::thread_rng()) as f32,
+        descent_rate: distributions.descent.ind_sample(&mut rand::thread_rng()) as f32,
+
+        duration: Duration::minutes(0),
+
+        integrator: Integrator::default(),
+        step: Duration::seconds(DEFAULT_STEP_SECONDS)
+    }, source);
+
+    match result {
+        Ok(unwrapped) => {
+            match unwrapped {
+                Prediction::Standard(prediction) => {
+                    let mut borrowed = prediction;
+
+                    Ok((borrowed.descent.pop(), borrowed.coverage.coverage))
+                },
+                _ => {
+                    panic!("Yikes");
+                }
+            }
+        },
+        Err(why) => {
+            Err(why)
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use chrono::prelude::*;
+use chrono::Duration;
+
+use predictor::point::*;
+
+pub const DEFAULT_STEP_SECONDS : i64 = 60;
+
+const ADAMS_HISTORY : usize = 3; // previous velocities Adams-Bashforth needs besides the current one
+
+const DEFAULT_ABSOLUTE_TOLERANCE : f32 = 10.0; // m
+const DEFAULT_RELATIVE_TOLERANCE : f32 = 0.001;
+const MIN_ADAPTIVE_STEP : f32 = 1.0; // seconds, below which steps are taken whatever their error
+const MAX_ADAPTIVE_STEP : f32 = 3600.0;
+
+// Dormand-Prince coefficients: when each stage is evaluated, how it is reached, and the weights of the fifth order solution
+// and of its difference from the fourth order one
+const DORMAND_PRINCE_C : [f32; 7] = [0.0, 1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+const DORMAND_PRINCE_A : [[f32; 6]; 7] = [
+    [0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    [19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0, 0.0, 0.0],
+    [9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0, 0.0],
+    [35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0]
+];
+const DORMAND_PRINCE_ERROR : [f32; 7] = [71.0 / 57600.0, 0.0, -71.0 / 16695.0, 71.0 / 1920.0, -17253.0 / 339200.0, 22.0 / 525.0, -1.0 / 40.0];
+
+/*
+ * How a trajectory is stepped through the wind field
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Integrator {
+    #[default]
+    Euler, // one wind lookup per step
+    RungeKutta4, // four lookups per step
+    AdamsBashforthMoulton, // fourth order predictor-corrector, two lookups per step once started by Runge-Kutta
+    DormandPrince(Tolerance) // adaptive fifth order, six lookups per step, with steps sized to keep the error within the tolerance
+}
+
+/*
+ * How much error an adaptive step may have, in meters of displacement
+ * A step's error may be up to absolute plus relative times how far the step moves
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    pub absolute: f32,
+    pub relative: f32
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            absolute: DEFAULT_ABSOLUTE_TOLERANCE,
+            relative: DEFAULT_RELATIVE_TOLERANCE
+        }
+    }
+}
+
+impl Integrator {
+    pub fn from_name(name: &str) -> Result<Integrator, String> {
+        match name {
+            "euler" => Ok(Integrator::Euler),
+            "rk4" => Ok(Integrator::RungeKutta4),
+            "abm" => Ok(Integrator::AdamsBashforthMoulton),
+            "dopri5" => Ok(Integrator::DormandPrince(Tolerance::default())),
+            _ => Err(format!("Unknown integrator {}", name))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Integrator::Euler => "euler",
+            Integrator::RungeKutta4 => "rk4",
+            Integrator::AdamsBashforthMoulton => "abm",
+            Integrator::DormandPrince(_) => "dopri5"
+        }
+    }
+}
+
+/*
+ * Steps a trajectory, giving a position every step
+ * Adams-Bashforth-Moulton remembers the velocities at earlier steps, and Dormand-Prince steps on its own schedule and
+ * interpolates positions in between, so each step has to follow on from the last, and a new stepper is needed whenever
+ * the velocity field changes, such as at burst
+ */
+pub struct Stepper {
+    integrator: Integrator,
+    seconds: f32,
+
+    history: Vec<Velocity>, // at the previous steps, oldest first
+    adaptive: Option<AdaptiveState>
+}
+
+/*
+ * The last step Dormand-Prince took, with the velocities at either end for interpolating within it
+ */
+struct AdaptiveState {
+    start: Point,
+    start_velocity: Velocity,
+    end: Point,
+    end_velocity: Velocity,
+
+    next_step: f32 // seconds to try next
+}
+
+impl Stepper {
+    pub fn new(integrator: Integrator, step: Duration) -> Stepper {
+        Stepper {
+            integrator,
+            seconds: step.num_milliseconds() as f32 / 1000.0,
+            history: vec![],
+            adaptive: None
+        }
+    }
+
+    /*
+     * Where a point will be one step later, with velocity_at giving the velocity at any point
+     */
+    pub fn step<F>(&mut self, point: &Point, velocity_at: &mut F) -> Result<Point, String> where F: FnMut(&Point) -> Result<Velocity, String> {
+        if self.seconds <= 0.0 {
+            return_error!("Step size must be positive");
+        }
+
+        match self.integrator {
+            Integrator::Euler => {
+                let velocity = result_or_return!(velocity_at(point));
+
+                Ok(point.advance(&velocity, self.seconds))
+            },
+            Integrator::RungeKutta4 => {
+                let velocity = result_or_return!(velocity_at(point));
+
+                self.runge_kutta(point, velocity, velocity_at)
+            },
+            Integrator::AdamsBashforthMoulton => {
+                let velocity = result_or_return!(velocity_at(point));
+
+                self.adams_bashforth_moulton(point, velocity, velocity_at)
+            },
+            Integrator::DormandPrince(tolerance) => self.dormand_prince(point, tolerance, velocity_at)
+        }
+    }
+
+    fn runge_kutta<F>(&self, point: &Point, k1: Velocity, velocity_at: &mut F) -> Result<Point, String> where F: FnMut(&Point) -> Result<Velocity, String> {
+        let half = self.seconds / 2.0;
+
+        let k2 = result_or_return!(velocity_at(&point.advance(&k1, half)));
+        let k3 = result_or_return!(velocity_at(&point.advance(&k2, half)));
+        let k4 = result_or_return!(velocity_at(&point.advance(&k3, self.seconds)));
+
+        let velocity = k1 * (1.0 / 6.0) + &(k2 * (2.0 / 6.0)) + &(k3 * (2.0 / 6.0)) + &(k4 * (1.0 / 6.0));
+
+        Ok(point.advance(&velocity, self.seconds))
+    }
+
+    /*
+     * Predicts with the fourth order Adams-Bashforth method, then corrects with Adams-Moulton using the velocity at the prediction
+     * Until there are enough earlier steps, Runge-Kutta is used instead
+     */
+    fn adams_bashforth_moulton<F>(&mut self, point: &Point, velocity: Velocity, velocity_at: &mut F) -> Result<Point, String> where F: FnMut(&Point) -> Result<Velocity, String> {
+        if self.history.len() < ADAMS_HISTORY {
+            let next = result_or_return!(self.runge_kutta(point, velocity.clone(), velocity_at));
+
+            self.history.push(velocity);
+
+            return Ok(next);
+        }
+
+        let (f3, f2, f1) = (self.history[0].clone(), self.history[1].clone(), self.history[2].clone());
+
+        let predictor = velocity.clone() * (55.0 / 24.0) + &(f1.clone() * (-59.0 / 24.0)) + &(f2.clone() * (37.0 / 24.0)) + &(f3 * (-9.0 / 24.0));
+        let predicted = result_or_return!(velocity_at(&point.advance(&predictor, self.seconds)));
+
+        let corrector = predicted * (9.0 / 24.0) + &(velocity.clone() * (19.0 / 24.0)) + &(f1 * (-5.0 / 24.0)) + &(f2 * (1.0 / 24.0));
+
+        self.history.remove(0);
+        self.history.push(velocity);
+
+        Ok(point.advance(&corrector, self.seconds))
+    }
+
+    /*
+     * Takes as many adaptive steps as it needs to get past the next output time, then interpolates back to it
+     * The first step tries the output interval, and later ones grow or shrink depending on how much error the last had
+     */
+    fn dormand_prince<F>(&mut self, point: &Point, tolerance: Tolerance, velocity_at: &mut F) -> Result<Point, String> where F: FnMut(&Point) -> Result<Velocity, String> {
+        if self.adaptive.is_none() {
+            let velocity = result_or_return!(velocity_at(point));
+
+            self.adaptive = Some(AdaptiveState {
+                start: point.clone(),
+                start_velocity: velocity.clone(),
+                end: point.clone(),
+                end_velocity: velocity,
+
+                next_step: self.seconds.min(MAX_ADAPTIVE_STEP)
+            });
+        }
+
+        let target = point.time + Duration::milliseconds((self.seconds * 1000.0).round() as i64);
+        let state = self.adaptive.as_mut().unwrap();
+
+        while state.end.time < target {
+            let remaining = target.signed_duration_since(state.end.time).num_milliseconds() as f32 / 1000.0;
+
+            // a long step can run past the end of the data when the trajectory does not need to go there,
+            // so it is retried as a step to just the output time
+            let (end, end_velocity, next_step) = match adaptive_step(&state.end, &state.end_velocity, state.next_step, tolerance, velocity_at) {
+                Ok(step) => step,
+                Err(why) => {
+                    if state.next_step <= remaining {
+                        return Err(why);
+                    }
+
+                    result_or_return!(adaptive_step(&state.end, &state.end_velocity, remaining, tolerance, velocity_at))
+                }
+            };
+
+            state.start = state.end.clone();
+            state.start_velocity = state.end_velocity.clone();
+            state.end = end;
+            state.end_velocity = end_velocity;
+            state.next_step = next_step;
+        }
+
+        Ok(interpolate(state, target))
+    }
+}
+
+/*
+ * Tries Dormand-Prince steps from a point, shrinking the step until its error is within the tolerance
+ * Returns where the step ended, the velocity there and how long the next step should be
+ */
+fn adaptive_step<F>(point: &Point, velocity: &Velocity, seconds: f32, tolerance: Tolerance, velocity_at: &mut F) -> Result<(Point, Velocity, f32), String> where F: FnMut(&Point) -> Result<Velocity, String> {
+    let mut seconds = seconds.max(MIN_ADAPTIVE_STEP);
+
+    loop {
+        let mut stages = vec![velocity.clone()];
+
+        for stage in 1..DORMAND_PRINCE_C.len() {
+            let c = DORMAND_PRINCE_C[stage];
+            let slope = combine(&stages, &DORMAND_PRINCE_A[stage]);
+
+            // the stage is reached by moving with slope for the whole step, but is evaluated c of the way through it
+            let stage_point = point.advance(&(slope.clone() * (1.0 / c)), c * seconds);
+
+            if stage == DORMAND_PRINCE_C.len() - 1 {
+                let end_velocity = result_or_return!(velocity_at(&stage_point));
+                stages.push(end_velocity);
+
+                let error = magnitude(&combine(&stages, &DORMAND_PRINCE_ERROR)) * seconds;
+                let allowed = tolerance.absolute + tolerance.relative * magnitude(&slope) * seconds;
+
+                let factor = if error <= 0.0 {
+                    5.0
+                } else {
+                    (0.9 * (allowed / error).powf(0.2)).clamp(0.2, 5.0)
+                };
+
+                if error <= allowed || seconds <= MIN_ADAPTIVE_STEP {
+                    let velocity = stages.pop().unwrap();
+                    let next = (seconds * factor).clamp(MIN_ADAPTIVE_STEP, MAX_ADAPTIVE_STEP);
+
+                    return Ok((stage_point, velocity, next));
+                }
+
+                seconds = (seconds * factor).max(MIN_ADAPTIVE_STEP);
+                break;
+            }
+
+            stages.push(result_or_return!(velocity_at(&stage_point)));
+        }
+    }
+}
+
+/*
+ * Where the trajectory was at a time within the last step, by cubic Hermite interpolation of the displacement
+ */
+fn interpolate(state: &AdaptiveState, time: DateTime<Utc>) -> Point {
+    let seconds = state.end.time.signed_duration_since(state.start.time).num_milliseconds() as f32 / 1000.0;
+    let elapsed = time.signed_duration_since(state.start.time).num_milliseconds() as f32 / 1000.0;
+
+    if seconds <= 0.0 || elapsed <= 0.0 {
+        return state.start.clone();
+    }
+
+    let theta = elapsed / seconds;
+    let average = state.start.velocity_to(&state.end);
+
+    let start_weight = theta * theta * theta - 2.0 * theta * theta + theta;
+    let average_weight = -2.0 * theta * theta * theta + 3.0 * theta * theta;
+    let end_weight = theta * theta * theta - theta * theta;
+
+    // the mean velocity from the start of the step to the time
+    let velocity = state.start_velocity.clone() * (start_weight / theta) + &(average * (average_weight / theta)) + &(state.end_velocity.clone() * (end_weight / theta));
+
+    state.start.advance(&velocity, elapsed)
+}
+
+fn combine(velocities: &[Velocity], weights: &[f32]) -> Velocity {
+    let mut sum = Velocity {
+        north: 0.0,
+        east: 0.0,
+        vertical: 0.0
+    };
+
+    for (velocity, weight) in velocities.iter().zip(weights.iter()) {
+        sum = sum + &(velocity.clone() * *weight);
+    }
+
+    sum
+}
+
+fn magnitude(velocity: &Velocity) -> f32 {
+    (velocity.north * velocity.north + velocity.east * velocity.east + velocity.vertical * velocity.vertical).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use predictor::wind_source::*;
+
+    const ASCENT_RATE : f32 = 5.0; // m/s
+    const SHEAR : f32 = 0.01; // m/s of east wind per m of altitude
+    const STEPS : i64 = 30;
+
+    const TIGHT : Tolerance = Tolerance {
+        absolute: 0.01,
+        relative: 0.0
+    };
+
+    fn start(latitude: f32) -> Point {
+        Point {
+            latitude,
+            longitude: 0.0,
+            altitude: 0.0,
+            time: Utc.ymd(2020, 1, 1).and_hms(0, 0, 0)
+        }
+    }
+
+    fn atmospheroid(east: f32, north: f32) -> Atmospheroid {
+        Atmospheroid::from_grib(east, north, 280.0, 101325.0, 0.0, 0.0)
+    }
+
+    /*
+     * Steps a rising balloon through a source, returning where it ends up and how many velocities were looked up
+     */
+    fn fly<S: WindSource>(source: &S, integrator: Integrator, from: &Point) -> (Point, usize) {
+        let mut stepper = Stepper::new(integrator, Duration::seconds(DEFAULT_STEP_SECONDS));
+        let mut evaluations = 0;
+        let mut velocity_field = |point: &Point| {
+            evaluations += 1;
+
+            source.velocity_at(point).map(|velocity| velocity + &Velocity { north: 0.0, east: 0.0, vertical: ASCENT_RATE })
+        };
+
+        let mut current = from.clone();
+
+        for _ in 0..STEPS {
+            current = stepper.step(&current, &mut velocity_field).unwrap();
+        }
+
+        (current, evaluations)
+    }
+
+    /*
+     * Where the balloon really is after the flight, for an east wind of SHEAR times its altitude
+     */
+    fn exact_shear(from: &Point) -> Point {
+        let seconds = (STEPS * DEFAULT_STEP_SECONDS) as f32;
+
+        // the wind at the average altitude, which the east wind grows linearly with
+        let mean_east = SHEAR * (from.altitude + ASCENT_RATE * seconds / 2.0);
+
+        from.advance(&Velocity { north: 0.0, east: mean_east, vertical: ASCENT_RATE }, seconds)
+    }
+
+    #[test]
+    fn every_integrator_is_exact_in_a_uniform_wind() {
+        let source = AnalyticSource::new(|_: &Point| atmospheroid(10.0, 0.0));
+        let from = start(45.0);
+
+        let seconds = (STEPS * DEFAULT_STEP_SECONDS) as f32;
+        let exact = from.advance(&Velocity { north: 0.0, east: 10.0, vertical: ASCENT_RATE }, seconds);
+
+        for integrator in &[Integrator::Euler, Integrator::RungeKutta4, Integrator::AdamsBashforthMoulton] {
+            let (end, _) = fly(&source, *integrator, &from);
+
+            assert!(end.distance_to(&exact) < 1.0, "{} is {} m off", integrator.name(), end.distance_to(&exact));
+            assert!((end.altitude - exact.altitude).abs() < 0.01, "{}", integrator.name());
+            assert_eq!(end.time, exact.time, "{}", integrator.name());
+        }
+    }
+
+    #[test]
+    fn higher_orders_follow_a_shear_more_closely_than_euler() {
+        let source = AnalyticSource::new(|point: &Point| atmospheroid(SHEAR * point.altitude, 0.0));
+        let from = start(0.0);
+        let exact = exact_shear(&from);
+
+        let (euler, _) = fly(&source, Integrator::Euler, &from);
+        let euler_error = euler.distance_to(&exact);
+
+        for integrator in &[Integrator::RungeKutta4, Integrator::AdamsBashforthMoulton] {
+            let (end, _) = fly(&source, *integrator, &from);
+            let error = end.distance_to(&exact);
+
+            assert!(error * 100.0 < euler_error, "{} is {} m off, Euler {} m", integrator.name(), error, euler_error);
+        }
+    }
+
+    #[test]
+    fn dormand_prince_outputs_every_step() {
+        let source = AnalyticSource::new(|point: &Point| atmospheroid(SHEAR * point.altitude, 0.0));
+        let from = start(0.0);
+
+        let mut stepper = Stepper::new(Integrator::DormandPrince(TIGHT), Duration::seconds(DEFAULT_STEP_SECONDS));
+        let mut velocity_field = |point: &Point| source.velocity_at(point).map(|velocity| velocity + &Velocity { north: 0.0, east: 0.0, vertical: ASCENT_RATE });
+
+        let mut current = from.clone();
+
+        for step in 1..(STEPS + 1) {
+            current = stepper.step(&current, &mut velocity_field).unwrap();
+
+            assert_eq!(current.time, from.time + Duration::seconds(step * DEFAULT_STEP_SECONDS));
+        }
+
+        assert!(current.distance_to(&exact_shear(&from)) < 1.0, "{} m off", current.distance_to(&exact_shear(&from)));
+    }
+
+    #[test]
+    fn dormand_prince_takes_fewer_evaluations_in_a_steady_field() {
+        let source = AnalyticSource::new(|point: &Point| atmospheroid(SHEAR * point.altitude, 0.0));
+        let from = start(0.0);
+
+        let (_, runge_kutta) = fly(&source, Integrator::RungeKutta4, &from);
+        let (end, dormand_prince) = fly(&source, Integrator::DormandPrince(TIGHT), &from);
+
+        assert!(dormand_prince < runge_kutta, "{} evaluations, Runge-Kutta {}", dormand_prince, runge_kutta);
+        assert!(end.distance_to(&exact_shear(&from)) < 1.0);
+    }
+
+    #[test]
+    fn rejects_non_positive_steps() {
+        let mut velocity_field = |_: &Point| Ok(Velocity { north: 0.0, east: 0.0, vertical: 0.0 });
+
+        for seconds in &[0, -60] {
+            for integrator in &[Integrator::Euler, Integrator::RungeKutta4, Integrator::AdamsBashforthMoulton, Integrator::DormandPrince(Tolerance::default())] {
+                let mut stepper = Stepper::new(*integrator, Duration::seconds(*seconds));
+
+                assert!(stepper.step(&start(0.0), &mut velocity_field).is_err(), "{} with {} s", integrator.name(), seconds);
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::f64::consts::PI;
+
+const EARTH_RADIUS : f64 = 6371229.0; // in m, the sphere NCEP uses for its regional grids
+
+/*
+ * A Lambert conformal conic grid, as used by regional models like HRRR and NAM
+ * Points are addressed by fractional grid indices, with (0, 0) at the first grid point
+ * Angles are in degrees, and distances in meters
+ */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LambertConformal {
+    pub nx: usize,
+    pub ny: usize,
+
+    pub la1: f64, // first grid point
+    pub lo1: f64,
+    pub lov: f64, // longitude where the grid is aligned with north
+    pub latin1: f64, // standard parallels
+    pub latin2: f64,
+
+    pub dx: f64,
+    pub dy: f64
+}
+
+impl LambertConformal {
+
+    /*
+     * Grid indices of a latitude and longitude
+     * The result may be outside of the grid
+     */
+    pub fn to_grid(&self, latitude: f64, longitude: f64) -> (f64, f64) {
+        let (x, y) = self.project(latitude, longitude);
+        let (x1, y1) = self.project(self.la1, self.lo1);
+
+        ((x - x1) / self.dx, (y - y1) / self.dy)
+    }
+
+    /*
+     * Latitude and longitude of a grid index, with longitude in [0, 360)
+     */
+    pub fn from_grid(&self, i: f64, j: f64) -> (f64, f64) {
+        let (x1, y1) = self.project(self.la1, self.lo1);
+
+        let x = x1 + i * self.dx;
+        let y = y1 + j * self.dy;
+
+        let n = self.cone();
+        let f = self.scale();
+
+        let rho = n.signum() * (x * x + y * y).sqrt();
+        let theta = if n > 0.0 { x.atan2(-y) } else { (-x).atan2(y) };
+
+        let latitude = 2.0 * (EARTH_RADIUS * f / rho).powf(1.0 / n).atan() - PI / 2.0;
+        let mut longitude = self.lov + (theta / n).to_degrees();
+
+        longitude %= 360.0;
+        if longitude < 0.0 {
+            longitude += 360.0;
+        }
+
+        (latitude.to_degrees(), longitude)
+    }
+
+    /*
+     * Rotates a wind relative to the grid's axes so that it is relative to east and north instead
+     */
+    pub fn to_earth_relative(&self, longitude: f64, u: f32, v: f32) -> (f32, f32) {
+        let angle = self.cone() * LambertConformal::longitude_difference(longitude, self.lov).to_radians();
+
+        let (sin, cos) = (angle.sin() as f32, angle.cos() as f32);
+
+        (cos * u + sin * v, -sin * u + cos * v)
+    }
+
+    /*
+     * Projected coordinates, relative to the pole of the cone
+     */
+    fn project(&self, latitude: f64, longitude: f64) -> (f64, f64) {
+        let n = self.cone();
+        let rho = EARTH_RADIUS * self.scale() / (PI / 4.0 + latitude.to_radians() / 2.0).tan().powf(n);
+        let theta = n * LambertConformal::longitude_difference(longitude, self.lov).to_radians();
+
+        (rho * theta.sin(), -rho * theta.cos())
+    }
+
+    fn cone(&self) -> f64 {
+        let phi1 = self.latin1.to_radians();
+        let phi2 = self.latin2.to_radians();
+
+        if (self.latin1 - self.latin2).abs() < 1e-9 {
+            return phi1.sin();
+        }
+
+        (phi1.cos() / phi2.cos()).ln() /
+            ((PI / 4.0 + phi2 / 2.0).tan() / (PI / 4.0 + phi1 / 2.0).tan()).ln()
+    }
+
+    fn scale(&self) -> f64 {
+        let phi1 = self.latin1.to_radians();
+        let n = self.cone();
+
+        phi1.cos() * (PI / 4.0 + phi1 / 2.0).tan().powf(n) / n
+    }
+
+    /*
+     * Difference between two longitudes, in [-180, 180)
+     */
+    fn longitude_difference(longitude: f64, reference: f64) -> f64 {
+        let mut difference = (longitude - reference) % 360.0;
+
+        if difference >= 180.0 {
+            difference -= 360.0;
+        } else if difference < -180.0 {
+            difference += 360.0;
+        }
+
+        difference
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hrrr() -> LambertConformal {
+        LambertConformal {
+            nx: 1799,
+            ny: 1059,
+            la1: 21.138123,
+            lo1: 237.280472,
+            lov: 262.5,
+            latin1: 38.5,
+            latin2: 38.5,
+            dx: 3000.0,
+            dy: 3000.0
+        }
+    }
+
+    #[test]
+    fn projects_the_corners_of_the_hrrr_grid() {
+        let grid = hrrr();
+
+        let (i, j) = grid.to_grid(21.138123, 237.280472);
+        assert!(i.abs() < 1e-6 && j.abs() < 1e-6);
+
+        // the last grid point, as listed by wgrib2
+        let (i, j) = grid.to_grid(47.842195, 299.082807);
+        assert!((i - 1798.0).abs() < 0.01 && (j - 1058.0).abs() < 0.01, "({}, {})", i, j);
+
+        let (latitude, longitude) = grid.from_grid(1798.0, 1058.0);
+        assert!((latitude - 47.842195).abs() < 1e-4 && (longitude - 299.082807).abs() < 1e-4, "({}, {})", latitude, longitude);
+    }
+
+    #[test]
+    fn round_trips_between_grid_indices_and_coordinates() {
+        let grid = hrrr();
+
+        for &(i, j) in &[(0.0, 0.0), (899.5, 529.25), (1200.0, 80.0), (1798.0, 1058.0), (-10.0, 1100.0)] {
+            let (latitude, longitude) = grid.from_grid(i, j);
+            let (round_i, round_j) = grid.to_grid(latitude, longitude);
+
+            assert!((round_i - i).abs() < 1e-6 && (round_j - j).abs() < 1e-6, "({}, {}) came back as ({}, {})", i, j, round_i, round_j);
+        }
+
+        // negative longitudes are the same place
+        let (i, j) = grid.to_grid(40.0, -100.0);
+        let (same_i, same_j) = grid.to_grid(40.0, 260.0);
+        assert!((i - same_i).abs() < 1e-9 && (j - same_j).abs() < 1e-9);
+    }
+}
@@ -1,11 +1,35 @@
 pub mod dataset;
+pub mod dataset_name;
 pub mod predictor;
 pub mod footprint;
+pub mod ensemble;
 pub mod point;
 pub mod dataset_reader;
+pub mod tile;
+pub mod coverage;
+pub mod wind_source;
+pub mod sounding;
+pub mod lambert;
+pub mod regional;
+pub mod telemetry;
+pub mod profile;
+pub mod wind_grid;
+pub mod integrator;
 
 pub use predictor::dataset::*;
+pub use predictor::dataset_name::*;
 pub use predictor::predictor::*;
 pub use predictor::footprint::*;
+pub use predictor::ensemble::*;
 pub use predictor::point::*;
 pub use predictor::dataset_reader::*;
+pub use predictor::tile::*;
+pub use predictor::coverage::*;
+pub use predictor::wind_source::*;
+pub use predictor::sounding::*;
+pub use predictor::lambert::*;
+pub use predictor::regional::*;
+pub use predictor::telemetry::*;
+pub use predictor::profile::*;
+pub use predictor::wind_grid::*;
+pub use predictor::integrator::*;
@@ -8,7 +8,12 @@ use std::f32;
 
 const INTEGRAL_DURATION : f32 = 60.0; // seconds
 const EARTH_RADIUS : f32 = 6371_000.0; // in m
-const DATA_RESOLUTION : f32 = 0.5; // resolution in GRIB files
+const DRY_AIR_GAS_CONSTANT : f32 = 287.05; // in J/(kg K)
+const GRAVITY : f32 = 9.80665; // in m/s^2
+pub const DATA_RESOLUTION : f32 = 0.5; // resolution of datasets that do not say otherwise, such as v1 tiles
+
+// isobaric levels used for lookups, in hPa
+pub const LEVELS : [i32; 31] = [2, 3, 5, 7, 10, 20, 30, 50, 70, 80, 100, 150, 200, 250, 300, 350, 400, 450, 500, 550, 600, 650, 700, 750, 800, 850, 900, 925, 950, 975, 1000];
 
 pub type Temperature = f32;
 
@@ -51,6 +56,18 @@ pub struct AlignedPoint {
     pub level: i32
 }
 
+/*
+ * Key for an aligned point in the atmosphere cache
+ * Each part is stored in its own field, so no two grid points, levels or datasets can share a key
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct CacheKey {
+    pub dataset_id: u32,
+    pub level: i32,
+    pub lat_index: i32,
+    pub lon_index: i32
+}
+
 /*
  * Directions in which a point can be aligned
  */
@@ -87,12 +104,67 @@ pub struct Velocity {
  */
 #[derive(Clone)]
 pub struct Atmospheroid {
-    pub temperature : Temperature,
-    pub velocity: Velocity
+    pub temperature : Temperature, // K
+    pub velocity: Velocity, // horizontal wind, with no vertical component so that trajectories only rise and fall as the balloon does
+    pub vertical_velocity: f32, // m/s, positive upwards
+    pub pressure: f32, // Pa
+    pub specific_humidity: f32, // kg/kg
+    pub density: f32 // kg/m^3
+}
+
+/*
+ * The atmosphere on an isobaric level, and how high the level is there
+ */
+#[derive(Clone)]
+pub struct IsobaricLevel {
+    pub atmospheroid: Atmospheroid,
+    pub height: f32 // geopotential height in m
 }
 
 impl Point {
 
+    /*
+     * Moves the point with a constant velocity for a number of seconds
+     */
+    pub fn advance(&self, velocity: &Velocity, seconds: f32) -> Point {
+        Point {
+            latitude: {
+                self.latitude + (velocity.north*seconds / EARTH_RADIUS) * (180.0 / f32::consts::PI)
+            },
+            longitude: {
+                bound(
+                    self.longitude + (velocity.east*seconds / EARTH_RADIUS) *
+                        (180.0 / f32::consts::PI) / f32::cos(self.latitude * f32::consts::PI/180.0)
+                )
+            },
+            altitude: {
+                self.altitude + velocity.vertical * seconds
+            },
+            time: {
+                self.time + Duration::milliseconds((seconds * 1000.0).round() as i64)
+            }
+        }
+    }
+
+    /*
+     * The constant velocity that would move this point to another, the reverse of adding a velocity
+     */
+    pub fn velocity_to(&self, other : &Point) -> Velocity {
+        let seconds = other.time.signed_duration_since(self.time).num_milliseconds() as f32 / 1000.0;
+
+        if seconds == 0.0 {
+            return Velocity { north: 0.0, east: 0.0, vertical: 0.0 };
+        }
+
+        let latitude = (self.latitude + other.latitude) / 2.0;
+
+        Velocity {
+            north: (other.latitude - self.latitude).to_radians() * EARTH_RADIUS / seconds,
+            east: bound(other.longitude - self.longitude).to_radians() * EARTH_RADIUS * latitude.to_radians().cos() / seconds,
+            vertical: (other.altitude - self.altitude) / seconds
+        }
+    }
+
     /*
      * Returns the distance to another point in meters
      */
@@ -112,14 +184,30 @@ impl Point {
         c * EARTH_RADIUS
     }
 
+    /*
+     * Pressure at the point's altitude in the standard atmosphere, in hPa
+     */
+    pub fn standard_pressure(&self) -> f32 {
+        1013.25*(1.0 - self.altitude/44330.0).powf(5.255)
+    }
+
+    /*
+     * Altitude at which the standard atmosphere has a pressure, in hPa, the reverse of standard_pressure
+     */
+    pub fn standard_altitude(pressure: f32) -> f32 {
+        44330.0*(1.0 - (pressure/1013.25).powf(1.0/5.255))
+    }
+
     /*
      * Converts the point to an aligned point
+     * Levels are picked using the standard atmosphere; Dataset corrects them using geopotential height when it can
+     * Resolution is the spacing of the dataset's grid, in degrees
      */
-    pub fn align(&self) -> Alignment {
-        let isobaric_hpa = 1013.25*(1.0 - self.altitude/44330.0).powf(5.255);
+    pub fn align(&self, resolution: f32) -> Alignment {
+        let isobaric_hpa = self.standard_pressure();
 
         //TODO: make a fast lookup structure for this
-        let levels = [2, 3, 5, 7, 10, 20, 30, 50, 70, 80, 100, 150, 200, 250, 300, 350, 400, 450, 500, 550, 600, 650, 700, 750, 800, 850, 900, 925, 950, 975, 1000];
+        let levels = LEVELS;
         let mut best_level : i32 = 1;
         let mut best_level_diff : f32 = (isobaric_hpa - (best_level as f32)).abs();
         let mut best_level_index : usize = 0;
@@ -162,9 +250,9 @@ impl Point {
             level_down_diff = best_level_diff;
         }
 
-        // Round to directional DATA_RESOLUTION
-        let mangled_lat = self.latitude / DATA_RESOLUTION;
-        let mangled_lon = self.longitude / DATA_RESOLUTION;
+        // Round to directional resolution
+        let mangled_lat = self.latitude / resolution;
+        let mangled_lon = self.longitude / resolution;
 
         let percent_north = mangled_lat.ceil() - mangled_lat.floor();
         let percent_east = mangled_lon.ceil() - mangled_lon.floor();
@@ -176,43 +264,43 @@ impl Point {
 
         Alignment {
             ne_down: AlignedPoint {
-                latitude: Point::align_lat(mangled_lat.ceil()),
-                longitude: Point::align_lon(mangled_lon.ceil()),
+                latitude: Point::align_lat(mangled_lat.ceil(), resolution),
+                longitude: Point::align_lon(mangled_lon.ceil(), resolution),
                 level: level_down
             },
             ne_up: AlignedPoint {
-                latitude: Point::align_lat(mangled_lat.ceil()),
-                longitude: Point::align_lon(mangled_lon.ceil()),
+                latitude: Point::align_lat(mangled_lat.ceil(), resolution),
+                longitude: Point::align_lon(mangled_lon.ceil(), resolution),
                 level: level_up
             },
             nw_down: AlignedPoint {
-                latitude: Point::align_lat(mangled_lat.ceil()),
-                longitude: Point::align_lon(mangled_lon.floor()),
+                latitude: Point::align_lat(mangled_lat.ceil(), resolution),
+                longitude: Point::align_lon(mangled_lon.floor(), resolution),
                 level: level_down
             },
             nw_up: AlignedPoint {
-                latitude: Point::align_lat(mangled_lat.ceil()),
-                longitude: Point::align_lon(mangled_lon.floor()),
+                latitude: Point::align_lat(mangled_lat.ceil(), resolution),
+                longitude: Point::align_lon(mangled_lon.floor(), resolution),
                 level: level_up
             },
             se_down: AlignedPoint {
-                latitude: Point::align_lat(mangled_lat.floor()),
-                longitude: Point::align_lon(mangled_lon.ceil()),
+                latitude: Point::align_lat(mangled_lat.floor(), resolution),
+                longitude: Point::align_lon(mangled_lon.ceil(), resolution),
                 level: level_down
             },
             se_up: AlignedPoint {
-                latitude: Point::align_lat(mangled_lat.floor()),
-                longitude: Point::align_lon(mangled_lon.ceil()),
+                latitude: Point::align_lat(mangled_lat.floor(), resolution),
+                longitude: Point::align_lon(mangled_lon.ceil(), resolution),
                 level: level_up
             },
             sw_down: AlignedPoint {
-                latitude: Point::align_lat(mangled_lat.floor()),
-                longitude: Point::align_lon(mangled_lon.floor()),
+                latitude: Point::align_lat(mangled_lat.floor(), resolution),
+                longitude: Point::align_lon(mangled_lon.floor(), resolution),
                 level: level_down
             },
             sw_up: AlignedPoint {
-                latitude: Point::align_lat(mangled_lat.floor()),
-                longitude: Point::align_lon(mangled_lon.floor()),
+                latitude: Point::align_lat(mangled_lat.floor(), resolution),
+                longitude: Point::align_lon(mangled_lon.floor(), resolution),
                 level: level_up
             },
             percent_north: percent_north,
@@ -225,8 +313,16 @@ impl Point {
 
     }
 
-    fn align_lat(rounded : f32) -> f32 {
-        let mut lat = rounded * DATA_RESOLUTION;
+    /*
+     * The longitude datasets store the point at, in [0, 360), the same convention aligned points use
+     * Regional models have to be looked up at it too, or they would be blended with global winds from elsewhere
+     */
+    pub fn dataset_longitude(&self) -> f32 {
+        Point::align_lon(self.longitude, 1.0)
+    }
+
+    fn align_lat(rounded : f32, resolution : f32) -> f32 {
+        let mut lat = rounded * resolution;
 
         if lat > 90.0 {
             lat = 90.0;
@@ -237,8 +333,8 @@ impl Point {
         return lat
     }
 
-    fn align_lon(rounded : f32) -> f32 {
-        let mut lon = rounded * DATA_RESOLUTION + 180.0;
+    fn align_lon(rounded : f32, resolution : f32) -> f32 {
+        let mut lon = rounded * resolution + 180.0;
 
         if lon >= 360.0 {
             lon -= 360.0;
@@ -250,95 +346,64 @@ impl Point {
     }
 }
 
+impl Alignment {
+
+    /*
+     * Moves an alignment onto different levels, keeping its horizontal position
+     * percent_up is how far the point is from the lower level to the upper one
+     */
+    pub fn with_levels(self, level_down: i32, level_up: i32, percent_up: f32) -> Alignment {
+        let at = |aligned: &AlignedPoint, level: i32| AlignedPoint {
+            latitude: aligned.latitude,
+            longitude: aligned.longitude,
+            level
+        };
+
+        Alignment {
+            ne_down: at(&self.ne_down, level_down),
+            ne_up: at(&self.ne_up, level_up),
+            nw_down: at(&self.nw_down, level_down),
+            nw_up: at(&self.nw_up, level_up),
+            se_down: at(&self.se_down, level_down),
+            se_up: at(&self.se_up, level_up),
+            sw_down: at(&self.sw_down, level_down),
+            sw_up: at(&self.sw_up, level_up),
+
+            percent_north: self.percent_north,
+            percent_south: self.percent_south,
+            percent_east: self.percent_east,
+            percent_west: self.percent_west,
+            percent_down: 1.0 - percent_up,
+            percent_up
+        }
+    }
+}
+
 impl<'a> Add<&'a Velocity> for Point {
     type Output = Point;
 
     fn add(self, velocity: &'a Velocity) -> Point {
-        Point {
-            latitude: {
-                self.latitude + (velocity.north*INTEGRAL_DURATION / EARTH_RADIUS) * (180.0 / f32::consts::PI)
-            },
-            longitude: {
-                bound(
-                    self.longitude + (velocity.east*INTEGRAL_DURATION / EARTH_RADIUS) *
-                        (180.0 / f32::consts::PI) / f32::cos(self.latitude * f32::consts::PI/180.0)
-                )
-            },
-            altitude: {
-                self.altitude + velocity.vertical * INTEGRAL_DURATION
-            },
-            time: {
-                self.time + Duration::seconds(INTEGRAL_DURATION as i64)
-            }
-        }
+        self.advance(velocity, INTEGRAL_DURATION)
     }
 }
 
 impl AlignedPoint {
 
-    pub fn key(&self, dataset_id : u32) -> u32 {
-        AlignedPoint::cache_key(self.level, self.latitude, self.longitude, dataset_id)
+    pub fn key(&self, dataset_id : u32, resolution : f32) -> CacheKey {
+        AlignedPoint::cache_key(self.level, self.latitude, self.longitude, dataset_id, resolution)
     }
 
     /*
-     * Hashes the point to a u32
+     * Builds the cache key for a point
+     * Latitude and longitude are converted to grid indices, so that float error cannot split one point into two keys
      */
-    pub fn cache_key(level : i32, latitude : f32, longitude: f32, dataset_id : u32) -> u32 {
-        // give 10 bits each to each part of the key
-        // each of these parts is converted to a u32
-        // WARNING: if any has a value greater than 1023 this will have cache collisions
-
-        let level_index : u32 = match level {
-            2 => 0,
-            3 => 1,
-            5 => 2,
-            7 => 3,
-            10 => 4,
-            20 => 5,
-            30 => 6,
-            50 => 7,
-            70 => 8,
-            80 => 9,
-            100 => 10,
-            150 => 11,
-            200 => 12,
-            250 => 13,
-            300 => 14,
-            350 => 15,
-            400 => 16,
-            450 => 17,
-            500 => 18,
-            550 => 19,
-            600 => 20,
-            650 => 21,
-            700 => 22,
-            750 => 23,
-            800 => 24,
-            850 => 25,
-            900 => 26,
-            925 => 27,
-            950 => 28,
-            975 => 29,
-            1000 => 30,
-            _ => {
-                panic!(format!("Unknown level for an aligned point: {}", level))
-            }
-        };
-
-        AlignedPoint::mask5(level_index) +
-            AlignedPoint::mask5(dataset_id) << 5 +
-            (AlignedPoint::mask10((latitude + 90.0)/DATA_RESOLUTION) << 10) +
-            (AlignedPoint::mask10(longitude/DATA_RESOLUTION) << 20)
-    }
-
-    fn mask5(num : u32) -> u32 {
-        let mask : u32 = 0b11111;
-        num & mask
-    }
-
-    fn mask10(num : f32) -> u32 {
-        let mask : u32 = 0b1111111111;
-        (num.trunc() as u32) & mask
+    pub fn cache_key(level : i32, latitude : f32, longitude: f32, dataset_id : u32, resolution : f32) -> CacheKey {
+        CacheKey {
+            dataset_id,
+            level,
+            lat_index: ((latitude + 90.0) / resolution).round() as i32,
+            lon_index: (longitude / resolution).round() as i32
+        }
     }
 }
 
@@ -366,13 +431,43 @@ impl Mul<f32> for Velocity {
     }
 }
 
+impl Atmospheroid {
+
+    /*
+     * Builds an atmospheroid from values as they are stored in GRIB files
+     * Density comes from the ideal gas law using virtual temperature, which accounts for humidity
+     * Omega (Pa/s, positive downwards) is converted to a vertical velocity in m/s, positive upwards
+     */
+    pub fn from_grib(east: f32, north: f32, temperature: Temperature, pressure: f32, specific_humidity: f32, omega: f32) -> Atmospheroid {
+        let virtual_temperature = temperature * (1.0 + 0.608 * specific_humidity);
+        let density = pressure / (DRY_AIR_GAS_CONSTANT * virtual_temperature);
+
+        Atmospheroid {
+            temperature,
+            velocity: Velocity {
+                east,
+                north,
+                vertical: 0.0
+            },
+            vertical_velocity: -omega / (density * GRAVITY),
+            pressure,
+            specific_humidity,
+            density
+        }
+    }
+}
+
 impl<'a> Add<&'a Atmospheroid> for Atmospheroid {
     type Output = Atmospheroid;
 
     fn add(self, other: &'a Atmospheroid) -> Atmospheroid {
         Atmospheroid {
             temperature: self.temperature + other.temperature,
-            velocity: self.velocity + &other.velocity
+            velocity: self.velocity + &other.velocity,
+            vertical_velocity: self.vertical_velocity + other.vertical_velocity,
+            pressure: self.pressure + other.pressure,
+            specific_humidity: self.specific_humidity + other.specific_humidity,
+            density: self.density + other.density
         }
     }
 }
@@ -386,7 +481,33 @@ impl Mul<f32> for Atmospheroid {
     fn mul(self, factor: f32) -> Atmospheroid {
         Atmospheroid {
             velocity: self.velocity * factor,
-            temperature: self.temperature * factor
+            vertical_velocity: self.vertical_velocity * factor,
+            temperature: self.temperature * factor,
+            pressure: self.pressure * factor,
+            specific_humidity: self.specific_humidity * factor,
+            density: self.density * factor
+        }
+    }
+}
+
+impl<'a> Add<&'a IsobaricLevel> for IsobaricLevel {
+    type Output = IsobaricLevel;
+
+    fn add(self, other: &'a IsobaricLevel) -> IsobaricLevel {
+        IsobaricLevel {
+            atmospheroid: self.atmospheroid + &other.atmospheroid,
+            height: self.height + other.height
+        }
+    }
+}
+
+impl Mul<f32> for IsobaricLevel {
+    type Output = IsobaricLevel;
+
+    fn mul(self, factor: f32) -> IsobaricLevel {
+        IsobaricLevel {
+            atmospheroid: self.atmospheroid * factor,
+            height: self.height * factor
         }
     }
 }
@@ -394,7 +515,12 @@ impl Mul<f32> for Atmospheroid {
 /*
  * Circularly clamps value between -180 and 180
  */
-fn bound(x : f32) -> f32 {
+pub fn bound(x : f32) -> f32 {
+    // shifting by 180 costs precision, which adds up over many small steps
+    if x >= -180.0 && x < 180.0 {
+        return x;
+    }
+
     let mut val = x;
     while val < -180.0 {
         val += 360.0
@@ -402,3 +528,75 @@ fn bound(x : f32) -> f32 {
 
     ((val + 180.0) % 360.0) - 180.0
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    const RESOLUTIONS : [f32; 3] = [0.25, 0.5, 1.0];
+    const DATASET_IDS : [u32; 5] = [1, 31, 32, 33, 1024];
+
+    /*
+     * Every aligned point of every grid, as the latitude and longitude the alignment produces
+     */
+    fn grid_points(resolution: f32) -> Vec<(f32, f32)> {
+        let rows = (180.0 / resolution) as i32 + 1;
+        let columns = (360.0 / resolution) as i32;
+
+        let mut points = Vec::with_capacity((rows * columns) as usize);
+
+        for row in 0..rows {
+            for column in 0..columns {
+                points.push((Point::align_lat((row - rows / 2) as f32, resolution), Point::align_lon((column - columns / 2) as f32, resolution)));
+            }
+        }
+
+        points
+    }
+
+    #[test]
+    fn cache_keys_are_unique_across_the_grid() {
+        for &resolution in RESOLUTIONS.iter() {
+            let points = grid_points(resolution);
+            let mut keys = HashSet::with_capacity(points.len());
+
+            for &(latitude, longitude) in points.iter() {
+                assert!(
+                    keys.insert(AlignedPoint::cache_key(LEVELS[0], latitude, longitude, DATASET_IDS[0], resolution)),
+                    "{}, {} shares a key at {} degrees", latitude, longitude, resolution
+                );
+            }
+
+            assert_eq!(keys.len(), points.len());
+        }
+    }
+
+    /*
+     * The full grid at every level and dataset has too many keys to hold in one set, so this checks that the level and
+     * dataset only ever change their own fields of the key, leaving the grid indices the test above shows are unique
+     */
+    #[test]
+    fn cache_keys_are_unique_across_levels_and_datasets() {
+        for &resolution in RESOLUTIONS.iter() {
+            for (latitude, longitude) in grid_points(resolution) {
+                let grid_key = AlignedPoint::cache_key(LEVELS[0], latitude, longitude, DATASET_IDS[0], resolution);
+
+                for &level in LEVELS.iter() {
+                    for &dataset_id in DATASET_IDS.iter() {
+                        assert_eq!(AlignedPoint::cache_key(level, latitude, longitude, dataset_id, resolution), CacheKey { dataset_id, level, ..grid_key });
+                    }
+                }
+            }
+        }
+
+        let mut keys = HashSet::new();
+
+        for &level in LEVELS.iter() {
+            for &dataset_id in DATASET_IDS.iter() {
+                assert!(keys.insert(AlignedPoint::cache_key(level, 37.5, 237.5, dataset_id, 0.5)), "level {} of dataset {} shares a key", level, dataset_id);
+            }
+        }
+    }
+}
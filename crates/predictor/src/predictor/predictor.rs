@@ -1,8 +1,11 @@
 use predictor::point::*;
-use predictor::dataset_reader::velocity_at;
+use predictor::coverage::*;
+use predictor::integrator::*;
+use predictor::wind_source::WindSource;
 use chrono::Duration;
 use serde_json;
 
+#[derive(Clone)]
 pub enum PredictionProfile {
     Standard,
     Float,
@@ -12,6 +15,7 @@ pub enum PredictionProfile {
 /*
  * All parameters that might be passed in to the prediction
  */
+#[derive(Clone)]
 pub struct PredictorParams {
     pub launch: Point,
 
@@ -23,7 +27,11 @@ pub struct PredictorParams {
     pub descent_rate: f32, // meters per second
 
     // valbal
-    pub duration: Duration
+    pub duration: Duration,
+
+    // how the trajectory is integrated, and the time between positions
+    pub integrator: Integrator,
+    pub step: Duration
 }
 
 /*
@@ -36,6 +44,9 @@ struct StandardPredictorParams {
     burst_altitude: f32, // meters
     ascent_rate: f32, // meters per second
     descent_rate: f32, // meters per second
+
+    integrator: Integrator,
+    step: Duration
 }
 
 /*
@@ -45,7 +56,10 @@ struct FloatPredictionParams {
     launch: Point,
 
     // valbal
-    duration: Duration
+    duration: Duration,
+
+    integrator: Integrator,
+    step: Duration
 }
 
 /*
@@ -61,12 +75,14 @@ pub enum Prediction {
 pub struct StandardPrediction {
     pub ascent: Vec<Point>,
     pub burst: Point,
-    pub descent: Vec<Point>
+    pub descent: Vec<Point>,
+    pub coverage: CoverageReport
 }
 
 #[derive(Serialize)]
 pub struct FloatPrediction {
-    pub positions: Vec<Point>
+    pub positions: Vec<Point>,
+    pub coverage: CoverageReport
 }
 
 impl Prediction {
@@ -86,7 +102,7 @@ impl Prediction {
  * Wrapper function for predictor
  * Based on the profile, delegates to the appropriate model
  */
-pub fn predict(params : PredictorParams) -> Result<Prediction, String> {
+pub fn predict<S: WindSource + ?Sized>(params : PredictorParams, source : &S) -> Result<Prediction, String> {
     match params.profile {
         PredictionProfile::Standard => {
             standard_predict(StandardPredictorParams {
@@ -94,35 +110,54 @@ pub fn predict(params : PredictorParams) -> Result<Prediction, String> {
 
                 burst_altitude: params.burst_altitude,
                 ascent_rate: params.ascent_rate,
-                descent_rate: params.descent_rate
-            })
+                descent_rate: params.descent_rate,
+
+                integrator: params.integrator,
+                step: params.step
+            }, source)
         },
 
         PredictionProfile::Float => {
             float_predict(FloatPredictionParams {
                 launch: params.launch,
 
-                duration: params.duration
-            })
+                duration: params.duration,
+
+                integrator: params.integrator,
+                step: params.step
+            }, source)
         },
 
         PredictionProfile::ValBal => {
             valbal_predict(FloatPredictionParams {
                 launch: params.launch,
 
-                duration: params.duration
-            })
+                duration: params.duration,
+
+                integrator: params.integrator,
+                step: params.step
+            }, source)
         }
     }
 }
 
-// TODO: Use Adams Bashforth Moulton for fancy, high quality integrals
+/*
+ * Looks up the velocity at a point, keeping track of the worst coverage seen so far
+ */
+fn velocity_at<S: WindSource + ?Sized>(point: &Point, source : &S, coverage: &mut Coverage) -> Result<Velocity, String> {
+    let (velocity, point_coverage) = result_or_return!(source.velocity_with_coverage_at(point));
+
+    *coverage = coverage.worst(point_coverage);
+
+    Ok(velocity)
+}
 
-fn standard_predict(params : StandardPredictorParams) -> Result<Prediction, String> {
+fn standard_predict<S: WindSource + ?Sized>(params : StandardPredictorParams, source : &S) -> Result<Prediction, String> {
 
     // TODO: implement checks to avoid infinite loops if ascent rate or descent rate is silly
 
     let mut current : Point = params.launch;
+    let mut coverage = Coverage::Covered;
 
     // ascent
     let mut ascent : Vec<Point> = vec![];
@@ -133,11 +168,14 @@ fn standard_predict(params : StandardPredictorParams) -> Result<Prediction, Stri
         vertical: params.ascent_rate
     };
 
-    while current.altitude < params.burst_altitude {
-        let velocity = result_or_return!(velocity_at(&current)) + &ascent_velocity;
+    {
+        let mut stepper = Stepper::new(params.integrator, params.step);
+        let mut velocity_field = |point: &Point| velocity_at(point, source, &mut coverage).map(|velocity| velocity + &ascent_velocity);
 
-        current = current + &velocity;
-        ascent.push(current.clone());
+        while current.altitude < params.burst_altitude {
+            current = result_or_return!(stepper.step(&current, &mut velocity_field));
+            ascent.push(current.clone());
+        }
     }
 
     // burst
@@ -151,53 +189,68 @@ fn standard_predict(params : StandardPredictorParams) -> Result<Prediction, Stri
         east: 0.0,
         vertical: -params.descent_rate
     };
-    while current.altitude > 0.0 {
-        let velocity = result_or_return!(velocity_at(&current)) + &descent_velocity;
 
-        current = current + &velocity;
-        descent.push(current.clone());
+    {
+        let mut stepper = Stepper::new(params.integrator, params.step);
+        let mut velocity_field = |point: &Point| velocity_at(point, source, &mut coverage).map(|velocity| velocity + &descent_velocity);
+
+        while current.altitude > 0.0 {
+            current = result_or_return!(stepper.step(&current, &mut velocity_field));
+            descent.push(current.clone());
+        }
     }
 
     Ok(Prediction::Standard(StandardPrediction {
-        ascent, burst, descent
+        ascent, burst, descent,
+        coverage: source.coverage_report(coverage)
     }))
 }
 
-fn float_predict(params : FloatPredictionParams) -> Result<Prediction, String> {
+fn float_predict<S: WindSource + ?Sized>(params : FloatPredictionParams, source : &S) -> Result<Prediction, String> {
     let mut current : Point = params.launch;
     let mut positions : Vec<Point> = vec![];
+    let mut coverage = Coverage::Covered;
 
     let launch_time = current.clone().time;
     let end_time = launch_time + params.duration;
 
-    while current.time < end_time {
-        let velocity = result_or_return!(velocity_at(&current));
+    {
+        let mut stepper = Stepper::new(params.integrator, params.step);
+        let mut velocity_field = |point: &Point| velocity_at(point, source, &mut coverage);
 
-        current = current + &velocity;
-        positions.push(current.clone());
+        while current.time < end_time {
+            current = result_or_return!(stepper.step(&current, &mut velocity_field));
+            positions.push(current.clone());
+        }
     }
 
     Ok(Prediction::ValBal(FloatPrediction {
-        positions
+        positions,
+        coverage: source.coverage_report(coverage)
     }))
 }
 
-fn valbal_predict(params : FloatPredictionParams) -> Result<Prediction, String> {
+fn valbal_predict<S: WindSource + ?Sized>(params : FloatPredictionParams, source : &S) -> Result<Prediction, String> {
     let mut current : Point = params.launch;
     let mut positions : Vec<Point> = vec![];
+    let mut coverage = Coverage::Covered;
 
     let launch_time = current.clone().time;
     let end_time = launch_time + params.duration;
 
-    while current.time < end_time {
-        let velocity = result_or_return!(velocity_at(&current));
+    {
+        let mut stepper = Stepper::new(params.integrator, params.step);
+        let mut velocity_field = |point: &Point| velocity_at(point, source, &mut coverage);
 
-        current = current + &velocity;
-        positions.push(current.clone());
+        while current.time < end_time {
+            current = result_or_return!(stepper.step(&current, &mut velocity_field));
+            positions.push(current.clone());
+        }
     }
 
     Ok(Prediction::ValBal(FloatPrediction {
-        positions
+        positions,
+        coverage: source.coverage_report(coverage)
     }))
 }
 
@@ -0,0 +1,211 @@
+use chrono::prelude::*;
+use serde_json;
+
+use predictor::point::*;
+use predictor::coverage::*;
+use predictor::wind_source::*;
+
+const KELVIN : f32 = 273.15;
+
+/*
+ * The atmosphere at one altitude of a profile
+ */
+#[derive(Serialize)]
+pub struct ProfileLevel {
+    pub level: Option<i32>, // isobaric level in hPa, or None for altitudes asked for
+    pub altitude: f32, // m, the geopotential height for isobaric levels
+    pub pressure: f32, // hPa
+    pub temperature: f32, // °C
+
+    pub east: f32, // m/s
+    pub north: f32,
+    pub speed: f32,
+    pub direction: f32, // degrees clockwise from north that the wind blows from
+
+    pub sources: Vec<String> // datasets used, with sounding or telemetry for measured winds
+}
+
+/*
+ * A vertical profile of the atmosphere above a location, in increasing altitude
+ * Altitudes and levels the source has nothing for are left out, and listed in missing and missing_levels
+ */
+#[derive(Serialize)]
+pub struct WindProfile {
+    pub latitude: f32,
+    pub longitude: f32,
+    pub time: String,
+
+    pub levels: Vec<ProfileLevel>,
+    pub missing: Vec<f32>,
+    pub missing_levels: Vec<i32>,
+    pub coverage: CoverageReport
+}
+
+impl WindProfile {
+    pub fn serialize(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/*
+ * Samples a source above a location, at any altitudes asked for and on each isobaric level when pressure_levels is set
+ * Isobaric levels are read as the source stores them, at their own geopotential height rather than interpolated to an altitude
+ */
+pub fn wind_profile<S: WindSource + ?Sized>(source: &S, latitude: f32, longitude: f32, time: DateTime<Utc>, altitudes: &[f32], pressure_levels: bool) -> Result<WindProfile, String> {
+    if let Some(altitude) = altitudes.iter().find(|altitude| !altitude.is_finite()) {
+        return_error!(format!("Invalid altitude {}", altitude));
+    }
+
+    if altitudes.is_empty() && !pressure_levels {
+        return_error!("No levels or altitudes to sample");
+    }
+
+    let at = |altitude: f32| Point {
+        latitude,
+        longitude,
+        altitude,
+        time
+    };
+
+    let mut levels = vec![];
+    let mut missing = vec![];
+    let mut missing_levels = vec![];
+    let mut coverage = Coverage::Covered;
+    let mut last_error = String::new();
+
+    for altitude in altitudes.iter() {
+        let point = at(*altitude);
+
+        match source.atmospheroid_with_coverage_at(&point) {
+            Ok((atmospheroid, point_coverage)) => {
+                coverage = coverage.worst(point_coverage);
+                levels.push(profile_level(None, *altitude, &atmospheroid, source.sources_at(&point)));
+            },
+            Err(why) => {
+                missing.push(*altitude);
+                last_error = why;
+            }
+        }
+    }
+
+    if pressure_levels {
+        for level in LEVELS.iter() {
+            match source.isobaric_level_at(&at(0.0), *level) {
+                Ok((isobaric, point_coverage)) if isobaric.height.is_finite() => {
+                    coverage = coverage.worst(point_coverage);
+                    levels.push(profile_level(Some(*level), isobaric.height, &isobaric.atmospheroid, source.sources_at(&at(isobaric.height))));
+                },
+                Ok(_) => {
+                    missing_levels.push(*level);
+                    last_error = format!("No geopotential height for {} hPa", level);
+                },
+                Err(why) => {
+                    missing_levels.push(*level);
+                    last_error = why;
+                }
+            }
+        }
+    }
+
+    if levels.is_empty() {
+        return Err(last_error);
+    }
+
+    levels.sort_by(|a, b| a.altitude.partial_cmp(&b.altitude).unwrap());
+
+    Ok(WindProfile {
+        latitude,
+        longitude,
+        time: format!("{:?}", time),
+
+        levels,
+        missing,
+        missing_levels,
+        coverage: source.coverage_report(coverage)
+    })
+}
+
+fn profile_level(level: Option<i32>, altitude: f32, atmospheroid: &Atmospheroid, sources: Vec<String>) -> ProfileLevel {
+    let velocity = &atmospheroid.velocity;
+
+    ProfileLevel {
+        level,
+        altitude,
+        pressure: atmospheroid.pressure / 100.0,
+        temperature: atmospheroid.temperature - KELVIN,
+
+        east: velocity.east,
+        north: velocity.north,
+        speed: (velocity.east * velocity.east + velocity.north * velocity.north).sqrt(),
+        direction: wind_direction(velocity),
+
+        sources
+    }
+}
+
+/*
+ * Direction a wind blows from, in degrees clockwise from north
+ */
+fn wind_direction(velocity: &Velocity) -> f32 {
+    let direction = (-velocity.east).atan2(-velocity.north).to_degrees();
+
+    if direction < 0.0 { direction + 360.0 } else { direction }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+     * Levels 100 m above the standard atmosphere, as on a warm day, with a wind that grows with height
+     */
+    struct WarmDay;
+
+    impl WindSource for WarmDay {
+        fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+            Ok(Atmospheroid::from_grib(point.altitude / 1000.0, 0.0, 280.0, point.standard_pressure() * 100.0, 0.0, 0.0))
+        }
+
+        fn isobaric_level_at(&self, _point: &Point, level: i32) -> Result<(IsobaricLevel, Coverage), String> {
+            let height = Point::standard_altitude(level as f32) + 100.0;
+
+            Ok((IsobaricLevel {
+                atmospheroid: Atmospheroid::from_grib(height / 1000.0, 0.0, 280.0, level as f32 * 100.0, 0.0, 0.0),
+                height
+            }, Coverage::Covered))
+        }
+    }
+
+    #[test]
+    fn reads_isobaric_levels_at_their_own_height() {
+        let profile = wind_profile(&WarmDay, 52.0, 0.0, Utc.ymd(2020, 1, 1).and_hms(0, 0, 0), &vec![1000.0], true).unwrap();
+
+        assert_eq!(profile.levels.len(), LEVELS.len() + 1);
+        assert!(profile.missing.is_empty() && profile.missing_levels.is_empty());
+
+        let level = profile.levels.iter().find(|level| level.level == Some(500)).unwrap();
+
+        assert_eq!(level.pressure, 500.0);
+        assert_eq!(level.altitude, Point::standard_altitude(500.0) + 100.0);
+        assert_eq!(level.east, level.altitude / 1000.0);
+
+        assert!(profile.levels.windows(2).all(|pair| pair[0].altitude <= pair[1].altitude));
+    }
+
+    #[test]
+    fn lists_levels_a_source_does_not_have() {
+        let source = AnalyticSource::new(|point: &Point| Atmospheroid::from_grib(1.0, 0.0, 280.0, point.standard_pressure() * 100.0, 0.0, 0.0));
+
+        let profile = wind_profile(&source, 52.0, 0.0, Utc.ymd(2020, 1, 1).and_hms(0, 0, 0), &vec![1000.0, 2000.0], true).unwrap();
+
+        assert_eq!(profile.levels.len(), 2);
+        assert_eq!(profile.missing_levels, LEVELS.to_vec());
+    }
+
+    #[test]
+    fn rejects_non_finite_altitudes() {
+        let source = AnalyticSource::new(|point: &Point| Atmospheroid::from_grib(1.0, 0.0, 280.0, point.standard_pressure() * 100.0, 0.0, 0.0));
+
+        assert!(wind_profile(&source, 52.0, 0.0, Utc.ymd(2020, 1, 1).and_hms(0, 0, 0), &vec![::std::f32::NAN], false).is_err());
+    }
+}
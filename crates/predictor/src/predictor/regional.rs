@@ -0,0 +1,600 @@
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use chrono::prelude::*;
+use memmap::Mmap;
+use serde_json;
+
+use predictor::point::*;
+use predictor::coverage::*;
+use predictor::lambert::LambertConformal;
+use predictor::dataset_reader::DataSetReader;
+use predictor::wind_source::*;
+
+pub const REGIONAL_DIRECTORY : &str = "regional"; // inside the data directory
+pub const MANIFEST_FILE : &str = "manifest.json";
+
+const FLOAT_SIZE : usize = 4;
+const RECORD_SIZE : usize = 3 * FLOAT_SIZE; // u, v, t
+const DEFAULT_BLEND_CELLS : f64 = 10.0;
+
+/*
+ * Describes a preprocessed regional dataset
+ * Each level is stored as L<level>.gribr, holding big endian f32 u, v, t records for every grid point, row by row
+ * Winds are relative to east and north, not the grid
+ */
+#[derive(Serialize, Deserialize)]
+pub struct RegionalManifest {
+    pub name: String,
+    pub created_at: i64, // unix seconds
+    pub time: i64,
+    pub projection: LambertConformal,
+    pub levels: Vec<i32>
+}
+
+impl RegionalManifest {
+    pub fn level_filename(level: i32) -> String {
+        "L".to_string() + level.to_string().as_str() + ".gribr"
+    }
+}
+
+/*
+ * A single forecast hour of a regional model
+ */
+pub struct RegionalDataset {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub time: DateTime<Utc>,
+
+    path: String,
+    modified: Option<SystemTime>,
+
+    projection: LambertConformal,
+    levels: Vec<(i32, Mmap)> // in increasing pressure
+}
+
+impl RegionalDataset {
+
+    pub fn open(directory: &String) -> Result<RegionalDataset, String> {
+        let modified = modified_at(directory);
+
+        let manifest : RegionalManifest = {
+            let mut file = result_or_return_why!(File::open(directory.clone() + "/" + MANIFEST_FILE), "Could not open manifest");
+            let mut contents = String::new();
+            result_or_return_why!(file.read_to_string(&mut contents), "Could not read manifest");
+
+            result_or_return_why!(serde_json::from_str(contents.as_str()), "Could not parse manifest")
+        };
+
+        let expected_length = manifest.projection.nx * manifest.projection.ny * RECORD_SIZE;
+
+        let mut levels = vec![];
+
+        for level in manifest.levels.iter() {
+            let file = result_or_return_why!(File::open(directory.clone() + "/" + RegionalManifest::level_filename(*level).as_str()), "Could not open level");
+            let length = result_or_return_why!(file.metadata(), "Could not read file metadata").len() as usize;
+
+            if length != expected_length {
+                return_error!(format!("Level {} of {} is incomplete", level, manifest.name));
+            }
+
+            levels.push((*level, result_or_return_why!(unsafe { Mmap::map(&file) }, "Could not map level")));
+        }
+
+        if levels.is_empty() {
+            return_error!("Regional dataset has no levels");
+        }
+
+        levels.sort_by_key(|&(level, _)| level);
+
+        Ok(RegionalDataset {
+            name: manifest.name,
+            created_at: Utc.timestamp(manifest.created_at, 0),
+            time: Utc.timestamp(manifest.time, 0),
+            path: directory.clone(),
+            modified,
+            projection: manifest.projection,
+            levels
+        })
+    }
+
+    /*
+     * A dataset held in anonymous memory, with the same u, v and t at every grid point of each level
+     */
+    #[cfg(test)]
+    pub fn in_memory(name: &str, time: DateTime<Utc>, projection: LambertConformal, levels: Vec<(i32, [f32; 3])>) -> RegionalDataset {
+        use memmap::MmapMut;
+
+        let levels = levels.into_iter().map(|(level, record)| {
+            let mut map = MmapMut::map_anon(projection.nx * projection.ny * RECORD_SIZE).unwrap();
+
+            for (offset, byte) in map.iter_mut().enumerate() {
+                let value = record[(offset % RECORD_SIZE) / FLOAT_SIZE].to_bits();
+                *byte = (value >> (24 - 8 * (offset % FLOAT_SIZE))) as u8;
+            }
+
+            (level, map.make_read_only().unwrap())
+        }).collect();
+
+        RegionalDataset {
+            name: name.to_string(),
+            created_at: time,
+            time,
+            path: String::new(),
+            modified: None,
+            projection,
+            levels
+        }
+    }
+
+    /*
+     * Whether the dataset on disk is still the one that was opened
+     * Like global datasets, regional ones are replaced wholesale by the preprocessor
+     */
+    pub fn is_unchanged(&self) -> bool {
+        self.modified.is_some() && modified_at(&self.path) == self.modified
+    }
+
+    /*
+     * How many grid cells a point is inside the edge of the domain, or None if it is outside
+     * Points above the top level or below the bottom one are outside too, so that the global datasets are used there
+     * rather than the nearest regional level
+     */
+    pub fn edge_distance(&self, point: &Point) -> Option<f64> {
+        let pressure = point.standard_pressure();
+
+        if pressure < self.levels[0].0 as f32 || pressure > self.levels[self.levels.len() - 1].0 as f32 {
+            return None;
+        }
+
+        let (i, j) = self.grid_position(point);
+
+        let max_i = (self.projection.nx - 1) as f64;
+        let max_j = (self.projection.ny - 1) as f64;
+
+        if i < 0.0 || j < 0.0 || i > max_i || j > max_j {
+            return None;
+        }
+
+        Some(i.min(j).min(max_i - i).min(max_j - j))
+    }
+
+    /*
+     * Bilinearly interpolates within the grid, and linearly in pressure between the levels either side of the point
+     * Altitude is converted to pressure with the standard atmosphere, as regional levels carry no geopotential height,
+     * so unlike global datasets the level can be off by hundreds of meters on hot or cold days
+     */
+    pub fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+        let (i, j) = self.grid_position(point);
+
+        if self.edge_distance(point).is_none() {
+            return_error!(format!("{} is outside of {}", point, self.name));
+        }
+
+        let pressure = point.standard_pressure();
+
+        // find the levels either side, which edge_distance has checked exist
+        let mut below = self.levels.len() - 1;
+        for index in 0..self.levels.len() {
+            if self.levels[index].0 as f32 >= pressure {
+                below = index;
+                break;
+            }
+        }
+        let above = if below == 0 || self.levels[below].0 as f32 <= pressure { below } else { below - 1 };
+
+        let below_atmospheroid = result_or_return!(self.horizontal_at(below, i, j));
+
+        if above == below {
+            return Ok(below_atmospheroid);
+        }
+
+        let above_atmospheroid = result_or_return!(self.horizontal_at(above, i, j));
+
+        let above_pressure = self.levels[above].0 as f32;
+        let below_pressure = self.levels[below].0 as f32;
+        let percent_above = (below_pressure - pressure) / (below_pressure - above_pressure);
+
+        Ok(below_atmospheroid * (1.0 - percent_above) + &(above_atmospheroid * percent_above))
+    }
+
+    /*
+     * Fractional grid indices of a point, which may be outside of the grid
+     */
+    fn grid_position(&self, point: &Point) -> (f64, f64) {
+        self.projection.to_grid(point.latitude as f64, point.dataset_longitude() as f64)
+    }
+
+    fn horizontal_at(&self, level_index: usize, i: f64, j: f64) -> Result<Atmospheroid, String> {
+        let max_i = self.projection.nx - 1;
+        let max_j = self.projection.ny - 1;
+
+        let i0 = (i.floor() as usize).min(max_i);
+        let j0 = (j.floor() as usize).min(max_j);
+        let i1 = (i0 + 1).min(max_i);
+        let j1 = (j0 + 1).min(max_j);
+
+        let percent_i = (i - i0 as f64) as f32;
+        let percent_j = (j - j0 as f64) as f32;
+
+        let a = result_or_return!(self.atmospheroid_at_grid(level_index, i0, j0));
+        let b = result_or_return!(self.atmospheroid_at_grid(level_index, i1, j0));
+        let c = result_or_return!(self.atmospheroid_at_grid(level_index, i0, j1));
+        let d = result_or_return!(self.atmospheroid_at_grid(level_index, i1, j1));
+
+        Ok(
+            (a * (1.0 - percent_i) + &(b * percent_i)) * (1.0 - percent_j) +
+                &((c * (1.0 - percent_i) + &(d * percent_i)) * percent_j)
+        )
+    }
+
+    fn atmospheroid_at_grid(&self, level_index: usize, i: usize, j: usize) -> Result<Atmospheroid, String> {
+        let (level, ref map) = self.levels[level_index];
+
+        let offset = (j * self.projection.nx + i) * RECORD_SIZE;
+        let bytes = &map[offset..(offset + RECORD_SIZE)];
+
+        let u = bytes_to_f32(&bytes[0..4]);
+        let v = bytes_to_f32(&bytes[4..8]);
+        let temperature = bytes_to_f32(&bytes[8..12]);
+
+        if u.is_nan() || v.is_nan() || temperature.is_nan() {
+            return_error!("Datapoint not found");
+        }
+
+        // north is -v, as it is for global datasets
+        Ok(Atmospheroid::from_grib(u, -v, temperature, (level as f32) * 100.0, 0.0, 0.0))
+    }
+}
+
+/*
+ * Every regional dataset in a directory
+ */
+pub struct RegionalReader {
+    directory: String,
+    datasets: Vec<Arc<RegionalDataset>> // by time, then by run
+}
+
+impl RegionalReader {
+
+    /*
+     * Reads every complete dataset in a directory
+     * A missing directory just means there is no regional model
+     */
+    pub fn new(directory: String) -> RegionalReader {
+        RegionalReader::scan(directory, None)
+    }
+
+    /*
+     * Reads the directory again, sharing the datasets that have not changed rather than mapping them again
+     */
+    pub fn rescan(&self) -> RegionalReader {
+        RegionalReader::scan(self.directory.clone(), Some(self))
+    }
+
+    fn scan(directory: String, previous: Option<&RegionalReader>) -> RegionalReader {
+        let mut datasets = vec![];
+
+        if let Ok(entries) = fs::read_dir(directory.as_str()) {
+            for entry in entries {
+                let path = match entry {
+                    Ok(entry) => entry.path(),
+                    Err(_) => continue
+                };
+
+                let path = match path.to_str() {
+                    Some(path) => path.to_string(),
+                    None => continue
+                };
+
+                // still being written
+                if path.ends_with(".partial") {
+                    continue;
+                }
+
+                let existing = previous.and_then(|previous| {
+                    previous.datasets.iter().find(|dataset| dataset.path == path && dataset.is_unchanged())
+                });
+
+                if let Some(dataset) = existing {
+                    datasets.push(dataset.clone());
+                    continue;
+                }
+
+                if let Ok(dataset) = RegionalDataset::open(&path) {
+                    datasets.push(Arc::new(dataset));
+                }
+            }
+        }
+
+        datasets.sort_by_key(|dataset| (dataset.time, dataset.created_at));
+
+        RegionalReader {
+            directory,
+            datasets
+        }
+    }
+
+    /*
+     * Only the runs that had started by a given time
+     */
+    pub fn as_of(&self, as_of: DateTime<Utc>) -> RegionalReader {
+        RegionalReader {
+            directory: self.directory.clone(),
+            datasets: self.datasets.iter().filter(|dataset| dataset.created_at <= as_of).cloned().collect()
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.datasets.iter().map(|dataset| dataset.name.clone()).collect()
+    }
+
+    /*
+     * The datasets either side of a time, and how far between them it is
+     * None if the time is outside of the regional model's range
+     */
+    fn bracket(&self, time: DateTime<Utc>) -> Option<(&RegionalDataset, &RegionalDataset, f32)> {
+        // datasets are sorted by run within each time, so the last one at or before the time is the newest run
+        let before = match self.datasets.iter().rev().find(|dataset| dataset.time <= time) {
+            Some(dataset) => &**dataset,
+            None => {
+                return None;
+            }
+        };
+
+        if before.time == time {
+            return Some((before, before, 0.0));
+        }
+
+        let first_after = match self.datasets.iter().find(|dataset| dataset.time > time) {
+            Some(dataset) => &**dataset,
+            None => {
+                return None;
+            }
+        };
+        let after = match self.datasets.iter().rev().find(|dataset| dataset.time == first_after.time) {
+            Some(dataset) => &**dataset,
+            None => {
+                return None;
+            }
+        };
+
+        let span = after.time.signed_duration_since(before.time).num_seconds() as f32;
+        let percent_after = time.signed_duration_since(before.time).num_seconds() as f32 / span;
+
+        Some((before, after, percent_after))
+    }
+
+    /*
+     * How many grid cells a point is inside the domain at both of the bracketing times
+     */
+    pub fn edge_distance(&self, point: &Point) -> Option<f64> {
+        let (before, after, _) = match self.bracket(point.time) {
+            Some(bracket) => bracket,
+            None => {
+                return None;
+            }
+        };
+
+        match (before.edge_distance(point), after.edge_distance(point)) {
+            (Some(before), Some(after)) => Some(before.min(after)),
+            _ => None
+        }
+    }
+}
+
+impl WindSource for RegionalReader {
+    fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+        let (before, after, percent_after) = some_or_return_why!(self.bracket(point.time), "Outside of the regional model's time range");
+
+        let before_atmospheroid = result_or_return!(before.atmospheroid_at(point));
+
+        if percent_after == 0.0 {
+            return Ok(before_atmospheroid);
+        }
+
+        let after_atmospheroid = result_or_return!(after.atmospheroid_at(point));
+
+        Ok(before_atmospheroid * (1.0 - percent_after) + &(after_atmospheroid * percent_after))
+    }
+
+    fn sources_at(&self, point: &Point) -> Vec<String> {
+        match self.bracket(point.time) {
+            Some((before, _, 0.0)) => vec![before.name.clone()],
+            Some((before, after, _)) => vec![before.name.clone(), after.name.clone()],
+            None => vec![]
+        }
+    }
+}
+
+/*
+ * Uses the regional model inside its domain and time range, and the global datasets everywhere else
+ * Within blend_cells of the edge of the domain, the two are blended linearly so that there is no jump in the winds
+ */
+pub struct NestedSource {
+    regional: Arc<RegionalReader>,
+    global: Arc<DataSetReader>,
+    blend_cells: f64
+}
+
+impl NestedSource {
+
+    pub fn new(regional: Arc<RegionalReader>, global: Arc<DataSetReader>) -> NestedSource {
+        NestedSource {
+            regional,
+            global,
+            blend_cells: DEFAULT_BLEND_CELLS
+        }
+    }
+
+    pub fn with_blend_cells(self, blend_cells: f64) -> NestedSource {
+        NestedSource {
+            blend_cells,
+            ..self
+        }
+    }
+
+    /*
+     * How much of the regional model to use at a point, from 0 to 1
+     */
+    fn regional_weight(&self, point: &Point) -> f32 {
+        match self.regional.edge_distance(point) {
+            Some(distance) if self.blend_cells <= 0.0 || distance >= self.blend_cells => 1.0,
+            Some(distance) => (distance / self.blend_cells) as f32,
+            None => 0.0
+        }
+    }
+}
+
+impl WindSource for NestedSource {
+    fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+        let (atmospheroid, _) = result_or_return!(self.atmospheroid_with_coverage_at(point));
+
+        Ok(atmospheroid)
+    }
+
+    fn atmospheroid_with_coverage_at(&self, point: &Point) -> Result<(Atmospheroid, Coverage), String> {
+        let weight = self.regional_weight(point);
+
+        if weight <= 0.0 {
+            return self.global.atmospheroid_with_coverage_at(point);
+        }
+
+        let regional = result_or_return!(self.regional.atmospheroid_at(point));
+
+        if weight >= 1.0 {
+            return Ok((regional, Coverage::Covered));
+        }
+
+        let (global, coverage) = result_or_return!(self.global.atmospheroid_with_coverage_at(point));
+
+        Ok((regional * weight + &(global * (1.0 - weight)), coverage))
+    }
+
+    fn coverage_report(&self, coverage: Coverage) -> CoverageReport {
+        self.global.coverage_report(coverage)
+    }
+
+    /*
+     * Regional levels carry no geopotential height, so isobaric levels always come from the global datasets
+     */
+    fn isobaric_level_at(&self, point: &Point, level: i32) -> Result<(IsobaricLevel, Coverage), String> {
+        self.global.isobaric_level_at(point, level)
+    }
+
+    fn sources_at(&self, point: &Point) -> Vec<String> {
+        let weight = self.regional_weight(point);
+
+        let mut sources = vec![];
+
+        if weight > 0.0 {
+            sources.extend(self.regional.sources_at(point));
+        }
+
+        if weight < 1.0 {
+            sources.extend(self.global.sources_at(point));
+        }
+
+        sources
+    }
+}
+
+fn modified_at(path: &String) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn bytes_to_f32(bytes : &[u8]) -> f32 {
+    let bits = ((bytes[0] as u32) << 24) | ((bytes[1] as u32) << 16) | ((bytes[2] as u32) << 8) | (bytes[3] as u32);
+
+    f32::from_bits(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use predictor::coverage::CoverageConfig;
+
+    /*
+     * The south west corner of the HRRR grid, 41 points across
+     */
+    fn projection() -> LambertConformal {
+        LambertConformal {
+            nx: 41,
+            ny: 41,
+            la1: 21.138123,
+            lo1: 237.280472,
+            lov: 262.5,
+            latin1: 38.5,
+            latin2: 38.5,
+            dx: 3000.0,
+            dy: 3000.0
+        }
+    }
+
+    fn time() -> DateTime<Utc> {
+        Utc.ymd(2024, 1, 1).and_hms(6, 0, 0)
+    }
+
+    /*
+     * A point at grid indices, in the longitude convention points use
+     */
+    fn point_at(i: f64, j: f64, altitude: f32) -> Point {
+        let (latitude, longitude) = projection().from_grid(i, j);
+
+        Point {
+            latitude: latitude as f32,
+            longitude: bound(longitude as f32 - 180.0),
+            altitude,
+            time: time()
+        }
+    }
+
+    fn nested() -> NestedSource {
+        let dataset = RegionalDataset::in_memory("hrrr_20240101_0600_000", time(), projection(), vec![(500, [10.0, -5.0, 250.0]), (850, [20.0, -10.0, 270.0])]);
+
+        let regional = RegionalReader {
+            directory: String::new(),
+            datasets: vec![Arc::new(dataset)]
+        };
+
+        NestedSource::new(Arc::new(regional), Arc::new(DataSetReader::in_memory(vec![], CoverageConfig::default()))).with_blend_cells(10.0)
+    }
+
+    #[test]
+    fn looks_up_points_in_the_dataset_longitude_convention() {
+        let source = nested();
+        let point = point_at(20.0, 20.0, Point::standard_altitude(850.0));
+
+        assert!((source.regional.edge_distance(&point).unwrap() - 20.0).abs() < 1e-2);
+
+        let atmospheroid = source.regional.atmospheroid_at(&point).unwrap();
+
+        // u and v as stored, with north being -v
+        assert!((atmospheroid.velocity.east - 20.0).abs() < 1e-2);
+        assert!((atmospheroid.velocity.north - 10.0).abs() < 1e-2);
+        assert_eq!(source.regional.sources_at(&point), vec!["hrrr_20240101_0600_000"]);
+    }
+
+    #[test]
+    fn blends_towards_the_global_datasets_at_the_edge() {
+        let source = nested();
+        let altitude = Point::standard_altitude(700.0);
+
+        assert_eq!(source.regional_weight(&point_at(20.0, 20.0, altitude)), 1.0);
+        assert!((source.regional_weight(&point_at(5.0, 20.0, altitude)) - 0.5).abs() < 1e-2);
+        assert!((source.regional_weight(&point_at(20.0, 38.0, altitude)) - 0.2).abs() < 1e-2);
+        assert_eq!(source.regional_weight(&point_at(-3.0, 20.0, altitude)), 0.0);
+
+        // above and below the regional levels, and outside of its time range
+        assert_eq!(source.regional_weight(&point_at(20.0, 20.0, Point::standard_altitude(400.0))), 0.0);
+        assert_eq!(source.regional_weight(&point_at(20.0, 20.0, Point::standard_altitude(900.0))), 0.0);
+        assert_eq!(source.regional_weight(&Point { time: time() + ::chrono::Duration::hours(1), ..point_at(20.0, 20.0, altitude) }), 0.0);
+
+        // the unshifted longitude is half the world away
+        let point = point_at(20.0, 20.0, altitude);
+        assert_eq!(source.regional_weight(&Point { longitude: bound(point.longitude + 180.0), ..point }), 0.0);
+    }
+}
@@ -0,0 +1,537 @@
+use std::fs::File;
+use std::io::Read;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use chrono::LocalResult;
+
+use predictor::point::*;
+use predictor::coverage::*;
+use predictor::wind_source::*;
+
+const KELVIN : f32 = 273.15;
+const IGRA_MISSING : [i32; 2] = [-9999, -8888];
+
+const DEFAULT_FULL_RADIUS : f32 = 25_000.0; // m
+const DEFAULT_BLEND_DISTANCE : f32 = 75_000.0; // m
+const DEFAULT_MAX_AGE_HOURS : i64 = 6;
+
+pub const SOUNDING_SOURCE : &str = "sounding"; // what a sounding is called in the sources used
+
+/*
+ * A single level of a sounding
+ */
+#[derive(Clone, Debug)]
+pub struct SoundingLevel {
+    pub altitude: f32, // m
+    pub pressure: f32, // Pa
+    pub temperature: Temperature, // K
+    pub east: f32, // m/s
+    pub north: f32
+}
+
+/*
+ * A vertical profile of wind and temperature, such as from a radiosonde
+ * The profile is taken to be the same everywhere; BlendedSource limits it to near where it was measured
+ */
+#[derive(Clone, Debug)]
+pub struct Sounding {
+    pub levels: Vec<SoundingLevel>, // in increasing altitude
+
+    // where and when the sounding was made, when the file says
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+    pub time: Option<DateTime<Utc>>
+}
+
+impl Sounding {
+
+    /*
+     * Reads an IGRA sounding, or a CSV of altitude, speed, direction and temperature
+     * IGRA files start with a # header record, and only their first sounding is read
+     */
+    pub fn open(path: &String) -> Result<Sounding, String> {
+        let mut contents = String::new();
+
+        {
+            let mut file = result_or_return_why!(File::open(path), "Could not open sounding");
+            result_or_return_why!(file.read_to_string(&mut contents), "Could not read sounding");
+        }
+
+        if is_igra(&contents) {
+            Sounding::parse_igra(&contents)
+        } else {
+            Sounding::parse_csv(&contents)
+        }
+    }
+
+    /*
+     * altitude (m), speed (m/s), direction the wind blows from (degrees), temperature (°C)
+     * Blank lines, comments starting with # and a header row are skipped
+     */
+    pub fn parse_csv(contents: &str) -> Result<Sounding, String> {
+        let mut levels = vec![];
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields : Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+
+            if fields.len() < 4 {
+                return_error!(format!("Expected 4 columns on line {} of sounding", index + 1));
+            }
+
+            let values : Vec<Result<f32, _>> = fields[0..4].iter().map(|field| field.parse::<f32>()).collect();
+
+            match (&values[0], &values[1], &values[2], &values[3]) {
+                (&Ok(altitude), &Ok(speed), &Ok(direction), &Ok(temperature)) => {
+                    if ![altitude, speed, direction, temperature].iter().all(|value| value.is_finite()) {
+                        return_error!(format!("Invalid number on line {} of sounding", index + 1));
+                    }
+
+                    let (east, north) = wind_components(speed, direction);
+
+                    levels.push(SoundingLevel {
+                        altitude,
+                        pressure: standard_pressure_at(altitude),
+                        temperature: temperature + KELVIN,
+                        east,
+                        north
+                    });
+                },
+                _ => {
+                    // a header row
+                    if levels.is_empty() && values[0].is_err() {
+                        continue;
+                    }
+
+                    return_error!(format!("Invalid number on line {} of sounding", index + 1));
+                }
+            }
+        }
+
+        Sounding::new(levels, None, None, None)
+    }
+
+    /*
+     * The first sounding in an IGRA version 2 data file
+     * Levels without a height, temperature or wind are skipped
+     */
+    pub fn parse_igra(contents: &str) -> Result<Sounding, String> {
+        let mut lines = contents.lines();
+
+        let header = some_or_return_why!(lines.next(), "Empty sounding");
+
+        let year = result_or_return!(igra_field(header, 13, 17));
+        let month = result_or_return!(igra_field(header, 18, 20));
+        let day = result_or_return!(igra_field(header, 21, 23));
+        let hour = result_or_return!(igra_field(header, 24, 26));
+        let latitude = result_or_return!(igra_field(header, 55, 62)) as f32 / 10_000.0;
+        let longitude = result_or_return!(igra_field(header, 63, 71)) as f32 / 10_000.0;
+
+        let time = match Utc.ymd_opt(year, month as u32, day as u32) {
+            LocalResult::Single(date) if (0..24).contains(&hour) => Some(date.and_hms(hour as u32, 0, 0)),
+            _ => None // the hour is 99 when it is not known
+        };
+
+        let mut levels = vec![];
+
+        for line in lines {
+            if line.starts_with('#') {
+                break;
+            }
+
+            if line.len() < 51 {
+                continue;
+            }
+
+            let pressure = result_or_return!(igra_field(line, 9, 15));
+            let height = result_or_return!(igra_field(line, 16, 21));
+            let temperature = result_or_return!(igra_field(line, 22, 27));
+            let direction = result_or_return!(igra_field(line, 40, 45));
+            let speed = result_or_return!(igra_field(line, 46, 51));
+
+            if [height, temperature, direction, speed].iter().any(|value| IGRA_MISSING.contains(value)) {
+                continue;
+            }
+
+            let altitude = height as f32;
+            let (east, north) = wind_components(speed as f32 / 10.0, direction as f32);
+
+            levels.push(SoundingLevel {
+                altitude,
+                pressure: if IGRA_MISSING.contains(&pressure) { standard_pressure_at(altitude) } else { pressure as f32 },
+                temperature: temperature as f32 / 10.0 + KELVIN,
+                east,
+                north
+            });
+        }
+
+        Sounding::new(levels, Some(latitude), Some(longitude), time)
+    }
+
+    fn new(mut levels: Vec<SoundingLevel>, latitude: Option<f32>, longitude: Option<f32>, time: Option<DateTime<Utc>>) -> Result<Sounding, String> {
+        if levels.len() < 2 {
+            return_error!("Sounding needs at least 2 levels with wind and temperature");
+        }
+
+        levels.sort_by(|a, b| a.altitude.partial_cmp(&b.altitude).unwrap());
+
+        Ok(Sounding {
+            levels,
+            latitude,
+            longitude,
+            time
+        })
+    }
+
+    /*
+     * The lowest and highest altitudes measured
+     */
+    pub fn altitude_range(&self) -> (f32, f32) {
+        (self.levels[0].altitude, self.levels[self.levels.len() - 1].altitude)
+    }
+}
+
+impl WindSource for Sounding {
+
+    /*
+     * Linearly interpolates between the levels either side of the point
+     * Outside of the altitudes measured there is nothing to go on, so it fails rather than extrapolating
+     */
+    fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+        let (bottom, top) = self.altitude_range();
+
+        if point.altitude < bottom || point.altitude > top {
+            return_error!(format!("{}m is outside of the sounding", point.altitude));
+        }
+
+        let above = some_or_return_why!(self.levels.iter().position(|level| level.altitude >= point.altitude), "Altitude not found in sounding");
+        let below = if above == 0 { 0 } else { above - 1 };
+
+        let lower = &self.levels[below];
+        let upper = &self.levels[above];
+
+        let percent_up = if upper.altitude > lower.altitude {
+            (point.altitude - lower.altitude) / (upper.altitude - lower.altitude)
+        } else {
+            0.0
+        };
+
+        let interpolate = |lower: f32, upper: f32| lower + (upper - lower) * percent_up;
+
+        Ok(Atmospheroid::from_grib(
+            interpolate(lower.east, upper.east),
+            interpolate(lower.north, upper.north),
+            interpolate(lower.temperature, upper.temperature),
+            interpolate(lower.pressure, upper.pressure),
+            0.0,
+            0.0
+        ))
+    }
+
+    fn sources_at(&self, _point: &Point) -> Vec<String> {
+        vec![SOUNDING_SOURCE.to_string()]
+    }
+}
+
+/*
+ * Uses a sounding near where and when it was made, and another source everywhere else
+ * The sounding is used fully within full_radius of its site, fading out linearly over blend_distance beyond that,
+ * and it also fades out linearly over max_age after it was made
+ */
+pub struct BlendedSource<'a, S: WindSource + ?Sized + 'a> {
+    sounding: Sounding,
+    source: &'a S,
+
+    latitude: f32,
+    longitude: f32,
+    time: DateTime<Utc>,
+
+    full_radius: f32, // m
+    blend_distance: f32, // m
+    max_age: Duration
+}
+
+impl<'a, S: WindSource + ?Sized + 'a> BlendedSource<'a, S> {
+
+    /*
+     * Soundings that do not say where or when they were made are taken to be from the launch
+     */
+    pub fn new(sounding: Sounding, source: &'a S, launch: &Point) -> BlendedSource<'a, S> {
+        BlendedSource {
+            latitude: sounding.latitude.unwrap_or(launch.latitude),
+            longitude: sounding.longitude.unwrap_or(launch.longitude),
+            time: sounding.time.unwrap_or(launch.time),
+
+            sounding,
+            source,
+
+            full_radius: DEFAULT_FULL_RADIUS,
+            blend_distance: DEFAULT_BLEND_DISTANCE,
+            max_age: Duration::hours(DEFAULT_MAX_AGE_HOURS)
+        }
+    }
+
+    pub fn with_distances(self, full_radius: f32, blend_distance: f32) -> BlendedSource<'a, S> {
+        BlendedSource {
+            full_radius,
+            blend_distance,
+            ..self
+        }
+    }
+
+    pub fn with_max_age(self, max_age: Duration) -> BlendedSource<'a, S> {
+        BlendedSource {
+            max_age,
+            ..self
+        }
+    }
+
+    /*
+     * How much of the sounding to use at a point, from 0 to 1
+     */
+    fn sounding_weight(&self, point: &Point) -> f32 {
+        let (bottom, top) = self.sounding.altitude_range();
+
+        if point.altitude < bottom || point.altitude > top {
+            return 0.0;
+        }
+
+        let site = Point {
+            latitude: self.latitude,
+            longitude: self.longitude,
+            altitude: point.altitude,
+            time: self.time
+        };
+
+        let distance = point.distance_to(&site);
+
+        let distance_weight = if distance <= self.full_radius {
+            1.0
+        } else if self.blend_distance <= 0.0 {
+            0.0
+        } else {
+            (1.0 - (distance - self.full_radius) / self.blend_distance).max(0.0)
+        };
+
+        let age = point.time.signed_duration_since(self.time).num_seconds().abs() as f32;
+        let max_age = self.max_age.num_seconds() as f32;
+
+        let age_weight = if max_age <= 0.0 { 0.0 } else { (1.0 - age / max_age).max(0.0) };
+
+        distance_weight * age_weight
+    }
+}
+
+impl<'a, S: WindSource + ?Sized + 'a> WindSource for BlendedSource<'a, S> {
+    fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+        let (atmospheroid, _) = result_or_return!(self.atmospheroid_with_coverage_at(point));
+
+        Ok(atmospheroid)
+    }
+
+    fn atmospheroid_with_coverage_at(&self, point: &Point) -> Result<(Atmospheroid, Coverage), String> {
+        let weight = self.sounding_weight(point);
+
+        if weight <= 0.0 {
+            return self.source.atmospheroid_with_coverage_at(point);
+        }
+
+        let measured = result_or_return!(self.sounding.atmospheroid_at(point));
+
+        if weight >= 1.0 {
+            return Ok((measured, Coverage::Covered));
+        }
+
+        let (forecast, coverage) = result_or_return!(self.source.atmospheroid_with_coverage_at(point));
+
+        Ok((measured * weight + &(forecast * (1.0 - weight)), coverage))
+    }
+
+    fn coverage_report(&self, coverage: Coverage) -> CoverageReport {
+        self.source.coverage_report(coverage)
+    }
+
+    fn sources_at(&self, point: &Point) -> Vec<String> {
+        let weight = self.sounding_weight(point);
+
+        let mut sources = vec![];
+
+        if weight > 0.0 {
+            sources.extend(self.sounding.sources_at(point));
+        }
+
+        if weight < 1.0 {
+            sources.extend(self.source.sources_at(point));
+        }
+
+        sources
+    }
+}
+
+/*
+ * East and north components of a wind blowing from a direction, in degrees clockwise from north
+ */
+fn wind_components(speed: f32, direction: f32) -> (f32, f32) {
+    let radians = direction.to_radians();
+
+    (-speed * radians.sin(), -speed * radians.cos())
+}
+
+/*
+ * Pressure of the standard atmosphere at an altitude, in Pa
+ */
+fn standard_pressure_at(altitude: f32) -> f32 {
+    Point {
+        latitude: 0.0,
+        longitude: 0.0,
+        altitude,
+        time: Utc::now()
+    }.standard_pressure() * 100.0
+}
+
+/*
+ * Whether a file starts with an IGRA header record, which has the year in columns 14 to 17
+ * CSV comments can be anything, so the columns are only looked at if they are there
+ */
+fn is_igra(contents: &str) -> bool {
+    match contents.lines().next() {
+        Some(line) => line.len() >= 71 && line.starts_with('#') && line.get(13..17).is_some_and(|year| year.chars().all(|character| character.is_ascii_digit())),
+        None => false
+    }
+}
+
+/*
+ * A fixed width integer field of an IGRA record, where start and end are 0 based and end is exclusive
+ */
+fn igra_field(line: &str, start: usize, end: usize) -> Result<i32, String> {
+    let field = some_or_return_why!(line.get(start..end), "IGRA record is too short");
+
+    field.trim().parse::<i32>().map_err(|_| format!("Invalid IGRA field {}", field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_csv_in_altitude_order() {
+        let sounding = Sounding::parse_csv("altitude,speed,direction,temperature\n1000,10,270,5\n0,5,180,15\n").unwrap();
+
+        assert_eq!(sounding.altitude_range(), (0.0, 1000.0));
+    }
+
+    #[test]
+    fn rejects_non_finite_values() {
+        for contents in &["0,5,180,15\nnan,10,270,5\n", "0,5,180,15\n1000,inf,270,5\n", "0,NaN,180,15\n1000,10,270,5\n"] {
+            assert!(Sounding::parse_csv(contents).is_err(), "{:?}", contents);
+        }
+    }
+
+    /*
+     * An IGRA data record, with the relative humidity and dewpoint depression missing
+     */
+    fn igra_level(pressure: i32, height: i32, temperature: i32, direction: i32, speed: i32) -> String {
+        format!("21 -9999{:>7}B{:>5}B{:>5}B-9999 -9999 {:>5} {:>5}", pressure, height, temperature, direction, speed)
+    }
+
+    fn igra_header(year: i32, hour: i32) -> String {
+        format!("#USM00072469 {} 01 01 {:02} 2315    3 ncdc-gts ncdc-gts  397500 -1048667", year, hour)
+    }
+
+    #[test]
+    fn parses_the_first_igra_sounding() {
+        let contents = [
+            igra_header(2024, 0),
+            igra_level(85000, 1600, -50, 270, 100),
+            igra_level(77000, 2300, -9999, 225, 80),
+            igra_level(70000, 3000, -150, 180, 50),
+            igra_header(2024, 12),
+            igra_level(85000, 1600, 0, 0, 0),
+            igra_level(70000, 3000, 0, 0, 0)
+        ].join("\n");
+
+        assert!(is_igra(&contents));
+
+        let sounding = Sounding::parse_igra(&contents).unwrap();
+
+        assert_eq!(sounding.latitude, Some(39.75));
+        assert!((sounding.longitude.unwrap() + 104.8667).abs() < 1e-4);
+        assert_eq!(sounding.time, Some(Utc.ymd(2024, 1, 1).and_hms(0, 0, 0)));
+
+        // the level without a temperature is skipped
+        assert_eq!(sounding.levels.len(), 2);
+        assert_eq!(sounding.altitude_range(), (1600.0, 3000.0));
+
+        let bottom = &sounding.levels[0];
+        assert_eq!(bottom.pressure, 85000.0);
+        assert!((bottom.temperature - 268.15).abs() < 1e-3);
+        assert!((bottom.east - 10.0).abs() < 1e-3 && bottom.north.abs() < 1e-3);
+
+        // an unknown hour leaves the time for the launch to fill in
+        let unknown_hour = [igra_header(2024, 99), igra_level(85000, 1600, -50, 270, 100), igra_level(70000, 3000, -150, 180, 50)].join("\n");
+        assert_eq!(Sounding::parse_igra(&unknown_hour).unwrap().time, None);
+    }
+
+    #[test]
+    fn does_not_take_csv_comments_for_igra_headers() {
+        // a multibyte character runs across the end of where the year would be
+        assert!(!is_igra("# Vent à 12 h — température, vitesse et direction du vent relevées au sol\n0,5,180,15\n"));
+        assert!(!is_igra("# altitude, speed, direction and temperature from the morning sounding\n0,5,180,15\n"));
+        assert!(!is_igra(""));
+    }
+
+    #[test]
+    fn resolves_winds_from_the_direction_they_blow_from() {
+        let close = |(east, north): (f32, f32), expected: (f32, f32)| (east - expected.0).abs() < 1e-4 && (north - expected.1).abs() < 1e-4;
+
+        assert!(close(wind_components(10.0, 0.0), (0.0, -10.0)));
+        assert!(close(wind_components(10.0, 90.0), (-10.0, 0.0)));
+        assert!(close(wind_components(10.0, 180.0), (0.0, 10.0)));
+        assert!(close(wind_components(10.0, 270.0), (10.0, 0.0)));
+        assert!(close(wind_components(10.0, 360.0), (0.0, -10.0)));
+    }
+
+    #[test]
+    fn weights_the_sounding_by_distance_and_age() {
+        let sounding = Sounding::parse_csv("0,10,270,15\n5000,10,270,-15\n").unwrap();
+        let forecast = AnalyticSource::new(|point: &Point| Atmospheroid::from_grib(0.0, 0.0, 250.0, point.standard_pressure() * 100.0, 0.0, 0.0));
+
+        let launch = Point {
+            latitude: 40.0,
+            longitude: -105.0,
+            altitude: 1000.0,
+            time: Utc.ymd(2024, 1, 1).and_hms(12, 0, 0)
+        };
+
+        let blended = BlendedSource::new(sounding, &forecast, &launch).with_distances(25_000.0, 75_000.0).with_max_age(Duration::hours(6));
+
+        // degrees of latitude for a distance north of the launch
+        let north_by = |meters: f32| Point { latitude: launch.latitude + (meters / 6_371_000.0).to_degrees(), ..launch.clone() };
+        let later_by = |hours: i64| Point { time: launch.time + Duration::hours(hours), ..launch.clone() };
+
+        assert_eq!(blended.sounding_weight(&launch), 1.0);
+        assert_eq!(blended.sounding_weight(&north_by(20_000.0)), 1.0);
+        assert!((blended.sounding_weight(&north_by(62_500.0)) - 0.5).abs() < 0.01);
+        assert_eq!(blended.sounding_weight(&north_by(200_000.0)), 0.0);
+
+        assert!((blended.sounding_weight(&later_by(3)) - 0.5).abs() < 1e-3);
+        assert!((blended.sounding_weight(&later_by(-3)) - 0.5).abs() < 1e-3);
+        assert_eq!(blended.sounding_weight(&later_by(7)), 0.0);
+
+        // both at once, and above the top of the sounding
+        let point = Point { time: launch.time + Duration::hours(3), ..north_by(62_500.0) };
+        assert!((blended.sounding_weight(&point) - 0.25).abs() < 0.01);
+        assert_eq!(blended.sounding_weight(&Point { altitude: 6000.0, ..launch.clone() }), 0.0);
+
+        // half of the sounding's 10 m/s west wind
+        let velocity = blended.velocity_at(&later_by(3)).unwrap();
+        assert!((velocity.east - 5.0).abs() < 1e-2, "{}", velocity.east);
+        assert_eq!(blended.sources_at(&later_by(3)), vec![SOUNDING_SOURCE.to_string()]);
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::BTreeMap;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use chrono::LocalResult;
+use serde_json;
+
+use predictor::point::*;
+use predictor::coverage::*;
+use predictor::wind_source::*;
+
+const DEFAULT_BAND_HEIGHT : f32 = 500.0; // m
+const DEFAULT_DECAY_HOURS : i64 = 2;
+const MAX_FIX_INTERVAL : i64 = 600; // seconds, longer gaps are not a straight line
+
+pub const TELEMETRY_SOURCE : &str = "telemetry"; // what the correction is called in the sources used
+
+/*
+ * A GPS fix from a flight, with the time in UNIX seconds
+ */
+#[derive(Deserialize)]
+struct TelemetryFix {
+    latitude: f32,
+    longitude: f32,
+    altitude: f32,
+    time: i64
+}
+
+/*
+ * The model's wind error over an altitude band, observed minus forecast
+ */
+#[derive(Clone, Debug)]
+pub struct WindResidual {
+    pub east: f32, // m/s
+    pub north: f32,
+    pub observed_at: DateTime<Utc>, // the latest fix in the band
+    pub seconds: f32 // of flight the residual is averaged over
+}
+
+/*
+ * Parses a JSON array of fixes like {"latitude": 37.4, "longitude": -122.2, "altitude": 1200, "time": 1530000000}
+ * An empty string is an empty track
+ */
+pub fn parse_track(json: &str) -> Result<Vec<Point>, String> {
+    if json.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let fixes : Vec<TelemetryFix> = result_or_return_why!(serde_json::from_str(json), "Invalid telemetry");
+
+    let mut track = vec![];
+
+    for fix in fixes {
+        let time = match Utc.timestamp_opt(fix.time, 0) {
+            LocalResult::Single(time) => time,
+            _ => {
+                return_error!(format!("Invalid telemetry time {}", fix.time));
+            }
+        };
+
+        track.push(Point {
+            latitude: fix.latitude,
+            longitude: fix.longitude,
+            altitude: fix.altitude,
+            time
+        });
+    }
+
+    track.sort_by_key(|point| point.time);
+
+    Ok(track)
+}
+
+/*
+ * Corrects another source's winds with what a flight has measured so far
+ * The drift between consecutive fixes is compared with the forecast wind at their midpoint, and the differences are averaged
+ * in altitude bands. Within the bands measured the average is added to the forecast, interpolated between band centers,
+ * and it decays exponentially with time since it was observed
+ */
+pub struct CorrectedSource<'a, S: WindSource + ?Sized + 'a> {
+    source: &'a S,
+    track: Vec<Point>,
+
+    band_height: f32, // m
+    decay: Duration, // how long it takes the correction to fall to 1/e
+
+    residuals: BTreeMap<i32, WindResidual> // by band, where band n starts at n * band_height
+}
+
+impl<'a, S: WindSource + ?Sized + 'a> CorrectedSource<'a, S> {
+    pub fn new(source: &'a S, track: Vec<Point>) -> CorrectedSource<'a, S> {
+        let residuals = band_residuals(source, &track, DEFAULT_BAND_HEIGHT);
+
+        CorrectedSource {
+            source,
+            track,
+
+            band_height: DEFAULT_BAND_HEIGHT,
+            decay: Duration::hours(DEFAULT_DECAY_HOURS),
+
+            residuals
+        }
+    }
+
+    pub fn with_band_height(self, band_height: f32) -> CorrectedSource<'a, S> {
+        let residuals = band_residuals(self.source, &self.track, band_height);
+
+        CorrectedSource {
+            band_height,
+            residuals,
+            ..self
+        }
+    }
+
+    pub fn with_decay(self, decay: Duration) -> CorrectedSource<'a, S> {
+        CorrectedSource {
+            decay,
+            ..self
+        }
+    }
+
+    /*
+     * The residual of each band measured, with the altitude of the bottom of the band
+     */
+    pub fn residuals(&self) -> Vec<(f32, WindResidual)> {
+        self.residuals.iter().map(|(band, residual)| (*band as f32 * self.band_height, residual.clone())).collect()
+    }
+
+    /*
+     * The east and north correction at a point, none outside of the bands measured
+     */
+    fn correction_at(&self, point: &Point) -> Option<(f32, f32)> {
+        let center = |band: i32| (band as f32 + 0.5) * self.band_height;
+
+        let band = (point.altitude / self.band_height).floor() as i32;
+
+        let below = self.residuals.range(..(band + 1)).rev().find(|&(band, _)| center(*band) <= point.altitude);
+        let above = self.residuals.range(band..).find(|&(band, _)| center(*band) >= point.altitude);
+
+        let (east, north, observed_at) = match (below, above, self.residuals.get(&band)) {
+            (Some((lower_band, lower)), Some((upper_band, upper)), _) => {
+                let percent_up = if upper_band > lower_band {
+                    (point.altitude - center(*lower_band)) / (center(*upper_band) - center(*lower_band))
+                } else {
+                    0.0
+                };
+
+                (
+                    lower.east + (upper.east - lower.east) * percent_up,
+                    lower.north + (upper.north - lower.north) * percent_up,
+                    lower.observed_at.max(upper.observed_at)
+                )
+            },
+            // the half band beyond the outermost centers
+            (_, _, Some(residual)) => (residual.east, residual.north, residual.observed_at),
+            _ => {
+                return None;
+            }
+        };
+
+        let age = point.time.signed_duration_since(observed_at).num_seconds().max(0) as f32;
+        let decay = self.decay.num_seconds() as f32;
+
+        let weight = if decay <= 0.0 { 0.0 } else { (-age / decay).exp() };
+
+        Some((east * weight, north * weight))
+    }
+}
+
+impl<'a, S: WindSource + ?Sized + 'a> WindSource for CorrectedSource<'a, S> {
+    fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+        let (atmospheroid, _) = result_or_return!(self.atmospheroid_with_coverage_at(point));
+
+        Ok(atmospheroid)
+    }
+
+    fn atmospheroid_with_coverage_at(&self, point: &Point) -> Result<(Atmospheroid, Coverage), String> {
+        let (mut atmospheroid, coverage) = result_or_return!(self.source.atmospheroid_with_coverage_at(point));
+
+        if let Some((east, north)) = self.correction_at(point) {
+            atmospheroid.velocity.east += east;
+            atmospheroid.velocity.north += north;
+        }
+
+        Ok((atmospheroid, coverage))
+    }
+
+    fn coverage_report(&self, coverage: Coverage) -> CoverageReport {
+        self.source.coverage_report(coverage)
+    }
+
+    fn sources_at(&self, point: &Point) -> Vec<String> {
+        let mut sources = self.source.sources_at(point);
+
+        if self.correction_at(point).is_some() {
+            sources.push(TELEMETRY_SOURCE.to_string());
+        }
+
+        sources
+    }
+}
+
+/*
+ * Averages observed minus forecast wind in each band, weighted by the time between fixes
+ * Pairs of fixes the source has no wind for are skipped
+ */
+fn band_residuals<S: WindSource + ?Sized>(source: &S, track: &[Point], band_height: f32) -> BTreeMap<i32, WindResidual> {
+    let mut residuals : BTreeMap<i32, WindResidual> = BTreeMap::new();
+
+    for pair in track.windows(2) {
+        let (start, end) = (&pair[0], &pair[1]);
+
+        let interval = end.time.signed_duration_since(start.time);
+
+        if interval.num_seconds() <= 0 || interval.num_seconds() > MAX_FIX_INTERVAL {
+            continue;
+        }
+
+        let observed = start.velocity_to(end);
+
+        let midpoint = Point {
+            latitude: (start.latitude + end.latitude) / 2.0,
+            longitude: bound(start.longitude + bound(end.longitude - start.longitude) / 2.0),
+            altitude: (start.altitude + end.altitude) / 2.0,
+            time: start.time + interval / 2
+        };
+
+        let forecast = match source.velocity_at(&midpoint) {
+            Ok(velocity) => velocity,
+            Err(_) => continue
+        };
+
+        let seconds = interval.num_milliseconds() as f32 / 1000.0;
+        let band = (midpoint.altitude / band_height).floor() as i32;
+
+        let residual = residuals.entry(band).or_insert(WindResidual {
+            east: 0.0,
+            north: 0.0,
+            observed_at: end.time,
+            seconds: 0.0
+        });
+
+        let total = residual.seconds + seconds;
+
+        residual.east = (residual.east * residual.seconds + (observed.east - forecast.east) * seconds) / total;
+        residual.north = (residual.north * residual.seconds + (observed.north - forecast.north) * seconds) / total;
+        residual.observed_at = residual.observed_at.max(end.time);
+        residual.seconds = total;
+    }
+
+    residuals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn launch_time() -> DateTime<Utc> {
+        Utc.ymd(2024, 1, 1).and_hms(12, 0, 0)
+    }
+
+    fn forecast(point: &Point) -> Atmospheroid {
+        Atmospheroid::from_grib(5.0, 0.0, 250.0, point.standard_pressure() * 100.0, 0.0, 0.0)
+    }
+
+    /*
+     * Fixes every 20 seconds along the equator, drifting east at 8 m/s while rising at 5 m/s to 1000 m
+     */
+    fn track() -> Vec<Point> {
+        (0..11).map(|fix| {
+            let seconds = fix as f32 * 20.0;
+
+            Point {
+                latitude: 0.0,
+                longitude: (8.0 * seconds / 6_371_000.0).to_degrees(),
+                altitude: 5.0 * seconds,
+                time: launch_time() + Duration::seconds(fix * 20)
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn averages_the_drift_against_the_forecast_in_bands() {
+        let source = AnalyticSource::new(forecast);
+        let corrected = CorrectedSource::new(&source, track());
+
+        let residuals = corrected.residuals();
+        assert_eq!(residuals.iter().map(|&(bottom, _)| bottom).collect::<Vec<f32>>(), vec![0.0, 500.0]);
+
+        for &(_, ref residual) in residuals.iter() {
+            assert!((residual.east - 3.0).abs() < 0.05, "{}", residual.east);
+            assert!(residual.north.abs() < 0.05);
+            assert_eq!(residual.seconds, 100.0);
+        }
+
+        assert_eq!(residuals[1].1.observed_at, launch_time() + Duration::seconds(200));
+
+        let point = Point { altitude: 750.0, time: launch_time() + Duration::seconds(200), ..track()[0].clone() };
+        let velocity = corrected.velocity_at(&point).unwrap();
+        assert!((velocity.east - 8.0).abs() < 0.05, "{}", velocity.east);
+        assert_eq!(corrected.sources_at(&point), vec![TELEMETRY_SOURCE.to_string()]);
+
+        // nothing was measured up here
+        assert_eq!(corrected.velocity_at(&Point { altitude: 3000.0, ..point }).unwrap().east, 5.0);
+    }
+
+    #[test]
+    fn decays_to_1_over_e_after_the_decay_time() {
+        let source = AnalyticSource::new(forecast);
+        let corrected = CorrectedSource::new(&source, track()).with_band_height(1000.0).with_decay(Duration::hours(1));
+
+        let observed_at = launch_time() + Duration::seconds(200);
+        let at = |time: DateTime<Utc>| corrected.correction_at(&Point { altitude: 500.0, time, ..track()[0].clone() }).unwrap().0;
+
+        let residual = at(observed_at);
+        assert!((residual - 3.0).abs() < 0.05, "{}", residual);
+
+        assert!((at(observed_at + Duration::hours(1)) - residual / ::std::f32::consts::E).abs() < 1e-4);
+        assert!((at(observed_at + Duration::hours(2)) - residual / ::std::f32::consts::E.powi(2)).abs() < 1e-4);
+
+        // before the band was observed it is not any stronger
+        assert_eq!(at(launch_time()), residual);
+    }
+
+    #[test]
+    fn parses_tracks_in_time_order() {
+        let track = parse_track(r#"[
+            {"latitude": 37.5, "longitude": -122.1, "altitude": 1500, "time": 1530000060},
+            {"latitude": 37.4, "longitude": -122.2, "altitude": 1200, "time": 1530000000}
+        ]"#).unwrap();
+
+        assert_eq!(track.len(), 2);
+        assert_eq!(track[0].altitude, 1200.0);
+        assert_eq!(track[0].time, Utc.timestamp(1530000000, 0));
+
+        assert!(parse_track("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_tracks() {
+        assert!(parse_track("[{").is_err());
+        assert!(parse_track(r#"{"latitude": 37.4, "longitude": -122.2, "altitude": 1200, "time": 1530000000}"#).is_err());
+        assert!(parse_track(r#"[{"latitude": 37.4, "longitude": -122.2, "time": 1530000000}]"#).is_err());
+        assert!(parse_track(r#"[{"latitude": 37.4, "longitude": -122.2, "altitude": 1200, "time": 9223372036854775807}]"#).unwrap_err().starts_with("Invalid telemetry time"));
+    }
+}
@@ -0,0 +1,655 @@
+use std::io::Read;
+use std::fs::File;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
+
+use lru_cache::LruCache;
+use memmap::Mmap;
+
+use predictor::point::*;
+use predictor::dataset::CELL_SIZE;
+
+const FLOAT_SIZE : usize = 4;
+const COORDINATE_SIZE : usize = 2 * FLOAT_SIZE; // every record starts with its lat and lon
+
+pub const TILE_VERSION : u16 = 2;
+const TILE_MAGIC : &[u8] = b"GRBP"; // not a valid latitude as a float, so cannot be the start of a v1 tile
+const MAX_HEADER_SIZE : usize = 2048;
+const MAX_OPEN_TILES : usize = 4096;
+const TILE_CACHE_SHARDS : usize = 16;
+const MAX_TILE_COLUMNS : f32 = 1000.0; // grid points along each side of a tile, which sizes its index
+
+const NOT_PRESENT : u32 = u32::MAX;
+
+/*
+ * What a tile holds
+ * Wind tiles (C<lat>_<lon>.gribp) have u, v, t
+ * Height tiles (G<lat>_<lon>.gribp) have geopotential height
+ * Extended tiles (X<lat>_<lon>.gribp) have specific humidity and omega
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TileKind {
+    Wind,
+    Height,
+    Extended
+}
+
+impl TileKind {
+
+    pub fn prefix(&self) -> &'static str {
+        match *self {
+            TileKind::Wind => "C",
+            TileKind::Height => "G",
+            TileKind::Extended => "X"
+        }
+    }
+
+    /*
+     * Variables in each record after lat and lon, in the order v1 tiles store them
+     */
+    pub fn variables(&self) -> &'static [&'static str] {
+        match *self {
+            TileKind::Wind => &["u", "v", "t"],
+            TileKind::Height => &["gh"],
+            TileKind::Extended => &["q", "w"]
+        }
+    }
+}
+
+/*
+ * Describes the layout of a tile
+ *
+ * v1 tiles have no header, and are a sequence of big endian f32 records in the order given by TileKind, at
+ * DATA_RESOLUTION and CELL_SIZE
+ *
+ * v2 tiles start with a header, all big endian:
+ *   "GRBP"
+ *   u16 version
+ *   u16 header length in bytes, including everything before it
+ *   f32 grid resolution, in degrees
+ *   f32 cell size, in degrees
+ *   u8 source length, then the source run (dataset name) as ASCII
+ *   u8 variable count, then for each a u8 length and the variable's short name as ASCII
+ * followed by f32 records of lat, lon, then each variable in order
+ */
+#[derive(Clone, Debug)]
+pub struct TileHeader {
+    pub version: u16,
+    pub resolution: f32,
+    pub cell_size: f32,
+    pub source: String,
+    pub variables: Vec<String>,
+
+    length: usize
+}
+
+impl TileHeader {
+
+    pub fn new(resolution: f32, cell_size: f32, source: String, variables: Vec<String>) -> TileHeader {
+        let length = TILE_MAGIC.len() + 2 + 2 + FLOAT_SIZE + FLOAT_SIZE +
+            1 + source.len() +
+            1 + variables.iter().map(|variable| 1 + variable.len()).sum::<usize>();
+
+        TileHeader {
+            version: TILE_VERSION,
+            resolution, cell_size, source, variables, length
+        }
+    }
+
+    /*
+     * The implicit layout of a headerless tile
+     */
+    pub fn legacy(kind: TileKind) -> TileHeader {
+        TileHeader {
+            version: 1,
+            resolution: DATA_RESOLUTION,
+            cell_size: CELL_SIZE,
+            source: String::new(),
+            variables: kind.variables().iter().map(|variable| variable.to_string()).collect(),
+            length: 0
+        }
+    }
+
+    /*
+     * Reads the header from the start of a tile, falling back to the legacy layout if there is none
+     */
+    pub fn parse(bytes: &[u8], kind: TileKind) -> Result<TileHeader, String> {
+        if bytes.len() < TILE_MAGIC.len() || &bytes[0..TILE_MAGIC.len()] != TILE_MAGIC {
+            return Ok(TileHeader::legacy(kind));
+        }
+
+        let mut reader = HeaderReader { bytes, offset: TILE_MAGIC.len() };
+
+        let version = result_or_return!(reader.u16());
+
+        if version != TILE_VERSION {
+            return_error!(format!("Unsupported tile version {}", version));
+        }
+
+        let length = result_or_return!(reader.u16()) as usize;
+        let resolution = result_or_return!(reader.f32());
+        let cell_size = result_or_return!(reader.f32());
+        let source = result_or_return!(reader.string());
+
+        let variable_count = result_or_return!(reader.u8());
+        let mut variables = vec![];
+
+        for _ in 0..variable_count {
+            variables.push(result_or_return!(reader.string()));
+        }
+
+        if reader.offset != length {
+            return_error!("Tile header length does not match its contents");
+        }
+
+        // the index has a slot for every grid point in the tile, so a corrupt header must not be able to make it huge
+        if !resolution.is_finite() || !cell_size.is_finite() || resolution <= 0.0 || cell_size <= 0.0 || cell_size / resolution > MAX_TILE_COLUMNS {
+            return_error!("Invalid tile resolution");
+        }
+
+        Ok(TileHeader {
+            version, resolution, cell_size, source, variables, length
+        })
+    }
+
+    pub fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(TILE_MAGIC);
+        push_u16(buffer, self.version);
+        push_u16(buffer, self.length as u16);
+        push_f32(buffer, self.resolution);
+        push_f32(buffer, self.cell_size);
+
+        buffer.push(self.source.len() as u8);
+        buffer.extend_from_slice(self.source.as_bytes());
+
+        buffer.push(self.variables.len() as u8);
+        for variable in self.variables.iter() {
+            buffer.push(variable.len() as u8);
+            buffer.extend_from_slice(variable.as_bytes());
+        }
+    }
+
+    pub fn record_size(&self) -> usize {
+        COORDINATE_SIZE + self.variables.len() * FLOAT_SIZE
+    }
+
+    /*
+     * Byte offsets within a record of each of the kind's variables
+     */
+    fn offsets(&self, kind: TileKind) -> Result<Vec<usize>, String> {
+        let mut offsets = vec![];
+
+        for variable in kind.variables().iter() {
+            let index = some_or_return_why!(self.variables.iter().position(|name| name == variable), format!("Tile has no {}", variable));
+
+            offsets.push(COORDINATE_SIZE + index * FLOAT_SIZE);
+        }
+
+        Ok(offsets)
+    }
+
+    /*
+     * Reads the header of a tile on disk, along with how many records it has, without mapping it
+     */
+    pub fn read_file(filename: &String, kind: TileKind) -> Result<(TileHeader, usize), String> {
+        let mut file = result_or_return_why!(File::open(filename), "Could not open file");
+        let length = result_or_return_why!(file.metadata(), "Could not read file metadata").len() as usize;
+
+        let mut bytes = vec![];
+        result_or_return_why!((&mut file).take(MAX_HEADER_SIZE as u64).read_to_end(&mut bytes), "Could not read tile header");
+
+        let header = result_or_return!(TileHeader::parse(&bytes, kind));
+        let records = result_or_return!(header.record_count(length));
+
+        Ok((header, records))
+    }
+
+    fn record_count(&self, file_length: usize) -> Result<usize, String> {
+        if file_length < self.length || !(file_length - self.length).is_multiple_of(self.record_size()) {
+            return_error!("Invalid number of bytes in file");
+        }
+
+        Ok((file_length - self.length) / self.record_size())
+    }
+}
+
+struct HeaderReader<'a> {
+    bytes: &'a [u8],
+    offset: usize
+}
+
+impl<'a> HeaderReader<'a> {
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.offset + count > self.bytes.len() {
+            return_error!("Tile header is truncated");
+        }
+
+        let bytes = &self.bytes[self.offset..(self.offset + count)];
+        self.offset += count;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(result_or_return!(self.take(1))[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = result_or_return!(self.take(2));
+
+        Ok(((bytes[0] as u16) << 8) | (bytes[1] as u16))
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(bytes_to_f32(result_or_return!(self.take(FLOAT_SIZE))))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = result_or_return!(self.u8()) as usize;
+        let bytes = result_or_return!(self.take(length));
+
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/*
+ * Identifies a tile across all datasets
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileKey {
+    pub dataset_id: u32,
+    pub kind: TileKind,
+    pub level: i32,
+    pub grid_lat: i32,
+    pub grid_lon: i32
+}
+
+/*
+ * Open tiles, shared between threads
+ * Split into shards so that threads looking up different tiles rarely wait on each other
+ */
+pub struct TileCache {
+    shards: Vec<Mutex<LruCache<TileKey, Arc<Tile>>>>
+}
+
+impl TileCache {
+
+    pub fn new() -> Self {
+        let mut shards = vec![];
+
+        for _ in 0..TILE_CACHE_SHARDS {
+            shards.push(Mutex::new(LruCache::new(MAX_OPEN_TILES / TILE_CACHE_SHARDS)));
+        }
+
+        TileCache {
+            shards
+        }
+    }
+
+    /*
+     * Returns the tile for a key, opening it if it is not already open
+     * The tile is opened without holding the lock, so a slow open does not block the rest of the shard
+     */
+    pub fn get_or_open<F>(&self, key: TileKey, open: F) -> Result<Arc<Tile>, String> where F: FnOnce() -> Result<Tile, String> {
+        let shard = &self.shards[TileCache::shard_for(&key)];
+
+        {
+            let mut tiles = result_or_return_why!(shard.lock(), "Could not establish lock on tiles");
+
+            if let Some(tile) = tiles.get_mut(&key) {
+                return Ok(tile.clone());
+            }
+        }
+
+        let tile = Arc::new(result_or_return!(open()));
+
+        let mut tiles = result_or_return_why!(shard.lock(), "Could not establish lock on tiles");
+
+        // another thread may have opened it in the meantime, in which case theirs wins
+        if let Some(existing) = tiles.get_mut(&key) {
+            return Ok(existing.clone());
+        }
+
+        tiles.insert(key, tile.clone());
+
+        Ok(tile)
+    }
+
+    fn shard_for(key: &TileKey) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+
+        (hasher.finish() as usize) % TILE_CACHE_SHARDS
+    }
+}
+
+impl Default for TileCache {
+    fn default() -> Self {
+        TileCache::new()
+    }
+}
+
+/*
+ * Returns a tile from the cache shared by every dataset, opening it if needed
+ */
+pub fn open_tile<F>(key: TileKey, open: F) -> Result<Arc<Tile>, String> where F: FnOnce() -> Result<Tile, String> {
+    static TILES : OnceLock<TileCache> = OnceLock::new();
+
+    TILES.get_or_init(TileCache::new).get_or_open(key, open)
+}
+
+/*
+ * Every tile in a level of a global dataset at a resolution, with the number of records each should contain
+ */
+pub fn expected_tiles(resolution: f32) -> BTreeMap<(i32, i32), usize> {
+    let mut tiles = BTreeMap::new();
+
+    let rows = (180.0 / resolution).round() as i32 + 1; // both poles
+    let columns = (360.0 / resolution).round() as i32; // 360 is the same as 0
+
+    for row in 0..rows {
+        let latitude = -90.0 + (row as f32) * resolution;
+
+        for column in 0..columns {
+            let longitude = (column as f32) * resolution;
+
+            let grid_lat = ((latitude / CELL_SIZE).floor() * CELL_SIZE) as i32;
+            let grid_lon = ((longitude / CELL_SIZE).floor() * CELL_SIZE) as i32;
+
+            *tiles.entry((grid_lat, grid_lon)).or_insert(0) += 1;
+        }
+    }
+
+    tiles
+}
+
+/*
+ * A single record in a tile
+ */
+pub struct GribLine {
+    pub latitude : f32,
+    pub longitude : f32,
+    pub u : f32,
+    pub v : f32,
+    pub temperature: f32
+}
+
+/*
+ * A single record in an extended tile
+ */
+pub struct ExtendedLine {
+    pub specific_humidity: f32, // kg/kg
+    pub omega: f32 // Pa/s, positive downwards
+}
+
+/*
+ * A memory mapped .gribp file
+ * The records in a tile are in no particular order, so an index from grid position to record
+ * is built once when the tile is opened; every lookup after that is a direct read from the map
+ */
+pub struct Tile {
+    map: Option<Mmap>,
+    pub header: TileHeader,
+    offsets: Vec<usize>, // of each of the kind's variables within a record
+
+    grid_lat: f32,
+    grid_lon: f32,
+    columns: usize,
+
+    index: Vec<u32>
+}
+
+impl Tile {
+
+    pub fn open(filename: &String, kind: TileKind, grid_lat: f32, grid_lon: f32) -> Result<Tile, String> {
+        let file = result_or_return_why!(File::open(filename), "Could not open file");
+        let length = result_or_return_why!(file.metadata(), "Could not read file metadata").len() as usize;
+
+        // zero length files cannot be mapped
+        let map = if length == 0 {
+            None
+        } else {
+            Some(result_or_return_why!(unsafe { Mmap::map(&file) }, "Could not map file"))
+        };
+
+        let header = match map {
+            Some(ref map) => result_or_return!(TileHeader::parse(&map[0..MAX_HEADER_SIZE.min(length)], kind)),
+            None => TileHeader::legacy(kind)
+        };
+
+        let records = result_or_return!(header.record_count(length));
+        let offsets = result_or_return!(header.offsets(kind));
+
+        // one extra row and column, as the poles and the last tile in each direction are inclusive
+        let cells = (header.cell_size / header.resolution).round() as usize + 1;
+
+        let mut tile = Tile {
+            map: None,
+            header,
+            offsets,
+
+            grid_lat,
+            grid_lon,
+            columns: cells,
+
+            index: vec![NOT_PRESENT; cells * cells]
+        };
+
+        let map = match map {
+            Some(map) => map,
+            None => {
+                return Ok(tile);
+            }
+        };
+
+        for record in 0..records {
+            let offset = tile.record_offset(record);
+            let bytes = &map[offset..(offset + COORDINATE_SIZE)];
+
+            if let Some(position) = tile.position_of(bytes_to_f32(&bytes[0..4]), bytes_to_f32(&bytes[4..8])) {
+                tile.index[position] = record as u32;
+            }
+        }
+
+        tile.map = Some(map);
+
+        Ok(tile)
+    }
+
+    /*
+     * Looks up the wind record at an aligned latitude and longitude
+     * All values are IEEE754 formatted big endian floats
+     */
+    pub fn get(&self, latitude: f32, longitude: f32) -> Option<GribLine> {
+        self.record_at(latitude, longitude).map(|bytes| {
+            GribLine {
+                latitude: bytes_to_f32(&bytes[0..4]),
+                longitude: bytes_to_f32(&bytes[4..8]),
+                u: self.field(bytes, 0),
+                v: self.field(bytes, 1),
+                temperature: self.field(bytes, 2)
+            }
+        })
+    }
+
+    /*
+     * Looks up the geopotential height at an aligned latitude and longitude, in a height tile
+     */
+    pub fn height(&self, latitude: f32, longitude: f32) -> Option<f32> {
+        self.record_at(latitude, longitude).map(|bytes| self.field(bytes, 0))
+    }
+
+    /*
+     * Looks up the specific humidity and omega at an aligned latitude and longitude, in an extended tile
+     */
+    pub fn extended(&self, latitude: f32, longitude: f32) -> Option<ExtendedLine> {
+        self.record_at(latitude, longitude).map(|bytes| {
+            ExtendedLine {
+                specific_humidity: self.field(bytes, 0),
+                omega: self.field(bytes, 1)
+            }
+        })
+    }
+
+    /*
+     * Reads one of the kind's variables out of a record
+     */
+    fn field(&self, record: &[u8], variable: usize) -> f32 {
+        let offset = self.offsets[variable];
+
+        bytes_to_f32(&record[offset..(offset + FLOAT_SIZE)])
+    }
+
+    fn record_offset(&self, record: usize) -> usize {
+        self.header.length + record * self.header.record_size()
+    }
+
+    fn record_at(&self, latitude: f32, longitude: f32) -> Option<&[u8]> {
+        let map = match self.map {
+            Some(ref map) => map,
+            None => {
+                return None;
+            }
+        };
+
+        let position = match self.position_of(latitude, longitude) {
+            Some(position) => position,
+            None => {
+                return None;
+            }
+        };
+
+        match self.index[position] {
+            NOT_PRESENT => None,
+            record => {
+                let offset = self.record_offset(record as usize);
+
+                Some(&map[offset..(offset + self.header.record_size())])
+            }
+        }
+    }
+
+    /*
+     * Position of a grid point in the index, if it falls inside the tile
+     */
+    fn position_of(&self, latitude: f32, longitude: f32) -> Option<usize> {
+        let row = ((latitude - self.grid_lat) / self.header.resolution).round();
+        let column = ((longitude - self.grid_lon) / self.header.resolution).round();
+
+        if row < 0.0 || column < 0.0 || row as usize >= self.columns || column as usize >= self.columns {
+            return None;
+        }
+
+        Some((row as usize) * self.columns + (column as usize))
+    }
+}
+
+fn bytes_to_f32(bytes : &[u8]) -> f32 {
+    let bits = ((bytes[0] as u32) << 24) | ((bytes[1] as u32) << 16) | ((bytes[2] as u32) << 8) | (bytes[3] as u32);
+
+    f32::from_bits(bits)
+}
+
+/*
+ * Appends a big endian u16
+ */
+pub fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.push((value >> 8) as u8);
+    buffer.push(value as u8);
+}
+
+/*
+ * Appends a big endian IEEE754 float
+ */
+pub fn push_f32(buffer: &mut Vec<u8>, value: f32) {
+    let bits = value.to_bits();
+
+    buffer.push((bits >> 24) as u8);
+    buffer.push((bits >> 16) as u8);
+    buffer.push((bits >> 8) as u8);
+    buffer.push(bits as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v2_headers_round_trip() {
+        let header = TileHeader::new(0.5, CELL_SIZE, "gfs.t06z.pgrb2.0p50.f012".to_string(), vec!["t".to_string(), "u".to_string(), "v".to_string()]);
+
+        let mut buffer = vec![];
+        header.write(&mut buffer);
+
+        assert_eq!(buffer.len(), header.length);
+
+        // a record after the header should not be read as part of it
+        for value in [37.5, 237.5, 250.0, 1.0, 2.0].iter() {
+            push_f32(&mut buffer, *value);
+        }
+
+        let parsed = TileHeader::parse(&buffer, TileKind::Wind).unwrap();
+
+        assert_eq!(parsed.version, TILE_VERSION);
+        assert_eq!(parsed.resolution, 0.5);
+        assert_eq!(parsed.cell_size, CELL_SIZE);
+        assert_eq!(parsed.source, "gfs.t06z.pgrb2.0p50.f012");
+        assert_eq!(parsed.variables, vec!["t", "u", "v"]);
+        assert_eq!(parsed.length, header.length);
+
+        assert_eq!(parsed.record_count(buffer.len()).unwrap(), 1);
+        assert_eq!(parsed.offsets(TileKind::Wind).unwrap(), vec![12, 16, 8]);
+    }
+
+    #[test]
+    fn headerless_tiles_are_v1() {
+        let mut buffer = vec![];
+
+        for value in [75.0, 350.0, 1.0, 2.0, 250.0, 75.5, 350.0, 3.0, 4.0, 251.0].iter() {
+            push_f32(&mut buffer, *value);
+        }
+
+        let parsed = TileHeader::parse(&buffer, TileKind::Wind).unwrap();
+
+        assert_eq!(parsed.version, 1);
+        assert_eq!(parsed.resolution, DATA_RESOLUTION);
+        assert_eq!(parsed.cell_size, CELL_SIZE);
+        assert_eq!(parsed.source, "");
+        assert_eq!(parsed.variables, vec!["u", "v", "t"]);
+        assert_eq!(parsed.length, 0);
+
+        assert_eq!(parsed.record_count(buffer.len()).unwrap(), 2);
+        assert_eq!(parsed.offsets(TileKind::Wind).unwrap(), vec![8, 12, 16]);
+    }
+
+    #[test]
+    fn rejects_unknown_versions_and_truncated_headers() {
+        let header = TileHeader::new(0.5, CELL_SIZE, "gfs_4_20180101_0000_003".to_string(), vec!["gh".to_string()]);
+
+        let mut buffer = vec![];
+        header.write(&mut buffer);
+
+        assert!(TileHeader::parse(&buffer[..(buffer.len() - 1)], TileKind::Height).is_err());
+
+        buffer[5] = 3;
+        assert!(TileHeader::parse(&buffer, TileKind::Height).is_err());
+    }
+
+    #[test]
+    fn rejects_resolutions_that_would_need_a_huge_index() {
+        for &(resolution, cell_size) in [(1e-6, CELL_SIZE), (0.0, CELL_SIZE), (-0.5, CELL_SIZE), (::std::f32::NAN, CELL_SIZE), (0.5, ::std::f32::INFINITY)].iter() {
+            let mut buffer = vec![];
+            TileHeader::new(resolution, cell_size, "gfs_4_20180101_0000_003".to_string(), vec!["gh".to_string()]).write(&mut buffer);
+
+            assert!(TileHeader::parse(&buffer, TileKind::Height).is_err(), "{} {}", resolution, cell_size);
+        }
+
+        let mut buffer = vec![];
+        TileHeader::new(0.1, CELL_SIZE, "gfs_4_20180101_0000_003".to_string(), vec!["gh".to_string()]).write(&mut buffer);
+
+        assert!(TileHeader::parse(&buffer, TileKind::Height).is_ok());
+    }
+}
@@ -0,0 +1,331 @@
+use std::fs::File;
+use std::io::Write;
+
+use chrono::prelude::*;
+use serde_json;
+
+use predictor::point::*;
+use predictor::coverage::*;
+use predictor::wind_source::*;
+use predictor::tile::{push_u16, push_f32};
+
+const MAX_GRID_CELLS : usize = 250_000;
+
+const RASTER_MAGIC : &[u8] = b"WNDG";
+const RASTER_VERSION : u16 = 1;
+const RASTER_SCALE : f32 = 100.0; // raster winds are in cm/s
+const RASTER_MISSING : i16 = i16::MIN;
+
+/*
+ * The area, altitude and time to sample winds over
+ * A box with west greater than east crosses the antimeridian
+ */
+pub struct WindGridParams {
+    pub north: f32,
+    pub south: f32,
+    pub west: f32,
+    pub east: f32,
+    pub resolution: f32, // degrees between grid points
+
+    pub altitude: f32,
+    pub time: DateTime<Utc>
+}
+
+/*
+ * Winds on a regular latitude longitude grid, in rows from north to south and columns from west to east
+ * Grid points the source has nothing for are null
+ */
+#[derive(Serialize)]
+pub struct WindGrid {
+    pub north: f32, // of the first row
+    pub west: f32, // of the first column
+    pub resolution: f32,
+    pub columns: usize,
+    pub rows: usize,
+
+    pub altitude: f32,
+    pub time: String,
+
+    pub u: Vec<Option<f32>>, // east, in m/s
+    pub v: Vec<Option<f32>>, // north, in m/s
+    pub max_speed: f32,
+
+    pub sources: Vec<String>,
+    pub coverage: CoverageReport
+}
+
+/*
+ * Everything about a grid but its winds, which is all that is returned when they are written as a raster
+ */
+#[derive(Serialize)]
+pub struct WindGridHeader {
+    pub north: f32,
+    pub west: f32,
+    pub resolution: f32,
+    pub columns: usize,
+    pub rows: usize,
+
+    pub altitude: f32,
+    pub time: String,
+
+    pub max_speed: f32,
+
+    pub sources: Vec<String>,
+    pub coverage: CoverageReport
+}
+
+impl WindGridHeader {
+    pub fn serialize(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl WindGrid {
+    pub fn serialize(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn into_header(self) -> WindGridHeader {
+        WindGridHeader {
+            north: self.north,
+            west: self.west,
+            resolution: self.resolution,
+            columns: self.columns,
+            rows: self.rows,
+
+            altitude: self.altitude,
+            time: self.time,
+
+            max_speed: self.max_speed,
+
+            sources: self.sources,
+            coverage: self.coverage
+        }
+    }
+
+    /*
+     * A compact binary form of the grid for map layers, all big endian:
+     *   "WNDG"
+     *   u16 version
+     *   f32 north, west and resolution, in degrees
+     *   u16 columns, then rows
+     *   f32 altitude, in m
+     *   f32 max speed, in m/s
+     * followed by an i16 u and v for each grid point in cm/s, in the same order as the JSON grid, with -32768 where there is no data
+     */
+    pub fn raster(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(32 + self.u.len() * 4);
+
+        buffer.extend_from_slice(RASTER_MAGIC);
+        push_u16(&mut buffer, RASTER_VERSION);
+        push_f32(&mut buffer, self.north);
+        push_f32(&mut buffer, self.west);
+        push_f32(&mut buffer, self.resolution);
+        push_u16(&mut buffer, self.columns as u16);
+        push_u16(&mut buffer, self.rows as u16);
+        push_f32(&mut buffer, self.altitude);
+        push_f32(&mut buffer, self.max_speed);
+
+        for (u, v) in self.u.iter().zip(self.v.iter()) {
+            push_u16(&mut buffer, raster_value(*u) as u16);
+            push_u16(&mut buffer, raster_value(*v) as u16);
+        }
+
+        buffer
+    }
+
+    pub fn write_raster(&self, path: &String) -> Result<(), String> {
+        let mut file = result_or_return_why!(File::create(path), "Could not create raster");
+        result_or_return_why!(file.write_all(&self.raster()), "Could not write raster");
+
+        Ok(())
+    }
+}
+
+/*
+ * Samples the same winds the predictor would use over a box
+ */
+pub fn wind_grid<S: WindSource + ?Sized>(params: &WindGridParams, source: &S) -> Result<WindGrid, String> {
+    if params.resolution <= 0.0 {
+        return_error!("Resolution must be positive");
+    }
+
+    if params.north < params.south || params.north > 90.0 || params.south < -90.0 {
+        return_error!(format!("Invalid latitudes {} to {}", params.south, params.north));
+    }
+
+    let width = if params.east < params.west { params.east + 360.0 - params.west } else { params.east - params.west };
+
+    // a little leeway, so that rounding does not lose the last row or column
+    let columns = (width / params.resolution + 1e-3).floor() as usize + 1;
+    let rows = ((params.north - params.south) / params.resolution + 1e-3).floor() as usize + 1;
+
+    if columns * rows > MAX_GRID_CELLS || columns > u16::MAX as usize || rows > u16::MAX as usize {
+        return_error!(format!("Grid of {} by {} is too large, the most is {} points", columns, rows, MAX_GRID_CELLS));
+    }
+
+    let mut u = Vec::with_capacity(columns * rows);
+    let mut v = Vec::with_capacity(columns * rows);
+    let mut max_speed : f32 = 0.0;
+
+    let mut sources : Vec<String> = vec![];
+    let mut coverage = Coverage::Covered;
+    let mut last_error = String::new();
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let point = Point {
+                latitude: params.north - row as f32 * params.resolution,
+                longitude: bound(params.west + column as f32 * params.resolution),
+                altitude: params.altitude,
+                time: params.time
+            };
+
+            match source.velocity_with_coverage_at(&point) {
+                Ok((velocity, point_coverage)) => {
+                    coverage = coverage.worst(point_coverage);
+                    max_speed = max_speed.max((velocity.east * velocity.east + velocity.north * velocity.north).sqrt());
+
+                    u.push(Some(velocity.east));
+                    v.push(Some(velocity.north));
+
+                    for name in source.sources_at(&point) {
+                        if !sources.contains(&name) {
+                            sources.push(name);
+                        }
+                    }
+                },
+                Err(why) => {
+                    u.push(None);
+                    v.push(None);
+                    last_error = why;
+                }
+            }
+        }
+    }
+
+    if u.iter().all(|value| value.is_none()) {
+        return Err(last_error);
+    }
+
+    Ok(WindGrid {
+        north: params.north,
+        west: bound(params.west),
+        resolution: params.resolution,
+        columns,
+        rows,
+
+        altitude: params.altitude,
+        time: format!("{:?}", params.time),
+
+        u,
+        v,
+        max_speed,
+
+        sources,
+        coverage: source.coverage_report(coverage)
+    })
+}
+
+fn raster_value(value: Option<f32>) -> i16 {
+    match value {
+        Some(value) => (value * RASTER_SCALE).round().clamp(-32767.0, 32767.0) as i16,
+        None => RASTER_MISSING
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+     * An east wind as fast as the longitude, and nothing north of 5°
+     */
+    struct LongitudeWinds;
+
+    impl WindSource for LongitudeWinds {
+        fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+            if point.latitude > 5.0 {
+                return_error!("Outside of the test winds");
+            }
+
+            Ok(Atmospheroid::from_grib(point.longitude, 0.0, 250.0, 50_000.0, 0.0, 0.0))
+        }
+    }
+
+    fn params(north: f32, south: f32, west: f32, east: f32, resolution: f32) -> WindGridParams {
+        WindGridParams {
+            north,
+            south,
+            west,
+            east,
+            resolution,
+
+            altitude: 5000.0,
+            time: Utc.ymd(2024, 1, 1).and_hms(12, 0, 0)
+        }
+    }
+
+    #[test]
+    fn wraps_columns_across_the_antimeridian() {
+        let grid = wind_grid(&params(5.0, 0.0, 170.0, -170.0, 5.0), &LongitudeWinds).unwrap();
+
+        assert_eq!((grid.columns, grid.rows), (5, 2));
+        assert_eq!(grid.west, 170.0);
+        assert_eq!(grid.u[0..5].to_vec(), vec![Some(170.0), Some(175.0), Some(-180.0), Some(-175.0), Some(-170.0)]);
+        assert_eq!(grid.u[5..10].to_vec(), grid.u[0..5].to_vec());
+        assert_eq!(grid.max_speed, 180.0);
+    }
+
+    #[test]
+    fn counts_rows_and_columns_including_both_edges() {
+        // 0.1 does not divide 1 exactly in floating point
+        let grid = wind_grid(&params(1.0, 0.0, 0.0, 1.0, 0.1), &LongitudeWinds).unwrap();
+        assert_eq!((grid.columns, grid.rows), (11, 11));
+        assert_eq!(grid.u.len(), 121);
+
+        let grid = wind_grid(&params(0.0, 0.0, 10.0, 10.0, 1.0), &LongitudeWinds).unwrap();
+        assert_eq!((grid.columns, grid.rows), (1, 1));
+
+        assert!(wind_grid(&params(90.0, -90.0, -180.0, 180.0, 0.1), &LongitudeWinds).is_err());
+        assert!(wind_grid(&params(0.0, 10.0, 0.0, 10.0, 1.0), &LongitudeWinds).is_err());
+        assert!(wind_grid(&params(10.0, 0.0, 0.0, 10.0, 0.0), &LongitudeWinds).is_err());
+    }
+
+    #[test]
+    fn leaves_points_without_winds_empty() {
+        let grid = wind_grid(&params(10.0, 0.0, 0.0, 10.0, 5.0), &LongitudeWinds).unwrap();
+
+        assert_eq!(grid.u[0..3].to_vec(), vec![None, None, None]);
+        assert_eq!(grid.u[3..6].to_vec(), vec![Some(0.0), Some(5.0), Some(10.0)]);
+
+        assert_eq!(wind_grid(&params(20.0, 10.0, 0.0, 10.0, 5.0), &LongitudeWinds).err(), Some("Outside of the test winds".to_string()));
+    }
+
+    #[test]
+    fn lays_out_the_raster_big_endian_after_the_header() {
+        let mut grid = wind_grid(&params(10.0, 5.0, 1.5, 2.0, 0.5), &LongitudeWinds).unwrap();
+        grid.u[2] = Some(400.0);
+
+        let raster = grid.raster();
+
+        assert_eq!(&raster[0..4], b"WNDG");
+        assert_eq!(&raster[4..6], &[0, 1]);
+        assert_eq!(&raster[6..10], &10f32.to_bits().to_be_bytes());
+        assert_eq!(&raster[10..14], &1.5f32.to_bits().to_be_bytes());
+        assert_eq!(&raster[14..18], &0.5f32.to_bits().to_be_bytes());
+        assert_eq!(&raster[18..22], &[0, 2, 0, 11]);
+        assert_eq!(&raster[22..26], &5000f32.to_bits().to_be_bytes());
+        assert_eq!(&raster[26..30], &grid.max_speed.to_bits().to_be_bytes());
+        assert_eq!(raster.len(), 30 + 22 * 4);
+
+        // u then v for each point, with no data north of 5°
+        assert_eq!(&raster[30..34], &[0x80, 0, 0x80, 0]);
+
+        let last = raster.len() - 8;
+        assert_eq!(&raster[last..], &[0, 150, 0, 0, 0, 200, 0, 0]);
+
+        // the too fast wind is clamped
+        assert_eq!(&raster[38..42], &[0x7F, 0xFF, 0x80, 0]);
+    }
+}
@@ -0,0 +1,88 @@
+use predictor::point::*;
+use predictor::coverage::*;
+
+/*
+ * Anything that can describe the atmosphere at a point
+ * The predictor and navigator only talk to winds through this, so new models and synthetic fields
+ * can be plugged in without touching them
+ */
+pub trait WindSource {
+
+    fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String>;
+
+    /*
+     * Like atmospheroid_at, but also says how well the point was covered
+     * Sources without any notion of coverage cover everywhere
+     */
+    fn atmospheroid_with_coverage_at(&self, point: &Point) -> Result<(Atmospheroid, Coverage), String> {
+        let atmospheroid = result_or_return!(self.atmospheroid_at(point));
+
+        Ok((atmospheroid, Coverage::Covered))
+    }
+
+    /*
+     * Summarizes the coverage of a whole prediction
+     */
+    fn coverage_report(&self, coverage: Coverage) -> CoverageReport {
+        CoverageReport {
+            policy: "none",
+            max_gap_hours: 0,
+            coverage
+        }
+    }
+
+    /*
+     * Names of the datasets that describe a point, for reporting which ones were used
+     * Sources that are not datasets have no names
+     */
+    fn sources_at(&self, _point: &Point) -> Vec<String> {
+        vec![]
+    }
+
+    /*
+     * The atmosphere stored on an isobaric level above a point, with the level's geopotential height there
+     * The altitude of the point is ignored. Sources that are not stored on isobaric levels have none
+     */
+    fn isobaric_level_at(&self, _point: &Point, level: i32) -> Result<(IsobaricLevel, Coverage), String> {
+        Err(format!("No {} hPa level in this source", level))
+    }
+
+    fn velocity_at(&self, point: &Point) -> Result<Velocity, String> {
+        let atmospheroid = result_or_return!(self.atmospheroid_at(point));
+
+        Ok(atmospheroid.velocity)
+    }
+
+    fn temperature_at(&self, point: &Point) -> Result<Temperature, String> {
+        let atmospheroid = result_or_return!(self.atmospheroid_at(point));
+
+        Ok(atmospheroid.temperature)
+    }
+
+    fn velocity_with_coverage_at(&self, point: &Point) -> Result<(Velocity, Coverage), String> {
+        let (atmospheroid, coverage) = result_or_return!(self.atmospheroid_with_coverage_at(point));
+
+        Ok((atmospheroid.velocity, coverage))
+    }
+}
+
+/*
+ * A field described by a function, such as a uniform wind or a synthetic shear layer
+ */
+pub struct AnalyticSource<F> where F: Fn(&Point) -> Atmospheroid {
+    field: F
+}
+
+impl<F> AnalyticSource<F> where F: Fn(&Point) -> Atmospheroid {
+    pub fn new(field: F) -> Self {
+        AnalyticSource {
+            field
+        }
+    }
+}
+
+impl<F> WindSource for AnalyticSource<F> where F: Fn(&Point) -> Atmospheroid {
+    fn atmospheroid_at(&self, point: &Point) -> Result<Atmospheroid, String> {
+        Ok((self.field)(point))
+    }
+}
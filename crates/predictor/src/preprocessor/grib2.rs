@@ -0,0 +1,989 @@
+use std::f32;
+
+use chrono::prelude::*;
+use chrono::LocalResult;
+use chrono::Duration;
+
+use predictor::lambert::LambertConformal;
+
+/*
+ * Decoder for GRIB edition 2 messages
+ * Only supports what GFS and the NCEP regional models use: regular lat/lon grids (template 3.0) and
+ * Lambert conformal grids (template 3.30), analysis/forecast products at
+ * a horizontal level (template 4.0, 4.1 and 4.8 share the same header) and simple or complex
+ * packing (templates 5.0, 5.2 and 5.3)
+ *
+ * Section layouts are from http://www.nco.ncep.noaa.gov/pmb/docs/grib2/grib2_doc/
+ * Offsets in this file are 0-indexed, whereas the documentation uses 1-indexed octets
+ */
+
+const INDICATOR_LENGTH : usize = 16;
+const END_SECTION : &[u8] = b"7777";
+
+pub const ISOBARIC_SURFACE : u8 = 100;
+
+/*
+ * A single decodable field inside of a GRIB message
+ */
+pub struct GribField<'a> {
+    pub discipline: u8,
+    pub reference_time: DateTime<Utc>,
+
+    pub product: ProductDefinition,
+    pub grid: Grid,
+
+    representation: &'a [u8],
+    bitmap: Option<&'a [u8]>,
+    data: &'a [u8]
+}
+
+/*
+ * The parts of the product definition section needed to figure out what a field is
+ */
+#[derive(Clone)]
+pub struct ProductDefinition {
+    pub template: u16,
+    pub category: u8,
+    pub number: u8,
+
+    pub forecast_time: u32,
+    pub forecast_time_unit: u8,
+    pub surface_type: u8,
+    pub surface_value: f64
+}
+
+/*
+ * The grids that fields can be on
+ */
+#[derive(Clone)]
+pub enum Grid {
+    LatLon(LatLonGrid),
+    Lambert(LambertGrid)
+}
+
+/*
+ * A Lambert conformal grid, as in template 3.30
+ */
+#[derive(Clone)]
+pub struct LambertGrid {
+    pub projection: LambertConformal,
+    pub grid_relative_winds: bool, // u and v are along the grid's axes, rather than east and north
+    pub scanning_mode: u8
+}
+
+/*
+ * A regular latitude/longitude grid, as in template 3.0
+ * Coordinates are stored in micro-degrees, as they are in the file
+ */
+#[derive(Clone)]
+pub struct LatLonGrid {
+    pub ni: usize,
+    pub nj: usize,
+
+    pub la1: i64,
+    pub lo1: i64,
+    pub la2: i64,
+    pub lo2: i64,
+
+    pub di: i64,
+    pub dj: i64,
+
+    pub scanning_mode: u8
+}
+
+impl<'a> GribField<'a> {
+
+    /*
+     * Short name of the parameter, using the same names as grib_get_data
+     * Returns None for anything the predictor does not use
+     */
+    pub fn short_name(&self) -> Option<&'static str> {
+        match (self.discipline, self.product.category, self.product.number) {
+            (0, 0, 0) => Some("t"),
+            (0, 2, 2) => Some("u"),
+            (0, 2, 3) => Some("v"),
+            (0, 2, 8) => Some("w"), // omega, in Pa/s
+            (0, 1, 0) => Some("q"),
+            (0, 3, 5) => Some("gh"),
+            _ => None
+        }
+    }
+
+    /*
+     * The time this field is a forecast for
+     * Returns None for units other than minutes and hours
+     */
+    pub fn valid_time(&self) -> Option<DateTime<Utc>> {
+        let forecast = self.product.forecast_time as i64;
+
+        match self.product.forecast_time_unit {
+            0 => Some(self.reference_time + Duration::minutes(forecast)),
+            1 => Some(self.reference_time + Duration::hours(forecast)),
+            _ => None
+        }
+    }
+
+    /*
+     * The isobaric level of this field, in hPa
+     * Returns None for other surfaces or for levels that are not a whole number of hPa
+     */
+    pub fn isobaric_level(&self) -> Option<i32> {
+        if self.product.surface_type != ISOBARIC_SURFACE {
+            return None;
+        }
+
+        let hpa = self.product.surface_value / 100.0;
+
+        if hpa < 1.0 || hpa.fract() != 0.0 {
+            return None;
+        }
+
+        Some(hpa as i32)
+    }
+
+    /*
+     * Unpacks the values in this field, one for each point of the grid
+     * Points masked out by the bitmap are NaN
+     */
+    pub fn decode(&self) -> Result<Vec<f32>, String> {
+        let template = read_u16(self.representation, 9);
+        let packed_count = read_u32(self.representation, 5) as usize;
+        let point_count = self.grid.point_count();
+
+        if packed_count > point_count {
+            return_error!(format!("{} packed values for a grid of {} points", packed_count, point_count));
+        }
+
+        let packed = match template {
+            0 => result_or_return!(self.unpack_simple(packed_count)),
+            2 | 3 => result_or_return!(self.unpack_complex(packed_count, template == 3)),
+            _ => {
+                return_error!(format!("Unsupported data representation template 5.{}", template));
+            }
+        };
+
+        match self.bitmap {
+            Some(bitmap) => {
+                let mut values = Vec::with_capacity(point_count);
+                let mut packed_values = packed.into_iter();
+
+                for i in 0..point_count {
+                    let byte = some_or_return_why!(bitmap.get(i / 8), "Bitmap is too short");
+
+                    if byte & (0x80 >> (i % 8)) == 0 {
+                        values.push(f32::NAN);
+                    } else {
+                        values.push(some_or_return_why!(packed_values.next(), "Fewer packed values than the bitmap expects"));
+                    }
+                }
+
+                Ok(values)
+            },
+            None => {
+                if packed.len() != point_count {
+                    return_error!(format!("Expected {} values, got {}", point_count, packed.len()));
+                }
+
+                Ok(packed)
+            }
+        }
+    }
+
+    /*
+     * Template 5.0: every value is stored with the same number of bits
+     */
+    fn unpack_simple(&self, count: usize) -> Result<Vec<f32>, String> {
+        let scaling = Scaling::from_representation(self.representation);
+        let bits = self.representation[19] as usize;
+
+        let mut reader = BitReader::new(self.data);
+        let mut values = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let packed = result_or_return!(reader.read(bits));
+            values.push(scaling.apply(packed as f64));
+        }
+
+        Ok(values)
+    }
+
+    /*
+     * Templates 5.2 and 5.3: values are split into groups, each with its own reference and width
+     * 5.3 additionally stores first or second order differences of neighbouring values
+     */
+    fn unpack_complex(&self, count: usize, spatial_differencing: bool) -> Result<Vec<f32>, String> {
+        let representation = self.representation;
+
+        if representation.len() < 47 {
+            return_error!("Data representation section is too short for complex packing");
+        }
+
+        let scaling = Scaling::from_representation(representation);
+        let reference_bits = representation[19] as usize;
+        let missing_management = representation[22];
+
+        let group_count = read_u32(representation, 31) as usize;
+        let width_reference = representation[35] as u64;
+        let width_bits = representation[36] as usize;
+        let length_reference = read_u32(representation, 37) as u64;
+        let length_increment = representation[41] as u64;
+        let last_length = read_u32(representation, 42) as u64;
+        let length_bits = representation[46] as usize;
+
+        if missing_management > 1 {
+            return_error!("Secondary missing values are not supported");
+        }
+
+        if group_count > count {
+            return_error!(format!("{} groups for {} packed values", group_count, count));
+        }
+
+        let mut reader = BitReader::new(self.data);
+
+        // extra descriptors for spatial differencing
+        let mut order = 0;
+        let mut first_values : Vec<i64> = vec![];
+        let mut minimum : i64 = 0;
+
+        if spatial_differencing {
+            if representation.len() < 49 {
+                return_error!("Data representation section is too short for spatial differencing");
+            }
+
+            order = representation[47] as usize;
+            let descriptor_bits = (representation[48] as usize) * 8;
+
+            if order != 1 && order != 2 {
+                return_error!(format!("Unsupported order of spatial differencing: {}", order));
+            }
+
+            if descriptor_bits > 0 {
+                for _ in 0..order {
+                    first_values.push(result_or_return!(reader.read_signed(descriptor_bits)));
+                }
+
+                minimum = result_or_return!(reader.read_signed(descriptor_bits));
+            }
+        }
+
+        // group references, widths and lengths each start on an octet boundary
+        let mut references = Vec::with_capacity(group_count);
+        for _ in 0..group_count {
+            references.push(result_or_return!(reader.read(reference_bits)));
+        }
+        reader.align();
+
+        let mut widths = Vec::with_capacity(group_count);
+        for _ in 0..group_count {
+            widths.push(width_reference + result_or_return!(reader.read(width_bits)));
+        }
+        reader.align();
+
+        let mut lengths = Vec::with_capacity(group_count);
+        for _ in 0..group_count {
+            lengths.push(length_reference + length_increment * result_or_return!(reader.read(length_bits)));
+        }
+        reader.align();
+
+        if let Some(last) = lengths.last_mut() {
+            *last = last_length;
+        }
+
+        if lengths.iter().sum::<u64>() != count as u64 {
+            return_error!(format!("Group lengths do not add up to {} packed values", count));
+        }
+
+        // unpack each group, leaving None where a value is missing
+        let mut integers : Vec<Option<i64>> = Vec::with_capacity(count);
+
+        for group in 0..group_count {
+            let width = widths[group] as usize;
+            let reference = references[group];
+
+            let group_missing = missing_management == 1 && width == 0 && reference == max_for_bits(reference_bits);
+
+            for _ in 0..lengths[group] {
+                if width == 0 {
+                    integers.push(if group_missing { None } else { Some(reference as i64) });
+                    continue;
+                }
+
+                let packed = result_or_return!(reader.read(width));
+
+                if missing_management == 1 && packed == max_for_bits(width) {
+                    integers.push(None);
+                } else {
+                    integers.push(Some((reference + packed) as i64));
+                }
+            }
+        }
+
+        // undo the spatial differencing, skipping over missing values
+        if spatial_differencing {
+            let mut previous : Vec<i64> = vec![];
+
+            for value in integers.iter_mut() {
+                let difference = match *value {
+                    Some(difference) => difference,
+                    None => continue
+                };
+
+                let restored = if previous.len() < order {
+                    first_values.get(previous.len()).cloned().unwrap_or(difference)
+                } else if order == 1 {
+                    difference + minimum + previous[previous.len() - 1]
+                } else {
+                    difference + minimum + 2*previous[previous.len() - 1] - previous[previous.len() - 2]
+                };
+
+                *value = Some(restored);
+                previous.push(restored);
+
+                if previous.len() > 2 {
+                    previous.remove(0);
+                }
+            }
+        }
+
+        Ok(integers.into_iter().map(|value| {
+            match value {
+                Some(value) => scaling.apply(value as f64),
+                None => f32::NAN
+            }
+        }).collect())
+    }
+}
+
+impl Grid {
+
+    pub fn point_count(&self) -> usize {
+        match *self {
+            Grid::LatLon(ref grid) => grid.ni * grid.nj,
+            Grid::Lambert(ref grid) => grid.projection.nx * grid.projection.ny
+        }
+    }
+
+    /*
+     * Latitude and longitude of the point at the given index in the data section
+     * Longitudes are in [0, 360)
+     */
+    pub fn coordinates(&self, index: usize) -> (f32, f32) {
+        match *self {
+            Grid::LatLon(ref grid) => grid.coordinates(index),
+            Grid::Lambert(ref grid) => {
+                let (i, j) = grid.indices(index);
+                let (latitude, longitude) = grid.projection.from_grid(i as f64, j as f64);
+
+                (latitude as f32, longitude as f32)
+            }
+        }
+    }
+
+    /*
+     * Grid spacing in degrees, or None for projected grids
+     */
+    pub fn resolution(&self) -> Option<f32> {
+        match *self {
+            Grid::LatLon(ref grid) => Some(grid.resolution()),
+            Grid::Lambert(_) => None
+        }
+    }
+}
+
+impl LambertGrid {
+
+    /*
+     * Grid indices of the point at the given index in the data section
+     * The direction of each axis is part of the projection, so (0, 0) is always the first point
+     */
+    pub fn indices(&self, index: usize) -> (usize, usize) {
+        if self.scanning_mode & 0x20 == 0 {
+            (index % self.projection.nx, index / self.projection.nx)
+        } else {
+            (index / self.projection.ny, index % self.projection.ny)
+        }
+    }
+}
+
+impl LatLonGrid {
+
+    /*
+     * Latitude and longitude of the point at the given index in the data section
+     * Longitudes are in [0, 360), matching grib_get_data
+     */
+    pub fn coordinates(&self, index: usize) -> (f32, f32) {
+        let (i, j) = if self.scanning_mode & 0x20 == 0 {
+            (index % self.ni, index / self.ni)
+        } else {
+            (index / self.nj, index % self.nj)
+        };
+
+        let lat_step = if self.la2 < self.la1 { -self.dj } else { self.dj };
+        let lon_step = if self.scanning_mode & 0x80 == 0 { self.di } else { -self.di };
+
+        let lat = self.la1 + (j as i64) * lat_step;
+        let mut lon = (self.lo1 + (i as i64) * lon_step) % 360_000_000;
+
+        if lon < 0 {
+            lon += 360_000_000;
+        }
+
+        ((lat as f64 / 1e6) as f32, (lon as f64 / 1e6) as f32)
+    }
+
+    /*
+     * Grid spacing in degrees
+     */
+    pub fn resolution(&self) -> f32 {
+        (self.di as f64 / 1e6) as f32
+    }
+}
+
+/*
+ * Splits a GRIB2 file into its fields
+ * Fields borrow from the given bytes and are only decoded on demand
+ */
+pub fn fields<'a>(bytes: &'a [u8]) -> Result<Vec<GribField<'a>>, String> {
+    let mut fields = vec![];
+    let mut offset = 0;
+
+    while offset + INDICATOR_LENGTH <= bytes.len() {
+        if &bytes[offset..(offset + 4)] != b"GRIB" {
+            // tolerate padding between messages
+            offset += 1;
+            continue;
+        }
+
+        let edition = bytes[offset + 7];
+        if edition != 2 {
+            return_error!(format!("Unsupported GRIB edition {}", edition));
+        }
+
+        let length = read_u64(bytes, offset + 8) as usize;
+
+        if length < INDICATOR_LENGTH || offset + length > bytes.len() {
+            return_error!("GRIB message extends past the end of the file");
+        }
+
+        result_or_return!(parse_message(&bytes[offset..(offset + length)], &mut fields));
+
+        offset += length;
+    }
+
+    Ok(fields)
+}
+
+/*
+ * Reads the sections of a single message
+ * Sections 2 through 7 may repeat, in which case each repetition yields another field
+ */
+fn parse_message<'a>(message: &'a [u8], fields: &mut Vec<GribField<'a>>) -> Result<(), String> {
+    let discipline = message[6];
+
+    let mut reference_time : Option<DateTime<Utc>> = None;
+    let mut grid : Option<Grid> = None;
+    let mut product : Option<ProductDefinition> = None;
+    let mut representation : Option<&'a [u8]> = None;
+    let mut bitmap : Option<&'a [u8]> = None;
+    let mut previous_bitmap : Option<&'a [u8]> = None;
+
+    let mut offset = INDICATOR_LENGTH;
+
+    while offset < message.len() {
+        if message.len() - offset >= 4 && &message[offset..(offset + 4)] == END_SECTION {
+            return Ok(());
+        }
+
+        if message.len() - offset < 5 {
+            return_error!("Truncated GRIB section");
+        }
+
+        let length = read_u32(message, offset) as usize;
+        let number = message[offset + 4];
+
+        if length < 5 || offset + length > message.len() {
+            return_error!(format!("Invalid length for GRIB section {}", number));
+        }
+
+        let section = &message[offset..(offset + length)];
+
+        match number {
+            1 => {
+                reference_time = Some(result_or_return!(parse_identification(section)));
+            },
+            2 => {
+                // local use section, nothing in here we need
+            },
+            3 => {
+                grid = Some(result_or_return!(parse_grid(section)));
+            },
+            4 => {
+                product = Some(result_or_return!(parse_product(section)));
+            },
+            5 => {
+                if length < 21 {
+                    return_error!("Data representation section is too short");
+                }
+
+                representation = Some(section);
+            },
+            6 => {
+                if length < 6 {
+                    return_error!("Bitmap section is too short");
+                }
+
+                bitmap = match section[5] {
+                    0 => {
+                        previous_bitmap = Some(&section[6..]);
+                        previous_bitmap
+                    },
+                    254 => previous_bitmap,
+                    255 => None,
+                    indicator => {
+                        return_error!(format!("Unsupported bitmap indicator {}", indicator));
+                    }
+                };
+            },
+            7 => {
+                fields.push(GribField {
+                    discipline,
+                    reference_time: some_or_return_why!(reference_time, "Missing identification section"),
+                    product: some_or_return_why!(product.clone(), "Missing product definition section"),
+                    grid: some_or_return_why!(grid.clone(), "Missing grid definition section"),
+                    representation: some_or_return_why!(representation, "Missing data representation section"),
+                    bitmap,
+                    data: &section[5..]
+                });
+            },
+            _ => {
+                return_error!(format!("Unknown GRIB section {}", number));
+            }
+        }
+
+        offset += length;
+    }
+
+    Err(String::from("GRIB message is missing its end section"))
+}
+
+fn parse_identification(section: &[u8]) -> Result<DateTime<Utc>, String> {
+    if section.len() < 19 {
+        return_error!("Identification section is too short");
+    }
+
+    let year = read_u16(section, 12) as i32;
+
+    match Utc.ymd_opt(year, section[14] as u32, section[15] as u32).and_hms_opt(section[16] as u32, section[17] as u32, section[18] as u32) {
+        LocalResult::Single(time) => Ok(time),
+        _ => Err(String::from("Invalid reference time"))
+    }
+}
+
+fn parse_grid(section: &[u8]) -> Result<Grid, String> {
+    if section.len() < 14 {
+        return_error!("Grid definition section is too short");
+    }
+
+    let template = read_u16(section, 12);
+
+    match template {
+        0 => Ok(Grid::LatLon(result_or_return!(parse_lat_lon_grid(section)))),
+        30 => Ok(Grid::Lambert(result_or_return!(parse_lambert_grid(section)))),
+        _ => Err(format!("Unsupported grid definition template 3.{}", template))
+    }
+}
+
+fn parse_lambert_grid(section: &[u8]) -> Result<LambertGrid, String> {
+    if section.len() < 81 {
+        return_error!("Grid definition section is too short");
+    }
+
+    let micro_degrees = |value: i64| (value as f64) / 1e6;
+    let scanning_mode = section[64];
+
+    // points run westwards if bit 1 is set, and southwards unless bit 2 is set
+    let x_direction = if scanning_mode & 0x80 == 0 { 1.0 } else { -1.0 };
+    let y_direction = if scanning_mode & 0x40 != 0 { 1.0 } else { -1.0 };
+
+    Ok(LambertGrid {
+        projection: LambertConformal {
+            nx: read_u32(section, 30) as usize,
+            ny: read_u32(section, 34) as usize,
+
+            la1: micro_degrees(read_signed(section, 38, 4)),
+            lo1: micro_degrees(read_signed(section, 42, 4)),
+            lov: micro_degrees(read_signed(section, 51, 4)),
+            latin1: micro_degrees(read_signed(section, 65, 4)),
+            latin2: micro_degrees(read_signed(section, 69, 4)),
+
+            // stored in mm
+            dx: x_direction * (read_u32(section, 55) as f64) / 1e3,
+            dy: y_direction * (read_u32(section, 59) as f64) / 1e3
+        },
+        grid_relative_winds: section[46] & 0x08 != 0,
+        scanning_mode
+    })
+}
+
+fn parse_lat_lon_grid(section: &[u8]) -> Result<LatLonGrid, String> {
+    if section.len() < 72 {
+        return_error!("Grid definition section is too short");
+    }
+
+    Ok(LatLonGrid {
+        ni: read_u32(section, 30) as usize,
+        nj: read_u32(section, 34) as usize,
+
+        la1: read_signed(section, 46, 4),
+        lo1: read_signed(section, 50, 4),
+        la2: read_signed(section, 55, 4),
+        lo2: read_signed(section, 59, 4),
+
+        di: read_u32(section, 63) as i64,
+        dj: read_u32(section, 67) as i64,
+
+        scanning_mode: section[71]
+    })
+}
+
+fn parse_product(section: &[u8]) -> Result<ProductDefinition, String> {
+    if section.len() < 34 {
+        return_error!("Product definition section is too short");
+    }
+
+    let template = read_u16(section, 7);
+
+    match template {
+        0 | 1 | 8 | 11 => {},
+        _ => {
+            return_error!(format!("Unsupported product definition template 4.{}", template));
+        }
+    }
+
+    let scale_factor = read_signed(section, 23, 1);
+    let scaled_value = read_signed(section, 24, 4);
+
+    Ok(ProductDefinition {
+        template,
+        category: section[9],
+        number: section[10],
+
+        forecast_time: read_u32(section, 18),
+        forecast_time_unit: section[17],
+        surface_type: section[22],
+        surface_value: (scaled_value as f64) / 10f64.powi(scale_factor as i32)
+    })
+}
+
+/*
+ * Converts packed integers back to physical values: Y = (R + X * 2^E) / 10^D
+ */
+struct Scaling {
+    reference: f64,
+    binary_factor: f64,
+    decimal_factor: f64
+}
+
+impl Scaling {
+    fn from_representation(representation: &[u8]) -> Scaling {
+        let reference = f32::from_bits(read_u32(representation, 11)) as f64;
+        let binary_scale = read_signed(representation, 15, 2);
+        let decimal_scale = read_signed(representation, 17, 2);
+
+        Scaling {
+            reference,
+            binary_factor: 2f64.powi(binary_scale as i32),
+            decimal_factor: 10f64.powi(-decimal_scale as i32)
+        }
+    }
+
+    fn apply(&self, packed: f64) -> f32 {
+        ((self.reference + packed * self.binary_factor) * self.decimal_factor) as f32
+    }
+}
+
+/*
+ * Reads big endian, arbitrarily sized unsigned integers from a byte slice
+ */
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    offset: usize // in bits
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader {
+            bytes,
+            offset: 0
+        }
+    }
+
+    fn read(&mut self, bits: usize) -> Result<u64, String> {
+        if bits > 64 {
+            return_error!(format!("Cannot read {} bits at once", bits));
+        }
+
+        if self.offset + bits > self.bytes.len() * 8 {
+            return_error!("Ran out of packed data");
+        }
+
+        let mut value : u64 = 0;
+        let mut remaining = bits;
+
+        while remaining > 0 {
+            let byte = self.bytes[self.offset / 8] as u64;
+            let used = self.offset % 8;
+            let available = 8 - used;
+            let take = if remaining < available { remaining } else { available };
+
+            let chunk = (byte >> (available - take)) & ((1 << take) - 1);
+            value = (value << take) | chunk;
+
+            self.offset += take;
+            remaining -= take;
+        }
+
+        Ok(value)
+    }
+
+    /*
+     * GRIB2 signed integers use a sign bit followed by the magnitude
+     */
+    fn read_signed(&mut self, bits: usize) -> Result<i64, String> {
+        let negative = result_or_return!(self.read(1)) == 1;
+        let magnitude = result_or_return!(self.read(bits - 1)) as i64;
+
+        Ok(if negative { -magnitude } else { magnitude })
+    }
+
+    /*
+     * Skips to the start of the next octet
+     */
+    fn align(&mut self) {
+        self.offset = self.offset.div_ceil(8) * 8;
+    }
+}
+
+fn max_for_bits(bits: usize) -> u64 {
+    if bits >= 64 {
+        return u64::MAX;
+    }
+
+    (1 << bits) - 1
+}
+
+fn read_unsigned(bytes: &[u8], offset: usize, length: usize) -> u64 {
+    let mut number : u64 = 0;
+    for i in 0..length {
+        number = (number << 8) | (bytes[offset + i] as u64);
+    }
+
+    number
+}
+
+fn read_signed(bytes: &[u8], offset: usize, length: usize) -> i64 {
+    let raw = read_unsigned(bytes, offset, length);
+    let sign_bit = 1u64 << (8 * length - 1);
+
+    if raw & sign_bit != 0 {
+        -((raw & !sign_bit) as i64)
+    } else {
+        raw as i64
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    read_unsigned(bytes, offset, 2) as u16
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    read_unsigned(bytes, offset, 4) as u32
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    read_unsigned(bytes, offset, 8)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /*
+     * A section of the given length, with values written at the same 0-indexed offsets the decoder reads them from
+     */
+    pub fn section(number: u8, length: usize, values: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut section = vec![0; length];
+
+        section[0..4].copy_from_slice(&be32(length as u32));
+        section[4] = number;
+
+        for &(offset, bytes) in values {
+            section[offset..(offset + bytes.len())].copy_from_slice(bytes);
+        }
+
+        section
+    }
+
+    pub fn be32(value: u32) -> [u8; 4] {
+        [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+    }
+
+    /*
+     * A message with u wind at 850 hPa on a 2x2 grid of 0.5 degrees, from 90N 0E
+     */
+    fn message(representation: Vec<u8>, bitmap: Vec<u8>, data: &[u8]) -> Vec<u8> {
+        let mut body = vec![];
+
+        // reference time 2018-02-17 06:00:00
+        body.extend(section(1, 21, &[(12, &[0x07, 0xE2, 2, 17, 6, 0, 0])]));
+
+        body.extend(section(3, 72, &[
+            (12, &[0, 0]),
+            (30, &be32(2)),
+            (34, &be32(2)),
+            (46, &be32(90_000_000)),
+            (50, &be32(0)),
+            (55, &be32(89_500_000)),
+            (59, &be32(500_000)),
+            (63, &be32(500_000)),
+            (67, &be32(500_000))
+        ]));
+
+        body.extend(section(4, 34, &[
+            (7, &[0, 0]),
+            (9, &[2, 2]),
+            (17, &[1]),
+            (18, &be32(6)),
+            (22, &[ISOBARIC_SURFACE, 0]),
+            (24, &be32(85000))
+        ]));
+
+        body.extend(representation);
+        body.extend(bitmap);
+        body.extend(section(7, 5 + data.len(), &[(5, data)]));
+        body.extend_from_slice(END_SECTION);
+
+        let length = (INDICATOR_LENGTH + body.len()) as u64;
+
+        let mut message = b"GRIB".to_vec();
+        message.extend_from_slice(&[0, 0, 0, 2]);
+        message.extend_from_slice(&be32((length >> 32) as u32));
+        message.extend_from_slice(&be32(length as u32));
+        message.extend(body);
+
+        message
+    }
+
+    fn no_bitmap() -> Vec<u8> {
+        section(6, 6, &[(5, &[255])])
+    }
+
+    #[test]
+    fn reads_the_headers() {
+        let representation = section(5, 21, &[(5, &be32(4)), (19, &[8])]);
+        let message = message(representation, no_bitmap(), &[1, 2, 3, 4]);
+
+        let fields = fields(&message).unwrap();
+        assert_eq!(fields.len(), 1);
+
+        let field = &fields[0];
+        assert_eq!(field.short_name(), Some("u"));
+        assert_eq!(field.isobaric_level(), Some(850));
+        assert_eq!(field.reference_time, Utc.ymd(2018, 2, 17).and_hms(6, 0, 0));
+        assert_eq!(field.valid_time(), Some(Utc.ymd(2018, 2, 17).and_hms(12, 0, 0)));
+        assert_eq!(field.grid.resolution(), Some(0.5));
+        assert_eq!(field.grid.coordinates(3), (89.5, 0.5));
+    }
+
+    #[test]
+    fn unpacks_simple_packing() {
+        // reference 10, binary scale 1 and decimal scale 1: (10 + X * 2) / 10
+        let representation = section(5, 21, &[
+            (5, &be32(4)),
+            (11, &be32(10f32.to_bits())),
+            (15, &[0, 1]),
+            (17, &[0, 1]),
+            (19, &[4])
+        ]);
+        let message = message(representation, no_bitmap(), &[0x01, 0x2F]);
+
+        let fields = fields(&message).unwrap();
+        assert_eq!(fields[0].decode().unwrap(), vec![1.0, 1.2, 1.4, 4.0]);
+    }
+
+    #[test]
+    fn applies_the_bitmap() {
+        let representation = section(5, 21, &[(5, &be32(2)), (19, &[8])]);
+        let bitmap = section(6, 7, &[(5, &[0, 0b1001_0000])]);
+        let message = message(representation, bitmap, &[7, 9]);
+
+        let values = fields(&message).unwrap()[0].decode().unwrap();
+        assert_eq!(values[0], 7.0);
+        assert!(values[1].is_nan() && values[2].is_nan());
+        assert_eq!(values[3], 9.0);
+    }
+
+    #[test]
+    fn unpacks_complex_packing_with_spatial_differencing() {
+        // one group of 4 first order differences, 2 bits each, with 1 octet descriptors
+        let representation = section(5, 49, &[
+            (5, &be32(4)),
+            (9, &[0, 3]),
+            (19, &[8]),
+            (21, &[1]),
+            (31, &be32(1)),
+            (36, &[8]),
+            (37, &be32(4)),
+            (41, &[1]),
+            (42, &be32(4)),
+            (46, &[8]),
+            (47, &[1, 1])
+        ]);
+
+        // first value 10, minimum 1, group reference 0, width 2, length 0, then differences 0, 1, 2 and 0
+        let message = message(representation, no_bitmap(), &[10, 1, 0, 2, 0, 0b0001_1000]);
+
+        let fields = fields(&message).unwrap();
+        assert_eq!(fields[0].decode().unwrap(), vec![10.0, 12.0, 15.0, 16.0]);
+    }
+
+    #[test]
+    fn unpacks_second_order_differences() {
+        // two groups: the first of width 0, the second of width 3
+        let representation = section(5, 49, &[
+            (5, &be32(4)),
+            (9, &[0, 3]),
+            (19, &[8]),
+            (21, &[1]),
+            (31, &be32(2)),
+            (36, &[8]),
+            (37, &be32(2)),
+            (41, &[1]),
+            (42, &be32(2)),
+            (46, &[8]),
+            (47, &[2, 2])
+        ]);
+
+        // first values 100 and 103 (-5 is a sign bit then the magnitude), minimum -5
+        // group references 0 and 4, widths 0 and 3, lengths 0 and 0, then differences 2 and 6
+        let data = [0, 100, 0, 103, 0x80, 5, 0, 4, 0, 3, 0, 0, 0b0101_1000];
+        let message = message(representation, no_bitmap(), &data);
+
+        // 103 + (103 - 100) + (4 + 2 - 5) = 107, then 107 + 4 + (4 + 6 - 5) = 116
+        let fields = fields(&message).unwrap();
+        assert_eq!(fields[0].decode().unwrap(), vec![100.0, 103.0, 107.0, 116.0]);
+    }
+
+    #[test]
+    fn rejects_truncated_sections() {
+        let representation = section(5, 21, &[(5, &be32(4)), (19, &[8])]);
+        let short_bitmap = message(representation, section(6, 5, &[]), &[1, 2, 3, 4]);
+
+        assert!(fields(&short_bitmap).is_err());
+
+        let representation = section(5, 20, &[(5, &be32(4)), (19, &[8])]);
+        let short_representation = message(representation, no_bitmap(), &[1, 2, 3, 4]);
+
+        assert!(fields(&short_representation).is_err());
+    }
+}
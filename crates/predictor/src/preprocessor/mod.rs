@@ -0,0 +1,6 @@
+pub mod grib2;
+#[allow(clippy::module_inception)]
+pub mod preprocessor;
+
+pub use preprocessor::grib2::*;
+pub use preprocessor::preprocessor::*;
@@ -0,0 +1,503 @@
+use std::io::prelude::*;
+use std::fs;
+use std::fs::File;
+use std::collections::BTreeMap;
+use std::time::Instant;
+use std::f32;
+
+use serde_json;
+
+use predictor::dataset::{CELL_SIZE, DATASET_MANIFEST, DatasetManifest};
+use predictor::tile::*;
+use predictor::regional::*;
+use predictor::dataset_name::DatasetName;
+use preprocessor::grib2;
+use preprocessor::grib2::*;
+
+/*
+ * Summary of a preprocessed dataset
+ */
+#[derive(Serialize)]
+pub struct Preprocessed {
+    pub name: String,
+    pub levels: Vec<i32>,
+    pub tiles: usize,
+    pub seconds: f64
+}
+
+impl Preprocessed {
+    pub fn serialize(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/*
+ * Indices of the fields for a single level
+ */
+#[derive(Default)]
+struct LevelFields {
+    u: Option<usize>,
+    v: Option<usize>,
+    t: Option<usize>,
+    gh: Option<usize>,
+    q: Option<usize>,
+    w: Option<usize>
+}
+
+const PARTIAL_SUFFIX : &str = ".partial";
+
+/*
+ * Converts a GRIB2 file into the tiled format read by Dataset
+ * Tiles are written to L<level>/C<lat>_<lon>.gribp, in the directory named after the file minus its extension
+ * Lambert conformal files are treated as a regional model, and written to regional/<model>_<run>_<forecast hour> instead
+ * Everything is written to a .partial directory first and renamed once complete, so readers never see half a dataset
+ * A manifest of every tile written goes in last, so that readers can validate the dataset without opening each tile
+ */
+pub fn preprocess(path: String) -> Result<Preprocessed, String> {
+    let start = Instant::now();
+
+    let bytes = {
+        let mut file = result_or_return_why!(File::open(&path), "Could not open GRIB file");
+        let mut bytes = vec![];
+        result_or_return_why!(file.read_to_end(&mut bytes), "Could not read GRIB file");
+
+        bytes
+    };
+
+    let fields = result_or_return!(grib2::fields(&bytes));
+
+    let directory = DatasetName::strip_extension(path.as_str()).to_string();
+
+    if directory == path {
+        return_error!("Expected a GRIB file extension");
+    }
+
+    // figure out where the u, v and t fields for each level are
+    let mut levels : BTreeMap<i32, LevelFields> = BTreeMap::new();
+
+    for (index, field) in fields.iter().enumerate() {
+        let level = match field.isobaric_level() {
+            Some(level) => level,
+            None => continue
+        };
+
+        let entry = levels.entry(level).or_default();
+
+        match field.short_name() {
+            Some("u") => entry.u = Some(index),
+            Some("v") => entry.v = Some(index),
+            Some("t") => entry.t = Some(index),
+            Some("gh") => entry.gh = Some(index),
+            Some("q") => entry.q = Some(index),
+            Some("w") => entry.w = Some(index),
+            _ => {}
+        }
+    }
+
+    // regional models go alongside the global datasets, in their own directory and format
+    if let Some(Grid::Lambert(grid)) = fields.first().map(|field| &field.grid) {
+        let parent = match directory.rfind('/') {
+            Some(index) => directory[..index].to_string(),
+            None => ".".to_string()
+        };
+        let name = result_or_return!(regional_name(&directory, &fields[0]));
+        let regional_directory = parent + "/" + REGIONAL_DIRECTORY + "/" + name.as_str();
+
+        return preprocess_regional(regional_directory, name, grid, &fields, &levels, start);
+    }
+
+    // global datasets are named after the file, which must be a name the reader recognizes
+    let name = result_or_return!(DatasetName::parse(path.as_str())).name;
+
+    let partial_directory = directory.clone() + PARTIAL_SUFFIX;
+
+    result_or_return!(clear_partial(&partial_directory));
+
+    let resolution = some_or_return_why!(fields.first().and_then(|field| field.grid.resolution()), "Tiles need a latitude/longitude grid");
+
+    let mut manifest = DatasetManifest::new(resolution);
+    let mut written_levels = vec![];
+    let mut tiles = 0;
+
+    for (level, level_fields) in levels.iter() {
+        let (u_index, v_index, t_index) = match (level_fields.u, level_fields.v, level_fields.t) {
+            (Some(u), Some(v), Some(t)) => (u, v, t),
+            _ => continue
+        };
+
+        let optional = |index: Option<usize>| index.map(|index| &fields[index]);
+
+        tiles += result_or_return!(write_level(&partial_directory, &name, *level, LevelGrids {
+            u: &fields[u_index],
+            v: &fields[v_index],
+            t: &fields[t_index],
+            gh: optional(level_fields.gh),
+            q: optional(level_fields.q),
+            w: optional(level_fields.w)
+        }, &mut manifest));
+        written_levels.push(*level);
+    }
+
+    if written_levels.is_empty() {
+        return_error!("No isobaric levels with u, v and t found");
+    }
+
+    {
+        let mut file = result_or_return_why!(File::create(partial_directory.clone() + "/" + DATASET_MANIFEST), "Could not create manifest");
+        result_or_return_why!(file.write_all(manifest.serialize().as_bytes()), "Could not write manifest");
+    }
+
+    result_or_return!(move_into_place(&partial_directory, &directory));
+
+    let elapsed = start.elapsed();
+
+    Ok(Preprocessed {
+        name,
+        levels: written_levels,
+        tiles,
+        seconds: elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64) / 1e9
+    })
+}
+
+/*
+ * Converts the fields of a regional model into the format read by RegionalDataset
+ * Every level is written as a single file, with winds rotated to be relative to east and north
+ */
+fn preprocess_regional(directory: String, name: String, grid: &LambertGrid, fields: &[GribField], levels: &BTreeMap<i32, LevelFields>, start: Instant) -> Result<Preprocessed, String> {
+    let partial_directory = directory.clone() + PARTIAL_SUFFIX;
+
+    result_or_return!(clear_partial(&partial_directory));
+    result_or_return_why!(fs::create_dir_all(&partial_directory), "Could not create regional directory");
+
+    let point_count = grid.projection.nx * grid.projection.ny;
+
+    let mut written_levels = vec![];
+    let mut valid_time = None;
+
+    for (level, level_fields) in levels.iter() {
+        let (u, v, t) = match (level_fields.u, level_fields.v, level_fields.t) {
+            (Some(u), Some(v), Some(t)) => (&fields[u], &fields[v], &fields[t]),
+            _ => continue
+        };
+
+        let u_values = result_or_return!(decode_matching(u, point_count, *level));
+        let v_values = result_or_return!(decode_matching(v, point_count, *level));
+        let t_values = result_or_return!(decode_matching(t, point_count, *level));
+
+        // records are in grid order, whatever order the file scans in
+        let mut records = vec![f32::NAN; point_count * 3];
+
+        for index in 0..point_count {
+            let (i, j) = grid.indices(index);
+
+            let (east, north) = if grid.grid_relative_winds {
+                let (_, longitude) = grid.projection.from_grid(i as f64, j as f64);
+
+                grid.projection.to_earth_relative(longitude, u_values[index], v_values[index])
+            } else {
+                (u_values[index], v_values[index])
+            };
+
+            let offset = (j * grid.projection.nx + i) * 3;
+            records[offset] = east;
+            records[offset + 1] = north;
+            records[offset + 2] = t_values[index];
+        }
+
+        let mut contents = Vec::with_capacity(records.len() * 4);
+        for value in records.iter() {
+            push_f32(&mut contents, *value);
+        }
+
+        let filename = partial_directory.clone() + "/" + RegionalManifest::level_filename(*level).as_str();
+        let mut file = result_or_return_why!(File::create(&filename), "Could not create level");
+        result_or_return_why!(file.write_all(&contents), "Could not write level");
+
+        if valid_time.is_none() {
+            valid_time = Some((u.reference_time, some_or_return_why!(u.valid_time(), "Unsupported forecast time unit")));
+        }
+
+        written_levels.push(*level);
+    }
+
+    let (created_at, time) = some_or_return_why!(valid_time, "No isobaric levels with u, v and t found");
+
+    let manifest = RegionalManifest {
+        name: name.clone(),
+        created_at: created_at.timestamp(),
+        time: time.timestamp(),
+        projection: grid.projection.clone(),
+        levels: written_levels.clone()
+    };
+
+    {
+        let mut file = result_or_return_why!(File::create(partial_directory.clone() + "/" + MANIFEST_FILE), "Could not create manifest");
+        result_or_return_why!(file.write_all(result_or_return_why!(serde_json::to_string(&manifest), "Could not serialize manifest").as_bytes()), "Could not write manifest");
+    }
+
+    result_or_return!(move_into_place(&partial_directory, &directory));
+
+    let elapsed = start.elapsed();
+
+    Ok(Preprocessed {
+        name,
+        tiles: written_levels.len(),
+        levels: written_levels,
+        seconds: elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64) / 1e9
+    })
+}
+
+/*
+ * Name of a regional dataset, such as hrrr_20240101_0600_003 for the third hour of the 06Z HRRR run
+ * Regional files are named after the model alone, like hrrr.t06z.wrfprsf03.grib2, so the run and forecast hour
+ * come from the file itself, and every hour gets its own directory
+ */
+fn regional_name(directory: &str, field: &GribField) -> Result<String, String> {
+    let filename = match directory.rfind('/') {
+        Some(index) => &directory[(index + 1)..],
+        None => directory
+    };
+    let model = filename.split('.').next().unwrap_or(filename);
+
+    if model.is_empty() {
+        return_error!("Expected the regional file to start with the model's name");
+    }
+
+    let valid_time = some_or_return_why!(field.valid_time(), "Unsupported forecast time unit");
+    let forecast_hours = valid_time.signed_duration_since(field.reference_time).num_hours();
+
+    Ok(format!("{}_{}_{:03}", model, field.reference_time.format("%Y%m%d_%H%M"), forecast_hours))
+}
+
+/*
+ * Clears out anything left behind by an interrupted run
+ */
+fn clear_partial(partial_directory: &str) -> Result<(), String> {
+    if fs::metadata(partial_directory).is_ok() {
+        result_or_return_why!(fs::remove_dir_all(partial_directory), "Could not remove partial directory");
+    }
+
+    Ok(())
+}
+
+/*
+ * Replaces any previous version of a dataset with the finished one
+ */
+fn move_into_place(partial_directory: &str, directory: &str) -> Result<(), String> {
+    if fs::metadata(directory).is_ok() {
+        result_or_return_why!(fs::remove_dir_all(directory), "Could not remove previous dataset");
+    }
+
+    result_or_return_why!(fs::rename(partial_directory, directory), "Could not move dataset into place");
+
+    Ok(())
+}
+
+/*
+ * The fields of a single level, some of which not every level has
+ */
+struct LevelGrids<'a, 'b: 'a> {
+    u: &'a GribField<'b>,
+    v: &'a GribField<'b>,
+    t: &'a GribField<'b>,
+    gh: Option<&'a GribField<'b>>,
+    q: Option<&'a GribField<'b>>,
+    w: Option<&'a GribField<'b>>
+}
+
+/*
+ * Decodes a single level and writes out all of its tiles
+ * Wind tiles hold lat, lon, u, v, t, height tiles hold lat, lon, gh and extended tiles hold lat, lon, q, omega
+ * Each tile is added to the manifest, and the number of tiles written is returned
+ */
+fn write_level(directory: &str, source: &str, level: i32, grids: LevelGrids, manifest: &mut DatasetManifest) -> Result<usize, String> {
+    let u = grids.u;
+    let u_values = result_or_return!(u.decode());
+    let v_values = result_or_return!(grids.v.decode());
+    let t_values = result_or_return!(grids.t.decode());
+
+    if u_values.len() != v_values.len() || u_values.len() != t_values.len() {
+        return_error!(format!("Grids for level {} do not match", level));
+    }
+
+    let level_directory = directory.to_string() + "/L" + level.to_string().as_str();
+    result_or_return_why!(fs::create_dir_all(&level_directory), "Could not create level directory");
+
+    let mut written = result_or_return!(write_tiles(&level_directory, level, TileKind::Wind, source, u, &[&u_values, &v_values, &t_values], manifest));
+
+    if let Some(gh) = grids.gh {
+        let gh_values = result_or_return!(decode_matching(gh, u_values.len(), level));
+
+        written += result_or_return!(write_tiles(&level_directory, level, TileKind::Height, source, u, &[&gh_values], manifest));
+    }
+
+    // upper levels have no vertical velocity, and sometimes no humidity, in which case they are 0
+    if grids.q.is_some() || grids.w.is_some() {
+        let q_values = match grids.q {
+            Some(q) => result_or_return!(decode_matching(q, u_values.len(), level)),
+            None => vec![0.0; u_values.len()]
+        };
+
+        let w_values = match grids.w {
+            Some(w) => result_or_return!(decode_matching(w, u_values.len(), level)),
+            None => vec![0.0; u_values.len()]
+        };
+
+        written += result_or_return!(write_tiles(&level_directory, level, TileKind::Extended, source, u, &[&q_values, &w_values], manifest));
+    }
+
+    Ok(written)
+}
+
+/*
+ * Decodes a field, checking it is on the same grid as the rest of the level
+ */
+fn decode_matching(field: &GribField, length: usize, level: i32) -> Result<Vec<f32>, String> {
+    let values = result_or_return!(field.decode());
+
+    if values.len() != length {
+        return_error!(format!("Grid of {} for level {} does not match", field.short_name().unwrap_or("field"), level));
+    }
+
+    Ok(values)
+}
+
+/*
+ * Groups records by tile, in grid order, and writes each tile to <prefix><lat>_<lon>.gribp with a v2 header
+ * Values must be in the order given by the tile kind
+ * Points where any value is missing are skipped
+ * The number of records in each tile is added to the manifest
+ */
+fn write_tiles(level_directory: &str, level: i32, kind: TileKind, source: &str, grid_field: &GribField, values: &[&Vec<f32>], manifest: &mut DatasetManifest) -> Result<usize, String> {
+    let resolution = some_or_return_why!(grid_field.grid.resolution(), "Tiles need a latitude/longitude grid");
+
+    let header = TileHeader::new(
+        resolution,
+        CELL_SIZE,
+        source.to_string(),
+        kind.variables().iter().map(|variable| variable.to_string()).collect()
+    );
+
+    let mut tiles : BTreeMap<(i32, i32), (Vec<u8>, usize)> = BTreeMap::new();
+
+    for i in 0..values[0].len() {
+        if values.iter().any(|field| field[i].is_nan()) {
+            continue;
+        }
+
+        let (latitude, longitude) = grid_field.grid.coordinates(i);
+
+        let grid_lat = ((latitude / CELL_SIZE).floor() * CELL_SIZE) as i32;
+        let grid_lon = ((longitude / CELL_SIZE).floor() * CELL_SIZE) as i32;
+
+        let &mut (ref mut tile, ref mut records) = tiles.entry((grid_lat, grid_lon)).or_insert_with(|| {
+            let mut tile = vec![];
+            header.write(&mut tile);
+
+            (tile, 0)
+        });
+
+        *records += 1;
+
+        push_f32(tile, latitude);
+        push_f32(tile, longitude);
+
+        for field in values.iter() {
+            push_f32(tile, field[i]);
+        }
+    }
+
+    for (&(grid_lat, grid_lon), &(ref contents, records)) in tiles.iter() {
+        let filename = level_directory.to_string() + "/" + kind.prefix() + grid_lat.to_string().as_str() + "_" + grid_lon.to_string().as_str() + ".gribp";
+
+        let mut file = result_or_return_why!(File::create(&filename), "Could not create tile");
+        result_or_return_why!(file.write_all(contents), "Could not write tile");
+
+        manifest.records.insert(DatasetManifest::tile_path(kind, level, grid_lat, grid_lon), records);
+    }
+
+    Ok(tiles.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use chrono::prelude::*;
+    use predictor::regional::RegionalDataset;
+    use preprocessor::grib2::tests::{section, be32};
+
+    /*
+     * A field at 850 hPa on a 2x2 corner of the HRRR grid, 3 hours into the 06Z run on 2018-02-17
+     * Every point has the same value
+     */
+    fn lambert_message(category: u8, number: u8, value: u8) -> Vec<u8> {
+        let mut body = vec![];
+
+        body.extend(section(1, 21, &[(12, &[0x07, 0xE2, 2, 17, 6, 0, 0])]));
+
+        body.extend(section(3, 81, &[
+            (12, &[0, 30]),
+            (30, &be32(2)),
+            (34, &be32(2)),
+            (38, &be32(21_138_123)),
+            (42, &be32(237_280_472)),
+            (46, &[0x08]),
+            (51, &be32(262_500_000)),
+            (55, &be32(3_000_000)),
+            (59, &be32(3_000_000)),
+            (64, &[0x40]),
+            (65, &be32(38_500_000)),
+            (69, &be32(38_500_000))
+        ]));
+
+        body.extend(section(4, 34, &[
+            (7, &[0, 0]),
+            (9, &[category, number]),
+            (17, &[1]),
+            (18, &be32(3)),
+            (22, &[ISOBARIC_SURFACE, 0]),
+            (24, &be32(85000))
+        ]));
+
+        body.extend(section(5, 21, &[(5, &be32(4)), (19, &[8])]));
+        body.extend(section(6, 6, &[(5, &[255])]));
+        body.extend(section(7, 9, &[(5, &[value; 4])]));
+        body.extend_from_slice(b"7777");
+
+        let mut message = b"GRIB".to_vec();
+        message.extend_from_slice(&[0, 0, 0, 2, 0, 0, 0, 0]);
+        message.extend_from_slice(&be32((16 + body.len()) as u32));
+        message.extend(body);
+
+        message
+    }
+
+    #[test]
+    fn preprocesses_regional_files() {
+        let directory = env::temp_dir().join(format!("preprocess_regional_{}", ::std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        // named as on NOMADS, which is not a name global datasets can have
+        let path = directory.join("hrrr.t06z.wrfprsf03.grib2").to_str().unwrap().to_string();
+
+        let mut bytes = lambert_message(2, 2, 10);
+        bytes.extend(lambert_message(2, 3, 20));
+        bytes.extend(lambert_message(0, 0, 250));
+        File::create(&path).unwrap().write_all(&bytes).unwrap();
+
+        let preprocessed = preprocess(path);
+        let regional_directory = directory.join(REGIONAL_DIRECTORY).join("hrrr_20180217_0600_003").to_str().unwrap().to_string();
+        let dataset = RegionalDataset::open(&regional_directory);
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        let preprocessed = preprocessed.unwrap();
+        assert_eq!(preprocessed.name, "hrrr_20180217_0600_003");
+        assert_eq!(preprocessed.levels, vec![850]);
+
+        let dataset = dataset.unwrap();
+        assert_eq!(dataset.name, "hrrr_20180217_0600_003");
+        assert_eq!(dataset.time, Utc.ymd(2018, 2, 17).and_hms(9, 0, 0));
+    }
+}
@@ -0,0 +1,38 @@
+# names datasets the same way the predictor reads them
+module DatasetNames
+
+  GRIB_EXTENSIONS = %w(.grb2 .grib2 .grb .grib)
+
+  class << self
+
+    # the name a downloaded dataset is saved under in data/
+    # NOMADS files (gfs.t00z.pgrb2.0p50.f003, or gep01.t00z.pgrb2a.0p50.f003 for GEFS) don't include the run date,
+    # so it's taken from their gfs.YYYYMMDD or gefs.YYYYMMDD directory
+    def filename(dataset_url)
+      parts = dataset_url.split('/')
+      filename = parts.last
+
+      if filename =~ /\A(gfs|gec\d{2}|gep\d{2})\.t\d{2}z\./
+        date = parts.reverse.map { |part| part[/\A(?:gfs|gefs)\.(\d{8})\z/, 1] }.compact.first
+        filename = filename.sub('.', ".#{date}.") if date.present?
+      end
+
+      filename += '.grb2' unless GRIB_EXTENSIONS.any? { |extension| filename.end_with? extension }
+
+      filename
+    end
+
+    # the directory a dataset is preprocessed into, which is also the name the predictor reports
+    def folder_name(dataset_url)
+      filename = self.filename(dataset_url)
+
+      GRIB_EXTENSIONS.each do |extension|
+        return filename[0...-extension.length] if filename.end_with? extension
+      end
+
+      filename
+    end
+
+  end
+
+end
@@ -18,7 +18,7 @@ module ProcessedDatasets
       end
 
       blobs.list_blobs('data', prefix: prefix).map do |blob|
-        blob.name.split('.').first
+        File.basename(blob.name, '.zip')
       end
     end
 